@@ -0,0 +1,170 @@
+//! Instructions per second of the Famicom memory, decoded by its `Bus`, against the memory it
+//! replaced, which decoded every access with a match on the address.
+//!
+//! `cargo bench --bench bus`
+
+use std::time::Instant;
+use cpu_6502_rs::cpu::word;
+use cpu_6502_rs::cpu::doubleword;
+use cpu_6502_rs::cpu::BusFault;
+use cpu_6502_rs::cpu::ExecMode;
+use cpu_6502_rs::cpu::InstructionStream;
+use cpu_6502_rs::cpu::MemoryAccessType;
+use cpu_6502_rs::cpu::Registers;
+use cpu_6502_rs::CpuVariant;
+use cpu_6502_rs::FamicomMemory;
+use cpu_6502_rs::StopReason;
+use cpu_6502_rs::System;
+use cpu_6502_rs::IO6502;
+
+const INSTRUCTIONS: u64 = 10_000_000;
+const RUNS: usize = 10;
+
+const RAM_SIZE_BYTES: usize = 0x800;
+
+// The Famicom memory as it was before the bus, kept as the baseline
+
+struct Ram {
+    data: Box<[word; RAM_SIZE_BYTES]>,
+}
+
+impl Ram {
+    fn new() -> Self {
+        Self { data: Box::new([word::zero(); RAM_SIZE_BYTES]) }
+    }
+
+    fn write(&mut self, address: doubleword, data: word) {
+        let address: u16 = address.native_value();
+        debug_assert!(address <= 0x07ff);
+        self.data[address as usize] = data;
+    }
+
+    fn read(&self, address: doubleword) -> word {
+        debug_assert!(address.native_value() <= 0x07ff);
+        self.data[address.as_addr()]
+    }
+}
+
+struct Cartridge {
+    program: Box<[word; 0x8000]>,
+}
+
+impl Cartridge {
+    fn new_zeroed() -> Self {
+        Self { program: Box::new([word::zero(); 0x8000]) }
+    }
+
+    fn push_program(&mut self, program: InstructionStream) {
+        self.program[..program.stream.len()].copy_from_slice(&program.stream);
+    }
+
+    fn read(&self, address: doubleword) -> word {
+        self.program[address.as_addr()]
+    }
+}
+
+struct MatchedMemory {
+    internal_ram: Ram,
+    cart: Cartridge,
+}
+
+impl MatchedMemory {
+    /// Returns the value read for a load, and None for a store
+    fn access(&mut self, address: doubleword, tpe: MemoryAccessType, data: Option<word>) -> Result<Option<word>, BusFault> {
+        let addr = address.native_value();
+        let fault = BusFault { address: addr, access: tpe };
+        let ret = match addr {
+            0x0000..=0x1FFF => {
+                let real_address = address.native_value() % 0x800; // Clamp mirrored RAM addresses to the real ones
+                match tpe {
+                    MemoryAccessType::Load => Some(self.internal_ram.read(doubleword::from(real_address))),
+                    MemoryAccessType::Store => {
+                        self.internal_ram.write(doubleword::from(real_address), data.expect("access function got a store request without a value"));
+                        None
+                    },
+                }
+            }, // RAM (repeated)
+            0x2000..=0x3FFF => None, // PPU (repeated)
+            0x4000..=0x4017 => None, // APU and IO
+            0x4018..=0x401F => None, // test Mode
+            0x8000..=0xFFFF => { // cartridge
+                match tpe {
+                    MemoryAccessType::Load => Some(self.cart.read(doubleword::from(addr - 0x8000u16))),
+                    MemoryAccessType::Store => return Err(fault), // ROM
+                }
+            },
+            _ => return Err(fault),
+        };
+        Ok(ret)
+    }
+}
+
+impl IO6502 for MatchedMemory {
+    fn new_resetted() -> Self {
+        Self { internal_ram: Ram::new(), cart: Cartridge::new_zeroed() }
+    }
+
+    fn reset(&mut self) {
+        self.internal_ram.data.iter_mut().for_each(|w| *w = word::zero());
+    }
+
+    fn push_program(&mut self, program: InstructionStream) {
+        self.cart.push_program(program);
+    }
+
+    fn store(&mut self, address: doubleword, data: word) -> Result<(), BusFault> {
+        let ret = self.access(address, MemoryAccessType::Store, Some(data))?;
+        debug_assert!(ret.is_none());
+        Ok(())
+    }
+
+    fn load(&mut self, address: doubleword) -> Result<word, BusFault> {
+        match self.access(address, MemoryAccessType::Load, None)? {
+            Some(value) => Ok(value),
+            None => Err(BusFault { address: address.native_value(), access: MemoryAccessType::Load }),
+        }
+    }
+
+    fn peek(&self, _address: doubleword) -> Option<word> {
+        None
+    }
+}
+
+/// Runs a RAM and ROM bound loop in both execution modes, returning the best number of
+/// millions of instructions per second of each out of a few runs
+fn measure<T: IO6502>(mem: T) -> [f64; 2] {
+    // loop: LDX #$00; inner: LDA $0200,X; ADC #$01; STA $0200,X; INX; BNE inner; JMP loop
+    let program: &[u8] = &[0xA2, 0x00, 0xBD, 0x00, 0x02, 0x69, 0x01, 0x9D, 0x00, 0x02, 0xE8, 0xD0, 0xF5, 0x4C, 0x00, 0x80];
+    let mut sys = System::with_memory(CpuVariant::Ricoh2A03, mem);
+    sys.memory_mut().push_program(InstructionStream::from(program.iter().map(|x| word::from(*x)).collect::<Vec<word>>()));
+    sys.set_registers(Registers { pc: 0x8000, ..sys.registers() });
+
+    let mut best = [0.0; 2];
+    for (mode, best) in [ExecMode::Instruction, ExecMode::Cycle].iter().zip(best.iter_mut()) {
+        sys.set_exec_mode(*mode).unwrap();
+        let elapsed = (0..RUNS).map(|_| {
+            let start = Instant::now();
+            assert_eq!(sys.run_for_instructions(INSTRUCTIONS), Ok(StopReason::InstructionBudget));
+            start.elapsed().as_secs_f64()
+        }).fold(f64::INFINITY, f64::min);
+        *best = INSTRUCTIONS as f64 / elapsed / 1e6;
+    }
+    best
+}
+
+fn main() {
+    // Alternated, so that both see the same machine load
+    let mut matched = [0.0f64; 2];
+    let mut bus = [0.0f64; 2];
+    for _ in 0..3 {
+        for (best, now) in matched.iter_mut().zip(measure(MatchedMemory::new_resetted()).iter()) {
+            *best = best.max(*now);
+        }
+        for (best, now) in bus.iter_mut().zip(measure(FamicomMemory::new_resetted()).iter()) {
+            *best = best.max(*now);
+        }
+    }
+    println!("{:<10} {:>12} {:>12}", "M instr/s", "instruction", "cycle");
+    println!("{:<10} {:>12.1} {:>12.1}", "match", matched[0], matched[1]);
+    println!("{:<10} {:>12.1} {:>12.1}", "bus", bus[0], bus[1]);
+}
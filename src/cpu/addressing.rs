@@ -0,0 +1,243 @@
+//! Effective address computation of every addressing mode, in instruction-stepped mode.
+//! Memory is only seen through a read function, so that the wrapping rules of the 6502
+//! (zero page indexing, zero page pointers, JMP ($xxFF)) can be checked on their own.
+
+use super::datastructures::word;
+use super::datastructures::doubleword;
+use super::datastructures::ClAdd;
+use super::opcodes::AddressingMode;
+
+/// Where an instruction finds its operand
+#[derive(Clone, Copy, Debug)]
+pub struct EffectiveAddress {
+    pub address: doubleword,
+    /// Whether indexing carried into the high byte, i.e. the base address and the effective
+    /// address are on different pages. Only the indexed modes can cross a page
+    pub page_crossed: bool,
+}
+
+impl EffectiveAddress {
+    fn new(address: doubleword) -> Self {
+        Self { address, page_crossed: false }
+    }
+
+    fn indexed(base: doubleword, index: word) -> Self {
+        let address = base.cl_add(index);
+        Self { address, page_crossed: base.to_words()[1].native_value() != address.to_words()[1].native_value() }
+    }
+}
+
+/// CPU state the computation depends on
+#[derive(Clone, Copy, Debug)]
+pub struct AddressingContext {
+    /// Address of the opcode
+    pub pc: doubleword,
+    pub x: word,
+    pub y: word,
+    /// Set for the 65C02, whose JMP (ind) fetches its pointer across pages
+    pub fixed_indirect_jmp: bool,
+}
+
+#[inline]
+/// Reads a pointer whose high byte is fetched without carrying into the high byte of the
+/// address, i.e. from the same page. This is how the 6502 reads zero page pointers and
+/// the JMP ($xxFF) target
+fn read_pointer_same_page<F: FnMut(doubleword) -> word>(read: &mut F, address: doubleword) -> doubleword {
+    let [lo_addr, page] = address.to_words();
+    let lo = read(address);
+    let hi = read(doubleword::from_words(page, lo_addr.cl_add(word::from(1u8))));
+    doubleword::from_words(hi, lo)
+}
+
+#[inline]
+fn read_pointer<F: FnMut(doubleword) -> word>(read: &mut F, address: doubleword) -> doubleword {
+    let lo = read(address);
+    let hi = read(address.cl_add(word::from(1u8)));
+    doubleword::from_words(hi, lo)
+}
+
+/// Computes the effective address of the instruction at `ctx.pc`, reading its operand bytes and
+/// any pointer through `read`. Immediate and relative operands are the byte following the opcode,
+/// and the zero page relative mode gives the zero page byte being tested.
+/// Returns None for the implied and accumulator modes, which do not address memory
+pub fn effective_address<F>(mode: AddressingMode, ctx: AddressingContext, mut read: F) -> Option<EffectiveAddress>
+    where F: FnMut(doubleword) -> word {
+    let operand_address = ctx.pc.cl_add(word::from(1u8));
+    let zeropage = |read: &mut F| read(operand_address).as_doubleword();
+    let absolute = |read: &mut F| read_pointer(read, operand_address);
+
+    let ret = match mode {
+        AddressingMode::Implied | AddressingMode::Accumulator => return None,
+        AddressingMode::Immediate | AddressingMode::Relative => EffectiveAddress::new(operand_address),
+        AddressingMode::Zeropage | AddressingMode::ZeropageRelative => EffectiveAddress::new(zeropage(&mut read)),
+        // Indexing wraps within the zero page
+        AddressingMode::ZeropageX => EffectiveAddress::new(read(operand_address).cl_add(ctx.x).as_doubleword()),
+        AddressingMode::ZeropageY => EffectiveAddress::new(read(operand_address).cl_add(ctx.y).as_doubleword()),
+        AddressingMode::Absolute => EffectiveAddress::new(absolute(&mut read)),
+        AddressingMode::AbsoluteX => EffectiveAddress::indexed(absolute(&mut read), ctx.x),
+        AddressingMode::AbsoluteY => EffectiveAddress::indexed(absolute(&mut read), ctx.y),
+        AddressingMode::Indirect => {
+            let pointer = absolute(&mut read);
+            EffectiveAddress::new(match ctx.fixed_indirect_jmp {
+                true => read_pointer(&mut read, pointer),
+                false => read_pointer_same_page(&mut read, pointer),
+            })
+        },
+        AddressingMode::IndirectX => {
+            let pointer = read(operand_address).cl_add(ctx.x).as_doubleword();
+            EffectiveAddress::new(read_pointer_same_page(&mut read, pointer))
+        },
+        AddressingMode::IndirectY => {
+            let pointer = zeropage(&mut read);
+            EffectiveAddress::indexed(read_pointer_same_page(&mut read, pointer), ctx.y)
+        },
+        AddressingMode::ZeropageIndirect => {
+            let pointer = zeropage(&mut read);
+            EffectiveAddress::new(read_pointer_same_page(&mut read, pointer))
+        },
+        AddressingMode::AbsoluteIndexedIndirect => {
+            let pointer = absolute(&mut read).cl_add(ctx.x);
+            EffectiveAddress::new(read_pointer(&mut read, pointer))
+        },
+    };
+    Some(ret)
+}
+
+#[cfg(test)]
+mod addressing_tests {
+
+    use super::*;
+
+    /// 64K of memory, with the instruction at $0300
+    struct Memory {
+        data: Vec<u8>,
+    }
+
+    impl Memory {
+        fn new(instruction: &[u8]) -> Self {
+            let mut data = vec![0u8; 0x10000];
+            data[0x0300..0x0300 + instruction.len()].copy_from_slice(instruction);
+            Self { data }
+        }
+
+        fn with(mut self, address: u16, bytes: &[u8]) -> Self {
+            for (idx, byte) in bytes.iter().enumerate() {
+                self.data[address as usize + idx] = *byte;
+            }
+            self
+        }
+
+        fn resolve(&self, mode: AddressingMode, x: u8, y: u8, fixed_indirect_jmp: bool) -> Option<(u16, bool)> {
+            let ctx = AddressingContext {
+                pc: doubleword::from(0x0300u16),
+                x: word::from(x),
+                y: word::from(y),
+                fixed_indirect_jmp,
+            };
+            effective_address(mode, ctx, |addr| word::from(self.data[addr.as_addr()]))
+                .map(|ea| (ea.address.native_value(), ea.page_crossed))
+        }
+    }
+
+    #[test]
+    fn non_memory_modes() {
+        let mem = Memory::new(&[0xEA]);
+        assert_eq!(mem.resolve(AddressingMode::Implied, 0, 0, false), None);
+        assert_eq!(mem.resolve(AddressingMode::Accumulator, 0, 0, false), None);
+        assert_eq!(mem.resolve(AddressingMode::Immediate, 0, 0, false), Some((0x0301, false)));
+        assert_eq!(mem.resolve(AddressingMode::Relative, 0, 0, false), Some((0x0301, false)));
+    }
+
+    #[test]
+    fn zeropage_modes() {
+        let mem = Memory::new(&[0xB5, 0x80]);
+        assert_eq!(mem.resolve(AddressingMode::Zeropage, 0x10, 0x20, false), Some((0x0080, false)));
+        assert_eq!(mem.resolve(AddressingMode::ZeropageRelative, 0x10, 0x20, false), Some((0x0080, false)));
+        assert_eq!(mem.resolve(AddressingMode::ZeropageX, 0x10, 0x20, false), Some((0x0090, false)));
+        assert_eq!(mem.resolve(AddressingMode::ZeropageY, 0x10, 0x20, false), Some((0x00A0, false)));
+        // Indexing wraps within the zero page, without a page cross
+        assert_eq!(mem.resolve(AddressingMode::ZeropageX, 0x90, 0x20, false), Some((0x0010, false)));
+        assert_eq!(mem.resolve(AddressingMode::ZeropageY, 0x10, 0xFF, false), Some((0x007F, false)));
+    }
+
+    #[test]
+    fn absolute_modes() {
+        let mem = Memory::new(&[0xBD, 0xF0, 0x12]);
+        assert_eq!(mem.resolve(AddressingMode::Absolute, 0x20, 0x05, false), Some((0x12F0, false)));
+        assert_eq!(mem.resolve(AddressingMode::AbsoluteX, 0x05, 0x20, false), Some((0x12F5, false)));
+        assert_eq!(mem.resolve(AddressingMode::AbsoluteX, 0x20, 0x05, false), Some((0x1310, true)));
+        assert_eq!(mem.resolve(AddressingMode::AbsoluteY, 0x20, 0x05, false), Some((0x12F5, false)));
+        assert_eq!(mem.resolve(AddressingMode::AbsoluteY, 0x05, 0x20, false), Some((0x1310, true)));
+
+        // Indexing wraps around the address space
+        let mem = Memory::new(&[0xBD, 0xFF, 0xFF]);
+        assert_eq!(mem.resolve(AddressingMode::AbsoluteX, 0x02, 0x00, false), Some((0x0001, true)));
+    }
+
+    #[test]
+    fn indexed_indirect() {
+        // ($40,X): the pointer is read from the zero page at $40 + X
+        let mem = Memory::new(&[0xA1, 0x40]).with(0x0045, &[0x34, 0x12]).with(0x00FF, &[0x78]).with(0x0000, &[0x56]);
+        assert_eq!(mem.resolve(AddressingMode::IndirectX, 0x05, 0x00, false), Some((0x1234, false)));
+        // $40 + $C0 wraps to $00, and a pointer at $FF has its high byte at $00
+        assert_eq!(mem.resolve(AddressingMode::IndirectX, 0xBF, 0x00, false), Some((0x5678, false)));
+        assert_eq!(mem.resolve(AddressingMode::IndirectX, 0xC0, 0x00, false), Some((0x0056, false)));
+    }
+
+    #[test]
+    fn indirect_indexed() {
+        // ($40),Y: Y is added to the pointer read from $40
+        let mem = Memory::new(&[0xB1, 0x40]).with(0x0040, &[0xF0, 0x12]);
+        assert_eq!(mem.resolve(AddressingMode::IndirectY, 0x00, 0x05, false), Some((0x12F5, false)));
+        assert_eq!(mem.resolve(AddressingMode::IndirectY, 0x00, 0x20, false), Some((0x1310, true)));
+
+        // A pointer at $FF has its high byte at $00
+        let mem = Memory::new(&[0xB1, 0xFF]).with(0x00FF, &[0x00, 0x99]).with(0x0000, &[0x20]);
+        assert_eq!(mem.resolve(AddressingMode::IndirectY, 0x00, 0x03, false), Some((0x2003, false)));
+
+        // The sum wraps around the address space
+        let mem = Memory::new(&[0xB1, 0x40]).with(0x0040, &[0xFF, 0xFF]);
+        assert_eq!(mem.resolve(AddressingMode::IndirectY, 0x00, 0x01, false), Some((0x0000, true)));
+    }
+
+    #[test]
+    fn zeropage_indirect() {
+        let mem = Memory::new(&[0xB2, 0xFF]).with(0x00FF, &[0x34]).with(0x0000, &[0x12]);
+        assert_eq!(mem.resolve(AddressingMode::ZeropageIndirect, 0x10, 0x10, true), Some((0x1234, false)));
+    }
+
+    #[test]
+    fn jmp_indirect() {
+        let mem = Memory::new(&[0x6C, 0xFF, 0x10]).with(0x10FF, &[0x34, 0x56]).with(0x1000, &[0x12]);
+        // The NMOS 6502 fetches the high byte from the start of the pointer's page
+        assert_eq!(mem.resolve(AddressingMode::Indirect, 0x00, 0x00, false), Some((0x1234, false)));
+        assert_eq!(mem.resolve(AddressingMode::Indirect, 0x00, 0x00, true), Some((0x5634, false)));
+
+        let mem = Memory::new(&[0x6C, 0x80, 0x10]).with(0x1080, &[0x34, 0x12]);
+        assert_eq!(mem.resolve(AddressingMode::Indirect, 0x00, 0x00, false), Some((0x1234, false)));
+        assert_eq!(mem.resolve(AddressingMode::Indirect, 0x00, 0x00, true), Some((0x1234, false)));
+    }
+
+    #[test]
+    fn absolute_indexed_indirect() {
+        // ($10FE,X): the pointer crosses pages freely, without a page cross penalty
+        let mem = Memory::new(&[0x7C, 0xFE, 0x10]).with(0x10FF, &[0x34, 0x12, 0x56]);
+        assert_eq!(mem.resolve(AddressingMode::AbsoluteIndexedIndirect, 0x01, 0x00, true), Some((0x1234, false)));
+        assert_eq!(mem.resolve(AddressingMode::AbsoluteIndexedIndirect, 0x02, 0x00, true), Some((0x5612, false)));
+    }
+
+    #[test]
+    fn only_indexed_modes_cross_pages() {
+        // Pointers and operands all over page boundaries, with large indexes
+        let mem = Memory::new(&[0x00, 0xFF, 0xFF]).with(0x00FF, &[0xFF]).with(0xFFFF, &[0xFF]);
+        for &mode in &[AddressingMode::Immediate, AddressingMode::Relative, AddressingMode::Zeropage,
+                       AddressingMode::ZeropageX, AddressingMode::ZeropageY, AddressingMode::Absolute,
+                       AddressingMode::Indirect, AddressingMode::IndirectX, AddressingMode::ZeropageIndirect,
+                       AddressingMode::AbsoluteIndexedIndirect, AddressingMode::ZeropageRelative] {
+            for &fixed in &[false, true] {
+                let (_, crossed) = mem.resolve(mode, 0xFF, 0xFF, fixed).unwrap();
+                assert!(!crossed, "{:?}", mode);
+            }
+        }
+    }
+}
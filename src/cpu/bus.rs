@@ -0,0 +1,679 @@
+//! Address decoding for systems assembled from devices. RAM, ROM, chip registers, mappers and
+//! custom peripherals are mapped on address ranges of a `Bus`, which dispatches the CPU
+//! accesses to them and serves as its `IO6502`.
+//!
+//! A mapping has a mirroring mask applied to the offset into its range, so a 2K RAM mapped on
+//! $0000-$1FFF with mask $07FF shows up 4 times, and 8 registers mapped on $2000-$3FFF with
+//! mask $0007 repeat every 8 bytes.
+//!
+//! Plain RAM and ROM are held by the bus itself, one after the other in a single buffer: a table
+//! of the 256 pages of the address space sends accesses to pages they fill straight to the
+//! buffer, and only the other pages are decoded through the mappings.
+//!
+//! The bus remembers the last value it carried. Bits a device leaves floating read as that
+//! value, and so do whole reads nothing answers when open bus is enabled, as on the NES.
+
+use std::any::Any;
+use std::ops::RangeInclusive;
+use super::datastructures::word;
+use super::datastructures::doubleword;
+use super::datastructures::InstructionStream;
+use super::savestate::SaveState;
+use super::savestate::SaveStateError;
+use super::savestate::StateReader;
+use super::savestate::StateWriter;
+use super::BusFault;
+use super::MemoryAccessType;
+use super::IO6502;
+
+/// Something answering bus accesses, like chip registers. Offsets are relative to the start of
+/// the mapped range, mirroring mask applied
+pub trait Device: Any + SaveState {
+    /// None when the device does not drive the data bus for this offset, e.g. a write-only register
+    fn load(&mut self, offset: doubleword) -> Option<word>;
+
+    /// Returns false when the device rejects the write
+    fn store(&mut self, offset: doubleword, data: word) -> bool;
+
+    /// Reads without any side effect, see `IO6502::peek`
+    fn peek(&self, offset: doubleword) -> Option<word>;
+
+    /// Bits of `load` and `peek` the device leaves floating, which read as the last value on the bus
+    fn undriven_bits(&self, _offset: doubleword) -> u8 {
+        0
+    }
+
+    /// Stores part of a program image, e.g. into the ROM of a mapper. Used by `IO6502::push_program`
+    fn write_image(&mut self, offset: doubleword, data: word) {
+        self.store(offset, data);
+    }
+
+    fn reset(&mut self) {}
+
+    /// See `IO6502::bank`
+    fn bank(&self, _offset: doubleword) -> Option<u8> {
+        None
+    }
+
+    /// Whether the device is wired to the NMI or IRQ line. The lines are polled at every
+    /// instruction, only on the devices returning true when they are added
+    fn drives_interrupts(&self) -> bool {
+        false
+    }
+
+    /// Whether the device pulls the NMI line
+    fn nmi_asserted(&self) -> bool {
+        false
+    }
+
+    /// Whether the device pulls the IRQ line
+    fn irq_asserted(&self) -> bool {
+        false
+    }
+}
+
+/// Identifies memory or a device added to a `Bus`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct DeviceId(usize);
+
+/// What a `DeviceId` stands for
+enum Slot {
+    /// Contents at `start..start + len` of `Bus::storage`. RAM is writable and cleared on reset,
+    /// ROM only gets program images
+    Memory { start: usize, len: usize, writable: bool },
+    Device(Box<dyn Device>),
+}
+
+/// Address range served by memory or a device
+struct Mapping {
+    start: u16,
+    end: u16,
+    mask: u16,
+    slot: usize,
+}
+
+/// How accesses to a page are served
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Page {
+    /// The whole page is in memory, starting at `base` in `Bus::storage`
+    Direct { base: u32, writable: bool },
+    /// Devices, unmapped space, or a page that is not all in the same memory: the mappings are searched
+    Decoded,
+}
+
+const PAGES: usize = 0x100;
+
+/// Memory, devices and the address ranges they are mapped on. Accesses to unmapped addresses fault
+pub struct Bus {
+    slots: Vec<Slot>,
+    /// Contents of all RAM and ROM
+    storage: Vec<word>,
+    mappings: Vec<Mapping>,
+    pages: [Page; PAGES],
+    /// Slots of the devices driving the interrupt lines
+    interrupt_sources: Vec<usize>,
+    /// Where `push_program` puts programs
+    program_origin: u16,
+    /// Last value read or written
+    latch: word,
+    /// Whether reads nothing answers give `latch` instead of faulting
+    open_bus: bool,
+    /// Whether stores nothing takes are dropped instead of faulting
+    ignore_stray_stores: bool,
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus {
+    /// A bus with nothing on it, loading programs at $0000
+    pub fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            storage: Vec::new(),
+            mappings: Vec::new(),
+            pages: [Page::Decoded; PAGES],
+            interrupt_sources: Vec::new(),
+            program_origin: 0,
+            latch: word::zero(),
+            open_bus: false,
+            ignore_stray_stores: false,
+        }
+    }
+
+    fn add_memory(&mut self, size: usize, writable: bool) -> DeviceId {
+        let start = self.storage.len();
+        self.storage.resize(start + size, word::zero());
+        self.slots.push(Slot::Memory { start, len: size, writable });
+        DeviceId(self.slots.len() - 1)
+    }
+
+    /// Adds `size` bytes of RAM, all zeroes, not mapped anywhere yet. Memory and devices are
+    /// saved, restored and reset in the order they are added
+    pub fn add_ram(&mut self, size: usize) -> DeviceId {
+        self.add_memory(size, true)
+    }
+
+    /// Adds `size` bytes of ROM, all zeroes until a program is pushed
+    pub fn add_rom(&mut self, size: usize) -> DeviceId {
+        self.add_memory(size, false)
+    }
+
+    pub fn add_device<D: Device>(&mut self, device: D) -> DeviceId {
+        if device.drives_interrupts() {
+            self.interrupt_sources.push(self.slots.len());
+        }
+        self.slots.push(Slot::Device(Box::new(device)));
+        DeviceId(self.slots.len() - 1)
+    }
+
+    /// Maps memory or a device on `range`, accesses reaching it at `(address - range.start()) & mask`.
+    /// Where ranges overlap, the last one mapped wins
+    pub fn map(&mut self, id: DeviceId, range: RangeInclusive<u16>, mask: u16) {
+        assert!(id.0 < self.slots.len(), "nothing with {:?} on this bus", id);
+        let (start, end) = (*range.start(), *range.end());
+        self.mappings.push(Mapping { start, end, mask, slot: id.0 });
+        for page in (start >> 8) as usize..=(end >> 8) as usize {
+            self.pages[page] = self.page_entry(page);
+        }
+    }
+
+    /// Direct when the last mapping touching the page puts all of it in memory, in order
+    fn page_entry(&self, page: usize) -> Page {
+        let first = (page << 8) as u16;
+        let last = first | 0xFF;
+        let mapping = match self.mappings.iter().rev().find(|mapping| mapping.start <= last && mapping.end >= first) {
+            Some(mapping) => mapping,
+            None => return Page::Decoded,
+        };
+        let in_order = mapping.start <= first && mapping.end >= last && mapping.start & 0xFF == 0 && mapping.mask & 0xFF == 0xFF;
+        match self.slots[mapping.slot] {
+            Slot::Memory { start, len, writable } if in_order => {
+                let offset = ((first - mapping.start) & mapping.mask) as usize;
+                match offset + 0x100 <= len {
+                    true => Page::Direct { base: (start + offset) as u32, writable },
+                    false => Page::Decoded,
+                }
+            },
+            _ => Page::Decoded,
+        }
+    }
+
+    /// Where `push_program` loads programs
+    pub fn set_program_origin(&mut self, origin: u16) {
+        self.program_origin = origin;
+    }
+
+    /// With open bus, reads nothing answers return the last value on the bus instead of faulting
+    pub fn set_open_bus(&mut self, enabled: bool) {
+        self.open_bus = enabled;
+    }
+
+    /// When enabled, stores to ROM, unmapped addresses or devices refusing them are dropped
+    /// instead of faulting, as on boards ignoring writes nothing decodes
+    pub fn set_ignore_stray_stores(&mut self, enabled: bool) {
+        self.ignore_stray_stores = enabled;
+    }
+
+    /// Contents of RAM or ROM, None for devices
+    pub fn memory(&self, id: DeviceId) -> Option<&[word]> {
+        match self.slots.get(id.0)? {
+            Slot::Memory { start, len, .. } => Some(&self.storage[*start..*start + *len]),
+            Slot::Device(_) => None,
+        }
+    }
+
+    /// Contents of RAM or ROM, None for devices
+    pub fn memory_mut(&mut self, id: DeviceId) -> Option<&mut [word]> {
+        match self.slots.get(id.0)? {
+            Slot::Memory { start, len, .. } => Some(&mut self.storage[*start..*start + *len]),
+            Slot::Device(_) => None,
+        }
+    }
+
+    /// The device, None if it is not a `D`
+    pub fn device<D: Device>(&self, id: DeviceId) -> Option<&D> {
+        match self.slots.get(id.0)? {
+            Slot::Device(device) => {
+                let device: &dyn Any = device.as_ref();
+                device.downcast_ref()
+            },
+            Slot::Memory { .. } => None,
+        }
+    }
+
+    /// The device, None if it is not a `D`
+    pub fn device_mut<D: Device>(&mut self, id: DeviceId) -> Option<&mut D> {
+        match self.slots.get_mut(id.0)? {
+            Slot::Device(device) => {
+                let device: &mut dyn Any = device.as_mut();
+                device.downcast_mut()
+            },
+            Slot::Memory { .. } => None,
+        }
+    }
+
+    fn interrupt_sources(&self) -> impl Iterator<Item = &dyn Device> + '_ {
+        self.interrupt_sources.iter().filter_map(move |&slot| match &self.slots[slot] {
+            Slot::Device(device) => Some(device.as_ref()),
+            Slot::Memory { .. } => None,
+        })
+    }
+
+    /// Slot mapped at `address` and the offset it sees
+    fn decode(&self, address: u16) -> Option<(usize, u16)> {
+        self.mappings.iter().rev()
+            .find(|mapping| (mapping.start..=mapping.end).contains(&address))
+            .map(|mapping| (mapping.slot, (address - mapping.start) & mapping.mask))
+    }
+
+    /// Kept out of line, so that the page table lookup is all that `load` inlines
+    #[inline(never)]
+    fn decoded_load(&mut self, address: u16) -> Option<word> {
+        let (slot, offset) = self.decode(address)?;
+        match &mut self.slots[slot] {
+            Slot::Memory { start, len, .. } => Self::cell(*start, *len, offset).map(|idx| self.storage[idx]),
+            Slot::Device(device) => {
+                let offset = doubleword::from(offset);
+                let undriven = device.undriven_bits(offset);
+                device.load(offset).map(|value| floating(value, undriven, self.latch))
+            },
+        }
+    }
+
+    /// Index in `storage` of `offset` into memory at `start..start + len`
+    #[inline]
+    fn cell(start: usize, len: usize, offset: u16) -> Option<usize> {
+        match (offset as usize) < len {
+            true => Some(start + offset as usize),
+            false => None,
+        }
+    }
+
+    /// False when nothing takes the write. Kept out of line like `decoded_load`
+    #[inline(never)]
+    fn decoded_store(&mut self, address: u16, data: word) -> bool {
+        let (slot, offset) = match self.decode(address) {
+            Some(decoded) => decoded,
+            None => return false,
+        };
+        match &mut self.slots[slot] {
+            Slot::Memory { start, len, writable: true } => match Self::cell(*start, *len, offset) {
+                Some(idx) => {
+                    self.storage[idx] = data;
+                    true
+                },
+                None => false,
+            },
+            Slot::Memory { writable: false, .. } => false,
+            Slot::Device(device) => device.store(doubleword::from(offset), data),
+        }
+    }
+}
+
+impl IO6502 for Bus {
+    fn new_resetted() -> Self {
+        Self::new()
+    }
+
+    fn reset(&mut self) {
+        for slot in self.slots.iter_mut() {
+            match slot {
+                Slot::Memory { start, len, writable: true } => self.storage[*start..*start + *len].iter_mut().for_each(|w| *w = word::zero()),
+                Slot::Memory { writable: false, .. } => (),
+                Slot::Device(device) => device.reset(),
+            }
+        }
+    }
+
+    /// Bytes falling on unmapped addresses are dropped
+    fn push_program(&mut self, program: InstructionStream) {
+        for (idx, byte) in program.stream.into_iter().enumerate() {
+            let address = self.program_origin.wrapping_add(idx as u16);
+            if let Some((slot, offset)) = self.decode(address) {
+                match &mut self.slots[slot] {
+                    Slot::Memory { start, len, .. } => {
+                        if let Some(idx) = Self::cell(*start, *len, offset) {
+                            self.storage[idx] = byte;
+                        }
+                    },
+                    Slot::Device(device) => device.write_image(doubleword::from(offset), byte),
+                }
+            }
+        }
+    }
+
+    #[inline]
+    fn store(&mut self, address: doubleword, data: word) -> Result<(), BusFault> {
+        let addr = address.native_value();
+        self.latch = data;
+        let done = match self.pages[(addr >> 8) as usize] {
+            Page::Direct { base, writable: true } => match self.storage.get_mut(base as usize + (addr & 0xFF) as usize) {
+                Some(cell) => {
+                    *cell = data;
+                    true
+                },
+                None => false,
+            },
+            Page::Direct { writable: false, .. } => false,
+            Page::Decoded => self.decoded_store(addr, data),
+        };
+        match done {
+            true => Ok(()),
+            false if self.ignore_stray_stores => Ok(()),
+            false => Err(BusFault { address: addr, access: MemoryAccessType::Store }),
+        }
+    }
+
+    #[inline]
+    fn load(&mut self, address: doubleword) -> Result<word, BusFault> {
+        let addr = address.native_value();
+        // Direct pages are always within `storage`, the miss branch of `get` is never taken but
+        // costs less than the panic path of indexing
+        let ret = match self.pages[(addr >> 8) as usize] {
+            Page::Direct { base, .. } => self.storage.get(base as usize + (addr & 0xFF) as usize).copied(),
+            Page::Decoded => self.decoded_load(addr),
+        };
+        let value = match ret {
+            Some(value) => value,
+            None if self.open_bus => self.latch,
+            None => return Err(BusFault { address: addr, access: MemoryAccessType::Load }),
+        };
+        self.latch = value;
+        Ok(value)
+    }
+
+    fn peek(&self, address: doubleword) -> Option<word> {
+        let (slot, offset) = self.decode(address.native_value())?;
+        match &self.slots[slot] {
+            Slot::Memory { start, len, .. } => Self::cell(*start, *len, offset).map(|idx| self.storage[idx]),
+            Slot::Device(device) => {
+                let offset = doubleword::from(offset);
+                device.peek(offset).map(|value| floating(value, device.undriven_bits(offset), self.latch))
+            },
+        }
+    }
+
+    fn bank(&self, address: doubleword) -> Option<u8> {
+        let (slot, offset) = self.decode(address.native_value())?;
+        match &self.slots[slot] {
+            Slot::Memory { .. } => None,
+            Slot::Device(device) => device.bank(doubleword::from(offset)),
+        }
+    }
+
+    #[inline]
+    fn nmi_asserted(&self) -> bool {
+        !self.interrupt_sources.is_empty() && self.interrupt_sources().any(|device| device.nmi_asserted())
+    }
+
+    #[inline]
+    fn irq_asserted(&self) -> bool {
+        !self.interrupt_sources.is_empty() && self.interrupt_sources().any(|device| device.irq_asserted())
+    }
+}
+
+// ROM is kept too, images are not always loaded from files the user still has
+impl SaveState for Bus {
+    fn save(&self, out: &mut StateWriter) {
+        for slot in self.slots.iter() {
+            match slot {
+                Slot::Memory { start, len, .. } => out.write_words(&self.storage[*start..*start + *len]),
+                Slot::Device(device) => device.save(out),
+            }
+        }
+        out.write_word(self.latch);
+    }
+
+    fn restore(&mut self, input: &mut StateReader) -> Result<(), SaveStateError> {
+        for slot in self.slots.iter_mut() {
+            match slot {
+                Slot::Memory { start, len, .. } => input.read_words(&mut self.storage[*start..*start + *len])?,
+                Slot::Device(device) => device.restore(input)?,
+            }
+        }
+        self.latch = input.read_word()?;
+        Ok(())
+    }
+}
+
+/// `value` with its `undriven` bits taken from `latch`
+#[inline]
+fn floating(value: word, undriven: u8, latch: word) -> word {
+    word::from((value.native_value() & !undriven) | (latch.native_value() & undriven))
+}
+
+/// Registers of a chip that is not emulated, like the PPU: stores are ignored and loads are
+/// not answered
+pub struct Unemulated;
+
+impl Device for Unemulated {
+    fn load(&mut self, _offset: doubleword) -> Option<word> {
+        None
+    }
+
+    fn store(&mut self, _offset: doubleword, _data: word) -> bool {
+        true
+    }
+
+    fn peek(&self, _offset: doubleword) -> Option<word> {
+        None
+    }
+}
+
+impl SaveState for Unemulated {
+    fn save(&self, _out: &mut StateWriter) {}
+
+    fn restore(&mut self, _input: &mut StateReader) -> Result<(), SaveStateError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod bus_tests {
+
+    use super::*;
+
+    /// Timer raising IRQ when its counter, written at offset 0, is ticked down to 0 by reads of offset 1
+    struct Timer {
+        counter: u8,
+    }
+
+    impl Device for Timer {
+        fn load(&mut self, offset: doubleword) -> Option<word> {
+            match offset.native_value() {
+                1 => {
+                    self.counter = self.counter.saturating_sub(1);
+                    Some(word::from(self.counter))
+                },
+                _ => None,
+            }
+        }
+
+        fn store(&mut self, offset: doubleword, data: word) -> bool {
+            if offset == 0u16 {
+                self.counter = data.native_value();
+            }
+            true
+        }
+
+        fn peek(&self, offset: doubleword) -> Option<word> {
+            match offset.native_value() {
+                1 => Some(word::from(self.counter)),
+                _ => None,
+            }
+        }
+
+        fn drives_interrupts(&self) -> bool {
+            true
+        }
+
+        fn irq_asserted(&self) -> bool {
+            self.counter == 0
+        }
+    }
+
+    impl SaveState for Timer {
+        fn save(&self, out: &mut StateWriter) {
+            out.write_u8(self.counter);
+        }
+
+        fn restore(&mut self, input: &mut StateReader) -> Result<(), SaveStateError> {
+            self.counter = input.read_u8()?;
+            Ok(())
+        }
+    }
+
+    /// Input port driving only its low bit, high
+    struct Port;
+
+    impl Device for Port {
+        fn load(&mut self, _offset: doubleword) -> Option<word> {
+            self.peek(doubleword::zero())
+        }
+
+        fn store(&mut self, _offset: doubleword, _data: word) -> bool {
+            true
+        }
+
+        fn peek(&self, _offset: doubleword) -> Option<word> {
+            Some(word::from(0x01u8))
+        }
+
+        fn undriven_bits(&self, _offset: doubleword) -> u8 {
+            0xFE
+        }
+    }
+
+    impl SaveState for Port {
+        fn save(&self, _out: &mut StateWriter) {}
+
+        fn restore(&mut self, _input: &mut StateReader) -> Result<(), SaveStateError> {
+            Ok(())
+        }
+    }
+
+    fn load(bus: &mut Bus, address: u16) -> Result<u8, BusFault> {
+        bus.load(doubleword::from(address)).map(|w| w.native_value())
+    }
+
+    fn store(bus: &mut Bus, address: u16, data: u8) -> Result<(), BusFault> {
+        bus.store(doubleword::from(address), word::from(data))
+    }
+
+    #[test]
+    fn mappings_mirror_and_override() {
+        let mut bus = Bus::new();
+        let ram = bus.add_ram(0x100);
+        bus.map(ram, 0x0000..=0x0FFF, 0x00FF);
+        let rom = bus.add_rom(0x10);
+        bus.map(rom, 0x0800..=0x08FF, 0x000F); // Hides part of the RAM mirrors
+
+        store(&mut bus, 0x0012, 0x34).unwrap();
+        assert_eq!(load(&mut bus, 0x0312), Ok(0x34));
+        assert_eq!(load(&mut bus, 0x0812), Ok(0x00));
+        assert_eq!(load(&mut bus, 0x0912), Ok(0x34));
+        assert_eq!(store(&mut bus, 0x0801, 0x56), Err(BusFault { address: 0x0801, access: MemoryAccessType::Store }));
+        assert_eq!(load(&mut bus, 0x1000), Err(BusFault { address: 0x1000, access: MemoryAccessType::Load }));
+        assert!(bus.peek(doubleword::from(0x1000u16)).is_none());
+
+        // Images go through ROM protection, and are mirrored like accesses
+        bus.set_program_origin(0x0810);
+        bus.push_program(InstructionStream::from(vec![word::from(0xEAu8), word::from(0x60u8)]));
+        assert_eq!(load(&mut bus, 0x0800), Ok(0xEA));
+        assert_eq!(load(&mut bus, 0x0821), Ok(0x60));
+
+        bus.reset();
+        assert_eq!(load(&mut bus, 0x0012), Ok(0x00));
+        assert_eq!(load(&mut bus, 0x0800), Ok(0xEA));
+    }
+
+    #[test]
+    fn page_table() {
+        let mut bus = Bus::new();
+        let ram = bus.add_ram(0x800);
+        bus.map(ram, 0x0000..=0x1FFF, 0x07FF);
+        let rom = bus.add_rom(0x4000);
+        bus.map(rom, 0x8000..=0xFFFF, 0x3FFF);
+        assert_eq!(bus.pages[0x09], Page::Direct { base: 0x100, writable: true });
+        assert_eq!(bus.pages[0xC0], Page::Direct { base: 0x800, writable: false });
+        assert_eq!(bus.pages[0x20], Page::Decoded);
+
+        // A device on part of a page, and memory smaller than a page, are decoded
+        let timer = bus.add_device(Timer { counter: 5 });
+        bus.map(timer, 0x0110..=0x0117, 0x0001);
+        let small = bus.add_ram(0x80);
+        bus.map(small, 0x6000..=0x60FF, 0x007F);
+        assert_eq!(bus.pages[0x01], Page::Decoded);
+        assert_eq!(bus.pages[0x60], Page::Decoded);
+
+        store(&mut bus, 0x0901, 0x12).unwrap();
+        assert_eq!(load(&mut bus, 0x0101), Ok(0x12));
+        assert_eq!(load(&mut bus, 0x0111), Ok(4));
+        store(&mut bus, 0x6001, 0x34).unwrap();
+        assert_eq!(load(&mut bus, 0x6081), Ok(0x34));
+        bus.memory_mut(rom).unwrap()[0x0123] = word::from(0x56u8);
+        assert_eq!(load(&mut bus, 0xC123), Ok(0x56));
+        assert_eq!(store(&mut bus, 0xC123, 0), Err(BusFault { address: 0xC123, access: MemoryAccessType::Store }));
+    }
+
+    #[test]
+    fn open_bus() {
+        let mut bus = Bus::new();
+        let ram = bus.add_ram(0x800);
+        bus.map(ram, 0x0000..=0x07FF, 0x07FF);
+        let port = bus.add_device(Port);
+        bus.map(port, 0x4016..=0x4016, 0x0000);
+
+        // Undriven bits float even without open bus
+        store(&mut bus, 0x0000, 0xA4).unwrap();
+        assert_eq!(load(&mut bus, 0x4016), Ok(0xA5));
+        assert_eq!(bus.peek(doubleword::from(0x4016u16)).unwrap(), 0xA5u8);
+        assert_eq!(load(&mut bus, 0x5000), Err(BusFault { address: 0x5000, access: MemoryAccessType::Load }));
+
+        bus.set_open_bus(true);
+        assert_eq!(load(&mut bus, 0x5000), Ok(0xA5));
+        assert_eq!(load(&mut bus, 0x0001), Ok(0x00));
+        assert_eq!(load(&mut bus, 0x5000), Ok(0x00));
+        assert_eq!(load(&mut bus, 0x4016), Ok(0x01));
+        assert!(bus.peek(doubleword::from(0x5000u16)).is_none());
+    }
+
+    #[test]
+    fn stray_stores() {
+        let mut bus = Bus::new();
+        let rom = bus.add_rom(0x100);
+        bus.map(rom, 0xFF00..=0xFFFF, 0x00FF);
+        assert_eq!(store(&mut bus, 0x5000, 1), Err(BusFault { address: 0x5000, access: MemoryAccessType::Store }));
+        assert_eq!(store(&mut bus, 0xFF00, 1), Err(BusFault { address: 0xFF00, access: MemoryAccessType::Store }));
+
+        bus.set_ignore_stray_stores(true);
+        assert_eq!(store(&mut bus, 0x5000, 1), Ok(()));
+        assert_eq!(store(&mut bus, 0xFF00, 1), Ok(()));
+        assert_eq!(load(&mut bus, 0xFF00), Ok(0));
+        assert_eq!(load(&mut bus, 0x5000), Err(BusFault { address: 0x5000, access: MemoryAccessType::Load }));
+    }
+
+    #[test]
+    fn custom_devices() {
+        let mut bus = Bus::new();
+        let timer = bus.add_device(Timer { counter: 0 });
+        bus.map(timer, 0x4000..=0x4007, 0x0001);
+        assert!(bus.irq_asserted());
+
+        store(&mut bus, 0x4002, 2).unwrap();
+        assert!(!bus.irq_asserted());
+        assert_eq!(bus.peek(doubleword::from(0x4003u16)).unwrap(), 2u8);
+        assert_eq!(load(&mut bus, 0x4005), Ok(1));
+        assert_eq!(load(&mut bus, 0x4000), Err(BusFault { address: 0x4000, access: MemoryAccessType::Load }));
+        assert_eq!(load(&mut bus, 0x4001), Ok(0));
+        assert!(bus.irq_asserted());
+
+        bus.device_mut::<Timer>(timer).unwrap().counter = 9;
+        assert_eq!(bus.device::<Timer>(timer).unwrap().counter, 9);
+        assert!(bus.memory(timer).is_none());
+    }
+}
@@ -0,0 +1,189 @@
+//! Control surface used by harnesses and debuggers to drive the CPU: single steps reporting
+//! what was executed, runs bounded by cycles, instructions, breakpoints or a condition, and
+//! access to the registers and memory.
+
+use super::datastructures::word;
+use super::datastructures::doubleword;
+use super::debugger::BreakpointId;
+use super::BusFault;
+use super::CpuVariant;
+use super::System;
+use super::IO6502;
+use super::ExecError;
+use super::MemoryAccessType;
+
+/// What a call to `System::step` executed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StepInfo {
+    /// Address of the instruction
+    pub pc: u16,
+    /// None when no instruction was executed: interrupt sequence, WAI or jammed CPU
+    pub opcode: Option<u8>,
+    /// Cycles taken
+    pub cycles: u8,
+}
+
+/// Programmer-visible registers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Registers {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub s: u8,
+    pub p: u8,
+    pub pc: u16,
+}
+
+/// Why a run returned
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// A breakpoint fired before the instruction at `pc`, which has not been executed
+    Breakpoint { id: BreakpointId, pc: u16 },
+    /// The instruction at `pc` made an access watched by a breakpoint. It has been executed
+    Watchpoint { id: BreakpointId, pc: u16, address: u16, access: MemoryAccessType },
+    /// A JAM opcode stuck the CPU
+    Jammed { pc: u16 },
+    /// An undocumented opcode was met while they are trapped, see `ExecError::IllegalOpcode`
+    IllegalOpcode { pc: u16, opcode: u8 },
+    /// The requested number of cycles has been run, possibly a few more to end the last instruction
+    CycleBudget,
+    /// The requested number of instructions has been run
+    InstructionBudget,
+    /// An instruction jumped or branched to itself, which is how test programs report their
+    /// result. Only detected once enabled with `set_stop_on_trap`
+    Trap { pc: u16 },
+    /// The condition given to `run_until` was met
+    Condition,
+    /// `step_over` or `step_out` is done
+    StepComplete,
+}
+
+impl<T: IO6502> System<T> {
+
+    pub fn registers(&self) -> Registers {
+        Registers {
+            a: self.a.native_value(),
+            x: self.x.native_value(),
+            y: self.y.native_value(),
+            s: self.s.native_value(),
+            p: self.p.native_value(),
+            pc: self.pc.native_value(),
+        }
+    }
+
+    /// Meant to be called between instructions, in cycle-stepped mode the instruction in
+    /// progress would go on with the new values
+    pub fn set_registers(&mut self, registers: Registers) {
+        self.a = word::from(registers.a);
+        self.x = word::from(registers.x);
+        self.y = word::from(registers.y);
+        self.s = word::from(registers.s);
+        self.p = word::from(registers.p);
+        self.pc = doubleword::from(registers.pc);
+        // A new I masks IRQs right away
+        self.delayed_i = None;
+    }
+
+    /// Number of cycles elapsed since the CPU was created
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn variant(&self) -> CpuVariant {
+        self.variant
+    }
+
+    /// Reads memory without any side effect, see `IO6502::peek`
+    pub fn peek(&self, address: u16) -> Option<u8> {
+        self.mem.peek(doubleword::from(address)).map(|val| val.native_value())
+    }
+
+    /// Writes memory through the bus, as the CPU would. Breakpoints are not involved
+    pub fn poke(&mut self, address: u16, data: u8) -> Result<(), BusFault> {
+        self.mem.store(doubleword::from(address), word::from(data))
+    }
+
+    pub fn memory(&self) -> &T {
+        &self.mem
+    }
+
+    pub fn memory_mut(&mut self) -> &mut T {
+        &mut self.mem
+    }
+
+    /// Stops runs when an instruction jumps or branches to itself. Off by default, since
+    /// programs commonly wait for interrupts that way
+    pub fn set_stop_on_trap(&mut self, enabled: bool) {
+        self.stop_on_trap = enabled;
+    }
+
+    /// Runs for at least `cycles` cycles
+    pub fn run_for_cycles(&mut self, cycles: u64) -> Result<StopReason, ExecError> {
+        let end = self.cycles + cycles;
+        self.run_with(|sys, _| match sys.cycles >= end {
+            true => Some(StopReason::CycleBudget),
+            false => None,
+        })
+    }
+
+    /// Runs `count` steps, as many instructions unless an interrupt or WAI takes some of them
+    pub fn run_for_instructions(&mut self, count: u64) -> Result<StopReason, ExecError> {
+        let mut done = 0u64;
+        if count == 0 {
+            return Ok(StopReason::InstructionBudget);
+        }
+        self.run_with(|_, _| {
+            done += 1;
+            match done >= count {
+                true => Some(StopReason::InstructionBudget),
+                false => None,
+            }
+        })
+    }
+
+    /// Runs until `condition`, checked after every step, is met
+    pub fn run_until<F: FnMut(&Self, &StepInfo) -> bool>(&mut self, mut condition: F) -> Result<StopReason, ExecError> {
+        self.run_with(|sys, info| match condition(sys, info) {
+            true => Some(StopReason::Condition),
+            false => None,
+        })
+    }
+
+    /// Runs until a breakpoint fires, a JAM, a trapped opcode or a trap if enabled
+    pub fn run(&mut self) -> Result<StopReason, ExecError> {
+        self.run_with(|_, _| None)
+    }
+
+    /// Steps until `stop` returns a reason or one of the common stop conditions happens.
+    /// A breakpoint on the first instruction is ignored, so that a run can resume from it
+    pub(super) fn run_with<F: FnMut(&Self, &StepInfo) -> Option<StopReason>>(&mut self, mut stop: F) -> Result<StopReason, ExecError> {
+        let mut first = true;
+        loop {
+            if !first && self.cycle_state.at_instruction_boundary() {
+                if let Some(reason) = self.breakpoint_before_step() {
+                    return Ok(reason);
+                }
+            }
+            first = false;
+
+            self.clear_watch_hit();
+            let info = match self.step() {
+                Ok(info) => info,
+                Err(ExecError::IllegalOpcode { pc, opcode }) => return Ok(StopReason::IllegalOpcode { pc, opcode }),
+                Err(err) => return Err(err),
+            };
+            if let Some(reason) = self.watchpoint_after_step(info.pc) {
+                return Ok(reason);
+            }
+            if self.jammed {
+                return Ok(StopReason::Jammed { pc: info.pc });
+            }
+            if let Some(reason) = stop(self, &info) {
+                return Ok(reason);
+            }
+            if self.stop_on_trap && info.opcode.is_some() && self.pc.native_value() == info.pc && !self.waiting {
+                return Ok(StopReason::Trap { pc: info.pc });
+            }
+        }
+    }
+}
@@ -0,0 +1,702 @@
+//! Cycle-stepped execution: every call to `System::tick` performs exactly one bus access,
+//! following the cycle-by-cycle behaviour of the NMOS 6502, dummy reads and writes included.
+
+use super::datastructures::word;
+use super::datastructures::doubleword;
+use super::datastructures::ClAdd;
+use super::datastructures::ClSub;
+use super::opcodes::Access;
+use super::opcodes::AddressingMode;
+use super::opcodes::Mnemonic;
+use super::System;
+use super::ExecError;
+use super::StepInfo;
+use super::savestate::SaveState;
+use super::savestate::SaveStateError;
+use super::savestate::StateReader;
+use super::savestate::StateWriter;
+use super::IO6502;
+use super::B_BIT;
+use super::U_BIT;
+
+/// How `System::step` executes instructions
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExecMode {
+    /// A whole instruction at once, through `alternate_exec`
+    Instruction,
+    /// One bus cycle at a time, through `tick`
+    Cycle,
+}
+
+/// Progress of the instruction being executed in cycle-stepped mode
+#[derive(Clone, Copy, Debug)]
+pub struct CycleState {
+    /// Opcode being executed, None when the next cycle fetches an opcode
+    opcode: Option<word>,
+    /// Cycle within the current instruction, the opcode fetch being cycle 0
+    t: u8,
+    /// Effective address, built over several cycles
+    addr: doubleword,
+    /// Zero page pointer of the indirect addressing modes
+    pointer: word,
+    /// Data latched from the bus
+    data: word,
+    /// Whether indexing carried into the high byte of `addr`
+    page_crossed: bool,
+    /// Whether the BRK sequence in progress was injected by an IRQ or an NMI
+    hardware_interrupt: bool,
+    /// Set when a 65C02 ADC or SBC in decimal mode needs its extra cycle
+    decimal_cycle: bool,
+    /// Address and opcode of the last instruction fetched, kept once it is done
+    instruction_pc: doubleword,
+    last_opcode: word,
+}
+
+impl CycleState {
+    pub fn new() -> Self {
+        Self {
+            opcode: None,
+            t: 0,
+            addr: doubleword::zero(),
+            pointer: word::zero(),
+            data: word::zero(),
+            page_crossed: false,
+            hardware_interrupt: false,
+            decimal_cycle: false,
+            instruction_pc: doubleword::zero(),
+            last_opcode: word::zero(),
+        }
+    }
+
+    /// True when no instruction is half-way through execution
+    pub fn at_instruction_boundary(&self) -> bool {
+        self.opcode.is_none()
+    }
+}
+
+impl SaveState for CycleState {
+    fn save(&self, out: &mut StateWriter) {
+        out.write_bool(self.opcode.is_some());
+        out.write_word(self.opcode.unwrap_or_else(word::zero));
+        out.write_u8(self.t);
+        out.write_doubleword(self.addr);
+        out.write_word(self.pointer);
+        out.write_word(self.data);
+        out.write_bool(self.page_crossed);
+        out.write_bool(self.hardware_interrupt);
+        out.write_bool(self.decimal_cycle);
+        out.write_doubleword(self.instruction_pc);
+        out.write_word(self.last_opcode);
+    }
+
+    fn restore(&mut self, input: &mut StateReader) -> Result<(), SaveStateError> {
+        let in_progress = input.read_bool()?;
+        let opcode = input.read_word()?;
+        self.opcode = match in_progress {
+            true => Some(opcode),
+            false => None,
+        };
+        self.t = input.read_u8()?;
+        self.addr = input.read_doubleword()?;
+        self.pointer = input.read_word()?;
+        self.data = input.read_word()?;
+        self.page_crossed = input.read_bool()?;
+        self.hardware_interrupt = input.read_bool()?;
+        self.decimal_cycle = input.read_bool()?;
+        self.instruction_pc = input.read_doubleword()?;
+        self.last_opcode = input.read_word()?;
+        Ok(())
+    }
+}
+
+impl<T: IO6502> System<T> {
+
+    /// Selects how `step` executes instructions. An instruction started in cycle-stepped
+    /// mode is completed before switching
+    pub fn set_exec_mode(&mut self, mode: ExecMode) -> Result<(), ExecError> {
+        while !self.cycle_state.at_instruction_boundary() {
+            self.tick()?;
+        }
+        self.exec_mode = mode;
+        Ok(())
+    }
+
+    /// Runs exactly one CPU cycle, i.e. one bus access. A bus fault is reported once its cycle
+    /// is over, the next tick carrying on with the instruction
+    pub fn tick(&mut self) -> Result<(), ExecError> {
+        if self.jammed {
+            return self.jammed_cycle().map(|_| ());
+        }
+        if self.stopped {
+            return Err(ExecError::Halted { pc: self.pc.native_value() });
+        }
+        if self.waiting && !self.wake_up() {
+            self.cycles += 1;
+            return Ok(());
+        }
+        self.poll_nmi();
+        match self.cycle_state.opcode {
+            None => {
+                // Interrupts are serviced by replacing the fetched opcode with BRK, PC staying put
+                self.cycle_state.instruction_pc = self.pc;
+                self.cycle_state.hardware_interrupt = self.interrupt_pending();
+                let instr = match self.cycle_state.hardware_interrupt {
+                    true => {
+                        self.dummy_read_pc();
+                        word::zero()
+                    },
+                    false => self.fetch_pc(),
+                };
+                let fetched = match self.cycle_state.hardware_interrupt {
+                    true => Ok(()),
+                    // A fault of the fetch is reported first, like in `step`
+                    false => self.take_bus_fault().and_then(|_| self.check_undocumented(instr, self.pc.cl_sub(doubleword::from(1u16)))),
+                };
+                if let Err(err) = fetched {
+                    // Faulted or trapped before anything happens, the fetch is undone
+                    self.pc = self.pc.cl_sub(doubleword::from(1u16));
+                    return Err(err);
+                }
+                // An interrupt sequence runs as BRK, which does not delay anything
+                self.latch_irq_mask(instr);
+                self.cycle_state.page_crossed = false;
+                self.cycle_state.last_opcode = instr;
+                if self.decode(instr).cycles == 1 {
+                    // 65C02 single cycle NOPs are done as soon as they are fetched
+                    self.op_implied(instr);
+                } else {
+                    self.cycle_state.opcode = Some(instr);
+                    self.cycle_state.t = 1;
+                }
+            },
+            Some(instr) => {
+                let t = self.cycle_state.t;
+                match self.cycle_exec(instr, t) {
+                    true => self.cycle_state.opcode = None,
+                    false => self.cycle_state.t += 1,
+                }
+            },
+        }
+        self.cycles += 1;
+        self.take_bus_fault()
+    }
+
+    /// Ticks until the current instruction is done. When called mid-instruction, only the
+    /// remaining cycles are counted. On error the instruction is left where it stopped
+    pub(super) fn cycle_step(&mut self) -> Result<StepInfo, ExecError> {
+        let mut cycles = 0u8;
+        loop {
+            self.tick()?;
+            cycles += 1;
+            if self.cycle_state.at_instruction_boundary() {
+                let opcode = match self.cycle_state.hardware_interrupt {
+                    true => None,
+                    false => Some(self.cycle_state.last_opcode.native_value()),
+                };
+                return Ok(StepInfo { pc: self.cycle_state.instruction_pc.native_value(), opcode, cycles });
+            }
+        }
+    }
+
+    #[inline]
+    /// Reads the byte at PC and increments it
+    fn fetch_pc(&mut self) -> word {
+        let ret = self.load(self.pc);
+        self.pc = self.pc.cl_add(doubleword::from(1u16));
+        ret
+    }
+
+    #[inline]
+    /// Reads the byte at PC, without incrementing it
+    fn dummy_read_pc(&mut self) {
+        self.load(self.pc);
+    }
+
+    /// Executes cycle `t` (>= 1) of `instr`. Returns true on the last cycle of the instruction
+    fn cycle_exec(&mut self, instr: word, t: u8) -> bool {
+        let opcode = self.decode(instr);
+
+        if self.cycle_state.decimal_cycle {
+            // Extra cycle of the 65C02 in decimal mode, reading the operand again
+            self.cycle_state.decimal_cycle = false;
+            self.load(self.cycle_state.addr);
+            return true;
+        }
+
+        match opcode.mnemonic {
+            Mnemonic::BRK => self.cycle_brk(t),
+            Mnemonic::JSR => self.cycle_jsr(t),
+            Mnemonic::RTS => self.cycle_rts(t),
+            Mnemonic::RTI => self.cycle_rti(t),
+            Mnemonic::PHA | Mnemonic::PHP | Mnemonic::PHX | Mnemonic::PHY => {
+                match t {
+                    1 => self.dummy_read_pc(),
+                    _ => {
+                        let val = match opcode.mnemonic {
+                            Mnemonic::PHA => self.a,
+                            Mnemonic::PHX => self.x,
+                            Mnemonic::PHY => self.y,
+                            _ => self.p | B_BIT | U_BIT,
+                        };
+                        self.push_word(val);
+                    },
+                }
+                t == 2
+            },
+            Mnemonic::PLA | Mnemonic::PLP | Mnemonic::PLX | Mnemonic::PLY => {
+                match t {
+                    1 => self.dummy_read_pc(),
+                    2 => {
+                        self.load(self.stack_address());
+                    },
+                    _ => {
+                        let val = self.pull_word();
+                        match opcode.mnemonic {
+                            Mnemonic::PLA => {
+                                self.a = val;
+                                self.update_flags_zn(self.a);
+                            },
+                            Mnemonic::PLX => {
+                                self.x = val;
+                                self.update_flags_zn(self.x);
+                            },
+                            Mnemonic::PLY => {
+                                self.y = val;
+                                self.update_flags_zn(self.y);
+                            },
+                            _ => self.p = (val & !B_BIT) | U_BIT,
+                        }
+                    },
+                }
+                t == 3
+            },
+            Mnemonic::JMP => self.cycle_jmp(opcode.mode, t),
+            Mnemonic::JAM => {
+                self.dummy_read_pc();
+                self.jammed = true;
+                true
+            },
+            // 65C02 $5C: an 8 cycle NOP that reads its operand, then spins on page $FF
+            Mnemonic::NOP if opcode.mode == AddressingMode::Absolute && opcode.cycles == 8 => {
+                match t {
+                    1 | 2 => {
+                        let byte = self.fetch_pc();
+                        self.cycle_state.addr = doubleword::from_words(word::from(0xFFu8), byte);
+                    },
+                    _ => {
+                        self.load(self.cycle_state.addr);
+                    },
+                }
+                t == 7
+            },
+            Mnemonic::WAI | Mnemonic::STP => {
+                self.dummy_read_pc();
+                if t == 2 {
+                    match opcode.mnemonic {
+                        Mnemonic::WAI => self.waiting = true,
+                        _ => self.stopped = true,
+                    }
+                }
+                t == 2
+            },
+            _ => match opcode.mode {
+                AddressingMode::Relative => self.cycle_branch(self.branch_condition(opcode.mnemonic), t),
+                AddressingMode::ZeropageRelative => self.cycle_bit_branch(instr, t),
+                _ => self.cycle_generic(instr, opcode.mode, t),
+            },
+        }
+    }
+
+    /// Addressing then access phases of the instructions that go through an effective address
+    fn cycle_generic(&mut self, instr: word, mode: AddressingMode, t: u8) -> bool {
+        let access = self.decode(instr).mnemonic.access();
+
+        match mode {
+            AddressingMode::Implied | AddressingMode::Accumulator => {
+                self.dummy_read_pc();
+                self.op_implied(instr);
+                true
+            },
+            AddressingMode::Immediate => {
+                self.cycle_state.addr = self.pc;
+                let val = self.fetch_pc();
+                self.finish_read(instr, val)
+            },
+            AddressingMode::Zeropage => match t {
+                1 => {
+                    self.cycle_state.addr = self.fetch_pc().as_doubleword();
+                    false
+                },
+                _ => self.cycle_access(instr, t - 2),
+            },
+            AddressingMode::ZeropageX | AddressingMode::ZeropageY => match t {
+                1 => {
+                    self.cycle_state.addr = self.fetch_pc().as_doubleword();
+                    false
+                },
+                2 => {
+                    // Dummy read from the unindexed address, indexing wraps within the zero page
+                    let base = self.cycle_state.addr;
+                    self.load(base);
+                    let index = match mode {
+                        AddressingMode::ZeropageX => self.x,
+                        _ => self.y,
+                    };
+                    self.cycle_state.addr = base.to_words()[0].cl_add(index).as_doubleword();
+                    false
+                },
+                _ => self.cycle_access(instr, t - 3),
+            },
+            AddressingMode::Absolute => match t {
+                1 => {
+                    self.cycle_state.addr = self.fetch_pc().as_doubleword();
+                    false
+                },
+                2 => {
+                    let hi = self.fetch_pc();
+                    self.cycle_state.addr = doubleword::from_words(hi, self.cycle_state.addr.to_words()[0]);
+                    false
+                },
+                _ => self.cycle_access(instr, t - 3),
+            },
+            AddressingMode::AbsoluteX | AddressingMode::AbsoluteY => match t {
+                1 => {
+                    self.cycle_state.addr = self.fetch_pc().as_doubleword();
+                    false
+                },
+                2 => {
+                    let hi = self.fetch_pc();
+                    let index = match mode {
+                        AddressingMode::AbsoluteX => self.x,
+                        _ => self.y,
+                    };
+                    self.index_address(doubleword::from_words(hi, self.cycle_state.addr.to_words()[0]), index);
+                    false
+                },
+                3 => self.cycle_indexed_read(instr, access),
+                _ => self.cycle_access(instr, t - 4),
+            },
+            AddressingMode::IndirectX => match t {
+                1 => {
+                    self.cycle_state.pointer = self.fetch_pc();
+                    false
+                },
+                2 => {
+                    self.load(self.cycle_state.pointer.as_doubleword());
+                    self.cycle_state.pointer = self.cycle_state.pointer.cl_add(self.x);
+                    false
+                },
+                3 => {
+                    self.cycle_state.addr = self.load(self.cycle_state.pointer.as_doubleword()).as_doubleword();
+                    false
+                },
+                4 => {
+                    let hi = self.load(self.cycle_state.pointer.cl_add(word::from(1u8)).as_doubleword());
+                    self.cycle_state.addr = doubleword::from_words(hi, self.cycle_state.addr.to_words()[0]);
+                    false
+                },
+                _ => self.cycle_access(instr, t - 5),
+            },
+            AddressingMode::IndirectY => match t {
+                1 => {
+                    self.cycle_state.pointer = self.fetch_pc();
+                    false
+                },
+                2 => {
+                    self.cycle_state.addr = self.load(self.cycle_state.pointer.as_doubleword()).as_doubleword();
+                    false
+                },
+                3 => {
+                    let hi = self.load(self.cycle_state.pointer.cl_add(word::from(1u8)).as_doubleword());
+                    self.index_address(doubleword::from_words(hi, self.cycle_state.addr.to_words()[0]), self.y);
+                    false
+                },
+                4 => self.cycle_indexed_read(instr, access),
+                _ => self.cycle_access(instr, t - 5),
+            },
+            AddressingMode::ZeropageIndirect => match t {
+                1 => {
+                    self.cycle_state.pointer = self.fetch_pc();
+                    false
+                },
+                2 => {
+                    self.cycle_state.addr = self.load(self.cycle_state.pointer.as_doubleword()).as_doubleword();
+                    false
+                },
+                3 => {
+                    let hi = self.load(self.cycle_state.pointer.cl_add(word::from(1u8)).as_doubleword());
+                    self.cycle_state.addr = doubleword::from_words(hi, self.cycle_state.addr.to_words()[0]);
+                    false
+                },
+                _ => self.cycle_access(instr, t - 4),
+            },
+            AddressingMode::Relative | AddressingMode::Indirect | AddressingMode::AbsoluteIndexedIndirect
+            | AddressingMode::ZeropageRelative => unreachable!("handled by cycle_exec"),
+        }
+    }
+
+    /// Adds the index to the low byte of `base` only, as the CPU does on the first try.
+    /// The carry into the high byte is remembered in `page_crossed`
+    fn index_address(&mut self, base: doubleword, index: word) {
+        let [lo, hi] = base.to_words();
+        let (new_lo, carry) = lo.native_value().overflowing_add(index.native_value());
+        self.cycle_state.addr = doubleword::from_words(hi, word::from(new_lo));
+        self.cycle_state.page_crossed = carry;
+    }
+
+    /// Read from the address before the high byte is fixed. If it was already right, a read
+    /// instruction is done here. Otherwise the read is a dummy one and the access happens next cycle
+    fn cycle_indexed_read(&mut self, instr: word, access: Access) -> bool {
+        if self.cycle_state.page_crossed {
+            match self.is_65c02() {
+                // The 65C02 reads the last operand byte again rather than a wrong address
+                true => self.load(self.pc.cl_sub(doubleword::from(1u16))),
+                false => self.load(self.cycle_state.addr),
+            };
+            self.cycle_state.addr = self.cycle_state.addr.cl_add(doubleword::from(0x100u16));
+            return false;
+        }
+
+        let val = self.load(self.cycle_state.addr);
+        match access {
+            Access::Read => self.finish_read(instr, val),
+            Access::ReadModifyWrite if self.is_65c02() && self.decode(instr).page_penalty => {
+                // 65C02 shifts only take the extra cycle on a page cross: this was their actual read,
+                // so the read cycle of the access phase is skipped
+                self.cycle_state.data = val;
+                self.cycle_state.t += 1;
+                false
+            },
+            _ => false,
+        }
+    }
+
+    /// Executes a read instruction on its operand. Returns true if the instruction is done,
+    /// false if it is a 65C02 ADC or SBC in decimal mode, which takes one more cycle
+    fn finish_read(&mut self, instr: word, val: word) -> bool {
+        let decimal_penalty = self.decimal_penalty(self.decode(instr).mnemonic);
+        self.op_read(instr, val);
+        self.cycle_state.decimal_cycle = decimal_penalty;
+        !decimal_penalty
+    }
+
+    /// Access phase at the effective address, `k` being the cycle within this phase
+    fn cycle_access(&mut self, instr: word, k: u8) -> bool {
+        let addr = self.cycle_state.addr;
+        match (self.decode(instr).mnemonic.access(), k) {
+            (Access::Read, _) => {
+                let val = self.load(addr);
+                self.finish_read(instr, val)
+            },
+            (Access::Write, _) => {
+                let (addr, val) = self.op_write(instr, addr, self.cycle_state.page_crossed);
+                self.store(addr, val);
+                true
+            },
+            (Access::ReadModifyWrite, 0) => {
+                self.cycle_state.data = self.load(addr);
+                false
+            },
+            (Access::ReadModifyWrite, 1) => {
+                // While the ALU works, the NMOS 6502 writes the unmodified value back and the 65C02 reads it again
+                let old = self.cycle_state.data;
+                match self.is_65c02() {
+                    true => {
+                        self.load(addr);
+                    },
+                    false => self.store(addr, old),
+                }
+                self.cycle_state.data = self.op_modify(instr, old);
+                false
+            },
+            (Access::ReadModifyWrite, _) => {
+                self.store(addr, self.cycle_state.data);
+                true
+            },
+        }
+    }
+
+    /// Branch with its offset at PC, `taken` being the branch condition
+    fn cycle_branch(&mut self, taken: bool, t: u8) -> bool {
+        match t {
+            1 => {
+                self.cycle_state.data = self.fetch_pc();
+                !taken
+            },
+            2 => {
+                self.dummy_read_pc();
+                let target = self.relative_address(self.cycle_state.data);
+                if !Self::crosses_page(self.pc, target) {
+                    self.pc = target;
+                    return true;
+                }
+                // PCL is updated first, the high byte is fixed on the next cycle
+                self.cycle_state.addr = target;
+                self.pc = doubleword::from_words(self.pc.to_words()[1], target.to_words()[0]);
+                false
+            },
+            _ => {
+                self.dummy_read_pc();
+                self.pc = self.cycle_state.addr;
+                true
+            },
+        }
+    }
+
+    /// 65C02 BBR and BBS: zero page operand, then a branch
+    fn cycle_bit_branch(&mut self, instr: word, t: u8) -> bool {
+        match t {
+            1 => {
+                self.cycle_state.addr = self.fetch_pc().as_doubleword();
+                false
+            },
+            2 => {
+                self.cycle_state.pointer = self.load(self.cycle_state.addr);
+                false
+            },
+            3 => {
+                self.load(self.cycle_state.addr);
+                false
+            },
+            _ => {
+                let bit_set = (self.cycle_state.pointer & Self::bit_mask(instr)).native_value() != 0;
+                let taken = bit_set == (self.decode(instr).mnemonic == Mnemonic::BBS);
+                self.cycle_branch(taken, t - 3)
+            },
+        }
+    }
+
+    fn cycle_jmp(&mut self, mode: AddressingMode, t: u8) -> bool {
+        match (mode, t) {
+            (_, 1) => {
+                self.cycle_state.addr = self.fetch_pc().as_doubleword();
+                false
+            },
+            (AddressingMode::Absolute, _) => {
+                let hi = self.fetch_pc();
+                self.pc = doubleword::from_words(hi, self.cycle_state.addr.to_words()[0]);
+                true
+            },
+            (_, 2) => {
+                let hi = self.fetch_pc();
+                self.cycle_state.addr = doubleword::from_words(hi, self.cycle_state.addr.to_words()[0]);
+                false
+            },
+            // The 65C02 spends a cycle fixing the JMP ($xxFF) bug, or adding X
+            (AddressingMode::AbsoluteIndexedIndirect, 3) => {
+                self.load(self.pc.cl_sub(doubleword::from(1u16)));
+                self.cycle_state.addr = self.cycle_state.addr.cl_add(self.x);
+                false
+            },
+            (_, 3) if self.is_65c02() => {
+                self.load(self.pc.cl_sub(doubleword::from(1u16)));
+                false
+            },
+            (_, 3) => {
+                self.cycle_state.data = self.load(self.cycle_state.addr);
+                false
+            },
+            (_, 4) if !self.is_65c02() => {
+                // The pointer's high byte is fetched without carrying into the page (JMP ($xxFF) bug)
+                let [lo, page] = self.cycle_state.addr.to_words();
+                let hi = self.load(doubleword::from_words(page, lo.cl_add(word::from(1u8))));
+                self.pc = doubleword::from_words(hi, self.cycle_state.data);
+                true
+            },
+            (_, 4) => {
+                self.cycle_state.data = self.load(self.cycle_state.addr);
+                false
+            },
+            _ => {
+                let hi = self.load(self.cycle_state.addr.cl_add(doubleword::from(1u16)));
+                self.pc = doubleword::from_words(hi, self.cycle_state.data);
+                true
+            },
+        }
+    }
+
+    fn cycle_jsr(&mut self, t: u8) -> bool {
+        match t {
+            1 => self.cycle_state.data = self.fetch_pc(),
+            2 => {
+                self.load(self.stack_address());
+            },
+            3 => self.push_word(self.pc.to_words()[1]),
+            4 => self.push_word(self.pc.to_words()[0]),
+            _ => {
+                let hi = self.load(self.pc);
+                self.pc = doubleword::from_words(hi, self.cycle_state.data);
+            },
+        }
+        t == 5
+    }
+
+    fn cycle_rts(&mut self, t: u8) -> bool {
+        match t {
+            1 => self.dummy_read_pc(),
+            2 => {
+                self.load(self.stack_address());
+            },
+            3 => self.cycle_state.data = self.pull_word(),
+            4 => {
+                let hi = self.pull_word();
+                self.pc = doubleword::from_words(hi, self.cycle_state.data);
+            },
+            _ => {
+                self.fetch_pc();
+            },
+        }
+        t == 5
+    }
+
+    fn cycle_rti(&mut self, t: u8) -> bool {
+        match t {
+            1 => self.dummy_read_pc(),
+            2 => {
+                self.load(self.stack_address());
+            },
+            3 => {
+                let p = self.pull_word();
+                self.p = (p & !B_BIT) | U_BIT;
+            },
+            4 => self.cycle_state.data = self.pull_word(),
+            _ => {
+                let hi = self.pull_word();
+                self.pc = doubleword::from_words(hi, self.cycle_state.data);
+            },
+        }
+        t == 5
+    }
+
+    fn cycle_brk(&mut self, t: u8) -> bool {
+        match t {
+            1 => match self.cycle_state.hardware_interrupt {
+                true => self.dummy_read_pc(),
+                // Padding byte
+                false => {
+                    self.fetch_pc();
+                },
+            },
+            2 => self.push_word(self.pc.to_words()[1]),
+            3 => self.push_word(self.pc.to_words()[0]),
+            4 => match self.cycle_state.hardware_interrupt {
+                true => self.push_word((self.p & !B_BIT) | U_BIT),
+                false => self.push_word(self.p | B_BIT | U_BIT),
+            },
+            5 => {
+                // The vector is chosen here, so an NMI detected up to now hijacks BRK and IRQ
+                self.cycle_state.addr = self.interrupt_vector();
+                self.cycle_state.data = self.load(self.cycle_state.addr);
+                self.set_I();
+                if self.is_65c02() {
+                    self.clear_D();
+                }
+            },
+            _ => {
+                let hi = self.load(self.cycle_state.addr.cl_add(doubleword::from(1u16)));
+                self.pc = doubleword::from_words(hi, self.cycle_state.data);
+            },
+        }
+        t == 6
+    }
+}
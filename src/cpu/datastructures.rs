@@ -6,13 +6,14 @@ use std::ops::BitXor;
 use std::ops::Shl;
 use std::ops::Shr;
 
-pub const CARRY_BIT: i16 = (1 << 8);
+pub const CARRY_BIT: i16 = 1 << 8;
 
 /// Trait that represents the act of appending (opposed to prepending) some data into a structure
 pub trait Push<O: Sized> {
     fn push(&mut self, data: O);
 }
 // Convenience structure to build the binary code
+#[derive(Default)]
 pub struct InstructionStream {
     pub stream: Vec<word>,
 }
@@ -47,11 +48,6 @@ impl From<Vec<word>> for InstructionStream {
     }
 }
 
-#[inline]
-fn carry_bit(val: doubleword) -> bool {
-    (val & CARRY_BIT) != 0u16
-}
-
 #[inline]
 fn carry_bit_u16(val: u16) -> bool {
     (val & (CARRY_BIT as u16)) != 0
@@ -73,54 +69,6 @@ fn get_bit_at_u16(data: u16, n: u8) -> Option<bool> {
     }
 }
 
-#[inline]
-fn get_bit_at_u32(data: u32, n: u8) -> Option<bool> {
-    match n {
-        0..=31 => Some(data & (1 << n) != 0),
-        _ => None,
-    }
-}
-
-#[inline]
-fn get_bit_at_u64(data: u64, n: u8) -> Option<bool> {
-    match n {
-        0..=63 => Some(data & (1 << n) != 0),
-        _ => None,
-    }
-}
-
-#[inline]
-fn get_bit_at_i8(data: i8, n: u8) -> Option<bool> {
-    match n {
-        0..=7 => Some(data & (1 << n) != 0),
-        _ => None,
-    }
-}
-
-#[inline]
-fn get_bit_at_i16(data: i16, n: u8) -> Option<bool> {
-    match n {
-        0..=15 => Some(data & (1 << n) != 0),
-        _ => None,
-    }
-}
-
-#[inline]
-fn get_bit_at_i32(data: i32, n: u8) -> Option<bool> {
-    match n {
-        0..=31 => Some(data & (1 << n) != 0),
-        _ => None,
-    }
-}
-
-#[inline]
-fn get_bit_at_i64(data: i64, n: u8) -> Option<bool> {
-    match n {
-        0..=63 => Some(data & (1 << n) != 0),
-        _ => None,
-    }
-}
-
 /// Trait reperesenting a carry-less (CL) addition
 /// Carry-less means that it must over- or under-flow silently
 pub trait ClAdd<O: Sized>: Sized {
@@ -128,6 +76,12 @@ pub trait ClAdd<O: Sized>: Sized {
     fn cl_add(self, other: O) -> Self::Output;
 }
 
+/// Trait reperesenting a borrow-less subtraction, the counterpart of `ClAdd`
+pub trait ClSub<O: Sized>: Sized {
+    type Output;
+    fn cl_sub(self, other: O) -> Self::Output;
+}
+
 /// Reperesents a word in 6502 (i.e. a single byte).
 /// Currently stored in native endianness
 #[derive(Clone, Copy, Debug)]
@@ -184,7 +138,7 @@ impl word {
     #[inline]
     // Performs an unchecked shift left, along with returning the carry value (i.e. bit '8' of the result)
     pub fn logical_shift_left_carry(self, rhs: u16) -> (word, bool) {
-        let intermediate: u16 = ((self.value as u16) << rhs) as u16;
+        let intermediate: u16 = (self.value as u16) << rhs;
 
         let ret = Self {
             value: intermediate as u8,
@@ -256,26 +210,6 @@ impl word {
         self.value & 0b00000011u8
     }
 
-    pub fn update_aaa(&mut self, byte: u8) {
-        debug_assert!(byte <= 0b111u8, "Assembler: trying to update aaa with a value more than 3 bits");
-        let val = self.native_value();
-        self.value = (byte << 5u8) | (val & 0b00011111u8);
-    }
-
-    // FIXME: implement it
-    pub fn update_bbb(&mut self, byte: u8) {
-        debug_assert!(byte <= 0b111u8, "Assembler: trying to update bbb with a value more than 3 bits");
-        let val = self.native_value();
-        self.value = (byte << 2u8) | (val & 0b11100011u8);
-    }
-
-    // FIXME: implement it
-    pub fn update_cc(&mut self, byte: u8) {
-        debug_assert!(byte <= 0b11u8, "Assembler: trying to update cc with a value more than 3 bits");
-        let val = self.native_value();
-        self.value = byte | (val & 0b11111100u8);
-    }
-
     pub fn as_i16(self) -> i16 {
         self.native_value() as i16
     }
@@ -292,6 +226,17 @@ impl ClAdd<Self> for word {
     }
 }
 
+impl ClSub<Self> for word {
+    type Output = Self;
+
+    #[inline]
+    fn cl_sub(self, other: Self) -> Self {
+        Self {
+            value: self.value.wrapping_sub(other.value),
+        }
+    }
+}
+
 impl Add<Self> for word {
     type Output = Self;
 
@@ -535,6 +480,17 @@ impl ClAdd<Self> for doubleword {
     }
 }
 
+impl ClSub<Self> for doubleword {
+    type Output = Self;
+
+    #[inline]
+    fn cl_sub(self, other: Self) -> Self {
+        Self {
+            value: self.value.wrapping_sub(other.value),
+        }
+    }
+}
+
 impl ClAdd<word> for doubleword {
     type Output = Self;
 
@@ -0,0 +1,169 @@
+//! Debugger core: breakpoints on execution, memory accesses, opcodes and interrupts, and
+//! stepping over or out of subroutines. Runs report the breakpoint that stopped them by its id,
+//! see `StopReason::Breakpoint` and `StopReason::Watchpoint`.
+
+use std::collections::BTreeMap;
+use super::datastructures::word;
+use super::datastructures::doubleword;
+use super::control::StopReason;
+use super::opcodes::Mnemonic;
+use super::ExecError;
+use super::MemoryAccessType;
+use super::System;
+use super::IO6502;
+
+/// Identifies a breakpoint, as given by `System::add_breakpoint`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BreakpointId(pub u32);
+
+/// When a run stops. Breakpoints fire before the instruction, watchpoints after the
+/// instruction that made the access
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// PC reaches `address`, with `bank` mapped there if given (see `IO6502::bank`)
+    Execute { address: u16, bank: Option<u8> },
+    /// The bus reads from `start..=end`, operand fetches and dummy reads included
+    Read { start: u16, end: u16 },
+    /// The bus writes to `start..=end`, dummy writes included
+    Write { start: u16, end: u16 },
+    /// The next instruction has this opcode
+    Opcode(u8),
+    /// An IRQ or NMI is about to be serviced
+    Interrupt,
+}
+
+/// First watched access of the instruction being executed
+#[derive(Clone, Copy, Debug)]
+struct WatchHit {
+    id: BreakpointId,
+    address: u16,
+    access: MemoryAccessType,
+}
+
+/// Breakpoints of a system, kept across save state restores
+#[derive(Clone, Debug, Default)]
+pub struct Debugger {
+    breakpoints: BTreeMap<BreakpointId, Breakpoint>,
+    next_id: u32,
+    /// Whether there are watchpoints, checked on every bus access
+    watching: bool,
+    watch_hit: Option<WatchHit>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the access if it is watched and nothing was recorded yet
+    pub fn watch(&mut self, address: doubleword, access: MemoryAccessType) {
+        if !self.watching || self.watch_hit.is_some() {
+            return;
+        }
+        let addr = address.native_value();
+        let hit = self.breakpoints.iter().find(|(_, bp)| match (**bp, access) {
+            (Breakpoint::Read { start, end }, MemoryAccessType::Load) |
+            (Breakpoint::Write { start, end }, MemoryAccessType::Store) => (start..=end).contains(&addr),
+            _ => false,
+        });
+        if let Some((&id, _)) = hit {
+            self.watch_hit = Some(WatchHit { id, address: addr, access });
+        }
+    }
+}
+
+impl<T: IO6502> System<T> {
+
+    /// Adds a breakpoint, returning the id runs report when it fires
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> BreakpointId {
+        let debugger = &mut self.debugger;
+        let id = BreakpointId(debugger.next_id);
+        debugger.next_id += 1;
+        debugger.breakpoints.insert(id, breakpoint);
+        debugger.watching |= matches!(breakpoint, Breakpoint::Read { .. } | Breakpoint::Write { .. });
+        id
+    }
+
+    /// Returns the removed breakpoint, None if there was none with this id
+    pub fn remove_breakpoint(&mut self, id: BreakpointId) -> Option<Breakpoint> {
+        let debugger = &mut self.debugger;
+        let ret = debugger.breakpoints.remove(&id);
+        debugger.watching = debugger.breakpoints.values().any(|bp| matches!(bp, Breakpoint::Read { .. } | Breakpoint::Write { .. }));
+        ret
+    }
+
+    /// Breakpoints in the order they were added
+    pub fn breakpoints(&self) -> impl Iterator<Item = (BreakpointId, Breakpoint)> + '_ {
+        self.debugger.breakpoints.iter().map(|(&id, &bp)| (id, bp))
+    }
+
+    /// Breakpoint firing before the next step, if any. Called by runs at instruction boundaries
+    pub(super) fn breakpoint_before_step(&mut self) -> Option<StopReason> {
+        if self.debugger.breakpoints.is_empty() {
+            return None;
+        }
+        let pc = self.pc.native_value();
+        let bank = self.mem.bank(self.pc);
+        let opcode = self.mem.peek(self.pc).map(|instr| instr.native_value());
+        self.poll_nmi();
+        let interrupt = self.interrupt_pending();
+
+        self.debugger.breakpoints.iter()
+            .find(|(_, bp)| match **bp {
+                Breakpoint::Execute { address, bank: wanted } => !self.waiting && address == pc && (wanted.is_none() || wanted == bank),
+                Breakpoint::Opcode(op) => !self.waiting && !interrupt && opcode == Some(op),
+                Breakpoint::Interrupt => interrupt,
+                Breakpoint::Read { .. } | Breakpoint::Write { .. } => false,
+            })
+            .map(|(&id, _)| StopReason::Breakpoint { id, pc })
+    }
+
+    /// Forgets watched accesses made outside of runs, called before every step of a run
+    pub(super) fn clear_watch_hit(&mut self) {
+        self.debugger.watch_hit = None;
+    }
+
+    /// Watchpoint hit by the step that was just run, if any
+    pub(super) fn watchpoint_after_step(&mut self, pc: u16) -> Option<StopReason> {
+        self.debugger.watch_hit.take()
+            .map(|hit| StopReason::Watchpoint { id: hit.id, pc, address: hit.address, access: hit.access })
+    }
+
+    /// Executes the next instruction, running a subroutine it calls until it returns. The call
+    /// is over once PC is right after the JSR with S back where it was, so recursive calls of the
+    /// same subroutine are run through. A subroutine returning elsewhere, e.g. past inline
+    /// arguments, or leaving the stack changed, is run until something else stops it
+    pub fn step_over(&mut self) -> Result<StopReason, ExecError> {
+        let is_call = self.mem.peek(self.pc).map(|instr| self.decode(instr).mnemonic) == Some(Mnemonic::JSR);
+        if !is_call {
+            return self.run_for_instructions(1).map(|reason| match reason {
+                StopReason::InstructionBudget => StopReason::StepComplete,
+                reason => reason,
+            });
+        }
+
+        // Back from the call once PC is after the JSR with the return address popped
+        let return_pc = self.pc.native_value().wrapping_add(3);
+        let s = self.s.native_value();
+        self.run_with(|sys, _| match sys.pc.native_value() == return_pc && sys.s.native_value() == s {
+            true => Some(StopReason::StepComplete),
+            false => None,
+        })
+    }
+
+    /// Runs until the current subroutine or interrupt handler returns, i.e. until an RTS or an
+    /// RTI pops a return address pushed before the call. S is compared as a distance, the stack
+    /// wrapping around within page $01
+    pub fn step_out(&mut self) -> Result<StopReason, ExecError> {
+        let s = self.s.native_value();
+        self.run_with(|sys, info| {
+            let returned = info.opcode
+                .map(|op| matches!(sys.decode(word::from(op)).mnemonic, Mnemonic::RTS | Mnemonic::RTI))
+                .unwrap_or(false);
+            match returned && sys.s.native_value().wrapping_sub(s) as i8 > 0 {
+                true => Some(StopReason::StepComplete),
+                false => None,
+            }
+        })
+    }
+}
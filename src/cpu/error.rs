@@ -0,0 +1,48 @@
+//! Errors reported by the CPU instead of panicking, so that callers can recover, report and continue
+
+use std::error::Error;
+use std::fmt;
+use super::MemoryAccessType;
+
+/// A bus access that no device could serve
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BusFault {
+    pub address: u16,
+    pub access: MemoryAccessType,
+}
+
+/// Why a step could not be executed normally
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExecError {
+    /// An undocumented opcode was met while they are trapped. Nothing was executed, PC still
+    /// points to the opcode
+    IllegalOpcode { pc: u16, opcode: u8 },
+    /// A bus access failed. The instruction still completed, failed reads returning 0
+    BusFault(BusFault),
+    /// The CPU is halted (JAM with the halting policy, or 65C02 STP) and only a reset restarts it
+    Halted { pc: u16 },
+}
+
+impl From<BusFault> for ExecError {
+    fn from(fault: BusFault) -> Self {
+        ExecError::BusFault(fault)
+    }
+}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExecError::IllegalOpcode { pc, opcode } => write!(f, "illegal opcode ${:02X} at ${:04X}", opcode, pc),
+            ExecError::BusFault(fault) => {
+                let access = match fault.access {
+                    MemoryAccessType::Load => "load from",
+                    MemoryAccessType::Store => "store to",
+                };
+                write!(f, "bus fault on {} ${:04X}", access, fault.address)
+            },
+            ExecError::Halted { pc } => write!(f, "CPU halted at ${:04X}", pc),
+        }
+    }
+}
+
+impl Error for ExecError {}
@@ -0,0 +1,202 @@
+//! 64K of RAM covering the whole address space, for generic 6502 programs, unit tests and
+//! fuzzing. Regions can be protected as ROM, and the interrupt lines are driven by hand.
+
+use std::ops::RangeInclusive;
+use super::datastructures::word;
+use super::datastructures::doubleword;
+use super::datastructures::InstructionStream;
+use super::savestate::SaveState;
+use super::savestate::SaveStateError;
+use super::savestate::StateReader;
+use super::savestate::StateWriter;
+use super::BusFault;
+use super::MemoryAccessType;
+use super::IO6502;
+use super::IRQ_VECTOR;
+use super::NMI_VECTOR;
+use super::RESET_VECTOR;
+
+const SIZE: usize = 0x10000;
+
+/// RAM on every address, except where protected
+pub struct FlatMemory {
+    data: Vec<word>,
+    /// Stores there fault
+    protected: Vec<RangeInclusive<u16>>,
+    /// Where `push_program` puts programs
+    program_origin: u16,
+    nmi: bool,
+    irq: bool,
+}
+
+impl Default for FlatMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FlatMemory {
+    /// All zeroes and writable, loading programs at $0000
+    pub fn new() -> Self {
+        Self {
+            data: vec![word::zero(); SIZE],
+            protected: Vec::new(),
+            program_origin: 0,
+            nmi: false,
+            irq: false,
+        }
+    }
+
+    /// Makes `range` read-only, stores there then fault. Loading programs and setting vectors
+    /// still write there
+    pub fn protect(&mut self, range: RangeInclusive<u16>) {
+        self.protected.push(range);
+    }
+
+    /// Places `program` at `origin`, past $FFFF it wraps around to $0000
+    pub fn load_at(&mut self, origin: u16, program: InstructionStream) {
+        for (idx, byte) in program.stream.into_iter().enumerate() {
+            self.data[origin.wrapping_add(idx as u16) as usize] = byte;
+        }
+    }
+
+    /// Where `push_program` loads programs
+    pub fn set_program_origin(&mut self, origin: u16) {
+        self.program_origin = origin;
+    }
+
+    #[inline]
+    fn set_vector(&mut self, vector: u16, target: u16) {
+        let [lo, hi] = doubleword::from(target).to_words();
+        self.data[vector as usize] = lo;
+        self.data[vector as usize + 1] = hi;
+    }
+
+    /// Where the CPU starts after a reset
+    pub fn set_reset_vector(&mut self, target: u16) {
+        self.set_vector(RESET_VECTOR, target);
+    }
+
+    /// Handler of IRQ and BRK
+    pub fn set_irq_vector(&mut self, target: u16) {
+        self.set_vector(IRQ_VECTOR, target);
+    }
+
+    pub fn set_nmi_vector(&mut self, target: u16) {
+        self.set_vector(NMI_VECTOR, target);
+    }
+
+    /// Asserts or releases the NMI line
+    pub fn set_nmi(&mut self, asserted: bool) {
+        self.nmi = asserted;
+    }
+
+    /// Asserts or releases the IRQ line
+    pub fn set_irq(&mut self, asserted: bool) {
+        self.irq = asserted;
+    }
+
+    #[inline]
+    fn is_protected(&self, address: u16) -> bool {
+        self.protected.iter().any(|range| range.contains(&address))
+    }
+}
+
+impl IO6502 for FlatMemory {
+    fn new_resetted() -> Self {
+        Self::new()
+    }
+
+    /// Clears everything but the protected regions
+    fn reset(&mut self) {
+        for address in 0..SIZE {
+            if !self.is_protected(address as u16) {
+                self.data[address] = word::zero();
+            }
+        }
+    }
+
+    fn push_program(&mut self, program: InstructionStream) {
+        self.load_at(self.program_origin, program);
+    }
+
+    fn store(&mut self, address: doubleword, data: word) -> Result<(), BusFault> {
+        if !self.protected.is_empty() && self.is_protected(address.native_value()) {
+            return Err(BusFault { address: address.native_value(), access: MemoryAccessType::Store });
+        }
+        self.data[address.as_addr()] = data;
+        Ok(())
+    }
+
+    fn load(&mut self, address: doubleword) -> Result<word, BusFault> {
+        Ok(self.data[address.as_addr()])
+    }
+
+    fn peek(&self, address: doubleword) -> Option<word> {
+        Some(self.data[address.as_addr()])
+    }
+
+    fn nmi_asserted(&self) -> bool {
+        self.nmi
+    }
+
+    fn irq_asserted(&self) -> bool {
+        self.irq
+    }
+}
+
+impl SaveState for FlatMemory {
+    fn save(&self, out: &mut StateWriter) {
+        out.write_words(&self.data);
+        out.write_bool(self.nmi);
+        out.write_bool(self.irq);
+    }
+
+    fn restore(&mut self, input: &mut StateReader) -> Result<(), SaveStateError> {
+        input.read_words(&mut self.data)?;
+        self.nmi = input.read_bool()?;
+        self.irq = input.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod flat_tests {
+
+    use super::*;
+    use super::super::ExecError;
+    use super::super::System;
+
+    fn stream(bytes: &[u8]) -> InstructionStream {
+        InstructionStream::from(bytes.iter().map(|b| word::from(*b)).collect::<Vec<word>>())
+    }
+
+    #[test]
+    fn programs_vectors_and_protection() {
+        let mut sys: System<FlatMemory> = System::new_resetted();
+        let mem = sys.memory_mut();
+        // LDA #$42; STA $F000; CLI; NOP
+        mem.load_at(0x1234, stream(&[0xA9, 0x42, 0x8D, 0x00, 0xF0, 0x58, 0xEA]));
+        mem.load_at(0xF100, stream(&[0x40])); // RTI
+        mem.protect(0xF000..=0xFFFF);
+        mem.set_reset_vector(0x1234);
+        mem.set_irq_vector(0xF100);
+        sys.reset().unwrap();
+        assert_eq!(sys.registers().pc, 0x1234);
+
+        sys.step().unwrap();
+        assert_eq!(sys.step(), Err(ExecError::BusFault(BusFault { address: 0xF000, access: MemoryAccessType::Store })));
+        assert_eq!(sys.peek(0xF000), Some(0x00));
+
+        sys.memory_mut().set_irq(true);
+        sys.step().unwrap(); // CLI
+        sys.step().unwrap(); // NOP, I is cleared one instruction late
+        assert_eq!(sys.registers().pc, 0x123B);
+        sys.step().unwrap();
+        assert_eq!(sys.registers().pc, 0xF100);
+
+        sys.memory_mut().reset();
+        assert_eq!(sys.peek(0x1234), Some(0x00));
+        assert_eq!(sys.peek(0xF100), Some(0x40));
+    }
+}
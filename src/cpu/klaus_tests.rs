@@ -0,0 +1,77 @@
+//! Klaus Dormann's 6502 functional and decimal tests, the standard acceptance tests of 6502 cores.
+//! The binaries are looked up in `test-roms/klaus`. They are GPL-3.0 and not vendored yet (see
+//! the README there), so the tests are ignored by default: `cargo test --release -- --ignored klaus`
+//! once they are in place.
+//!
+//! Both tests report their result by trapping, i.e. by jumping to the instruction itself:
+//! the run stops when PC gets stuck, and the trap address tells if the test succeeded.
+
+use std::fs;
+use std::path::PathBuf;
+use super::datastructures::word;
+use super::datastructures::doubleword;
+use super::datastructures::InstructionStream;
+use super::control::StopReason;
+use super::flat::FlatMemory;
+use super::CpuVariant;
+use super::System;
+use super::IO6502;
+
+/// Way more than the ~100M cycles taken by the functional test
+const CYCLE_BUDGET: u64 = 200_000_000;
+
+/// Trap address of a successful run of the functional test, as assembled in `bin_files`.
+/// Taken from the upstream listing, not yet checked against a run
+const FUNCTIONAL_SUCCESS: u16 = 0x3469;
+/// Where the functional test keeps the number of the test being run
+const FUNCTIONAL_TEST_CASE: u16 = 0x0200;
+
+/// The decimal test is assembled at $0200 and sets ERROR to 0 on success. Taken from the
+/// upstream source, not yet checked against a run
+const DECIMAL_START: u16 = 0x0200;
+const DECIMAL_ERROR: u16 = 0x000B;
+
+/// Loads `name` at `address`, with PC at `start`
+fn load_rom(name: &str, address: u16, start: u16) -> System<FlatMemory> {
+    let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "test-roms", "klaus", name].iter().collect();
+    let bytes = fs::read(&path).unwrap_or_else(|err| panic!("{}: {} (see test-roms/klaus/README.md)", path.display(), err));
+    assert!(address as usize + bytes.len() <= 0x10000, "{} does not fit at ${:04X}", name, address);
+
+    let program: Vec<word> = bytes.iter().map(|b| word::from(*b)).collect();
+    let mut sys: System<FlatMemory> = System::with_variant(CpuVariant::Nmos6502);
+    sys.mem.load_at(address, InstructionStream::from(program));
+    sys.pc = doubleword::from(start);
+    sys.set_stop_on_trap(true);
+    sys
+}
+
+/// Runs until the test traps, returning the trap address
+fn run_to_trap(sys: &mut System<FlatMemory>, name: &str) -> u16 {
+    match sys.run_for_cycles(CYCLE_BUDGET) {
+        Ok(StopReason::Trap { pc }) => pc,
+        other => panic!("{} did not trap: {:?} at ${:04X}", name, other, sys.pc.native_value()),
+    }
+}
+
+fn peek(sys: &System<FlatMemory>, address: u16) -> u8 {
+    sys.mem.peek(doubleword::from(address)).unwrap().native_value()
+}
+
+#[test]
+#[ignore = "needs 6502_functional_test.bin in test-roms/klaus"]
+fn klaus_functional_test() {
+    let name = "6502_functional_test.bin";
+    let mut sys = load_rom(name, 0x0000, 0x0400);
+    let trap = run_to_trap(&mut sys, name);
+    assert_eq!(trap, FUNCTIONAL_SUCCESS, "functional test failed in test ${:02X}, trapped at ${:04X}",
+               peek(&sys, FUNCTIONAL_TEST_CASE), trap);
+}
+
+#[test]
+#[ignore = "needs 6502_decimal_test.bin in test-roms/klaus"]
+fn klaus_decimal_test() {
+    let name = "6502_decimal_test.bin";
+    let mut sys = load_rom(name, DECIMAL_START, DECIMAL_START);
+    let trap = run_to_trap(&mut sys, name);
+    assert_eq!(peek(&sys, DECIMAL_ERROR), 0, "decimal test failed, trapped at ${:04X}", trap);
+}
@@ -1,19 +1,65 @@
 
-pub mod datastructures;
-use datastructures::word;
-use datastructures::doubleword;
+pub(crate) mod datastructures;
+pub(crate) mod opcodes;
+mod bus;
+mod flat;
+mod addressing;
+mod control;
+mod cycle;
+mod debugger;
+mod error;
+mod savestate;
+mod trace;
+#[cfg(test)]
+mod klaus_tests;
+#[cfg(test)]
+mod processor_tests;
+#[cfg(test)]
+mod nestest_tests;
 use datastructures::ClAdd;
+use datastructures::ClSub;
 use datastructures::CARRY_BIT;
-use datastructures::InstructionStream;
+use opcodes::Access;
+use opcodes::AddressingMode;
+use opcodes::Mnemonic;
+use opcodes::Opcode;
+use addressing::AddressingContext;
+pub use datastructures::word;
+pub use datastructures::doubleword;
+pub use datastructures::InstructionStream;
+pub use bus::Bus;
+pub use bus::Device;
+pub use bus::DeviceId;
+use bus::Unemulated;
+pub use flat::FlatMemory;
+pub use control::Registers;
+pub use control::StepInfo;
+pub use control::StopReason;
+pub use debugger::Breakpoint;
+pub use debugger::BreakpointId;
+use debugger::Debugger;
+pub use savestate::SaveState;
+pub use savestate::SaveStateError;
+pub use savestate::StateReader;
+pub use savestate::StateWriter;
+pub use trace::Tracer;
+use cycle::CycleState;
+pub use cycle::ExecMode;
+pub use error::BusFault;
+pub use error::ExecError;
 
 const RAM_SIZE_BYTES: usize = 0x800;
 
 
 #[cfg(test)]
+#[allow(clippy::items_after_test_module)]
 mod famicom_tests {
 
 
     use super::*;
+    use super::control::StopReason;
+    use super::debugger::Breakpoint;
+    use super::savestate::SaveStateError;
 
     fn tests_init_system_resetted() -> System<FamicomMemory> {
         let mut ret = System::new_resetted();
@@ -46,13 +92,14 @@ mod famicom_tests {
         sys.p = word::from(5u8);
         sys.pc = doubleword::from(5u16);
 
-        sys.reset();
+        sys.reset().unwrap();
 
-        assert_eq!(sys.a, 0u8);
-        assert_eq!(sys.x, 0u8);
-        assert_eq!(sys.y, 0u8);
-        assert_eq!(sys.s, 0u8);
-        assert_eq!(sys.p, 0u8);
+        // A, X and Y are untouched, S goes down by 3, I is set and PC comes from the (empty) cartridge
+        assert_eq!(sys.a, 5u8);
+        assert_eq!(sys.x, 5u8);
+        assert_eq!(sys.y, 5u8);
+        assert_eq!(sys.s, 2u8);
+        assert_eq!(sys.p, 0x25u8);
         assert_eq!(sys.pc, 0u16);
     }
 
@@ -86,6 +133,8 @@ mod famicom_tests {
     }
 
 
+    /// To be removed once the Klaus tests run by default, until then they are opt-in (see
+    /// `klaus_tests`) and this is the smoke test of loads and stores
     #[test]
     fn cpu_can_load_and_store() {
         // Instruction sequence:
@@ -98,228 +147,1348 @@ mod famicom_tests {
 
         let mut sys = tests_init_system_resetted();
 
-        sys.run_programm_for(InstructionStream::from(program), 3);
+        sys.run_programm_for(InstructionStream::from(program), 3).unwrap();
 
         let addr = doubleword::from(0x01);
         assert_eq!(sys.load(addr), 0x01u8);
+    }
 
 
-        // Instruction sequence:
-        // AND #00
-        // ORA #01
-        // STA 01
+    #[test]
+    fn low_nibble_test() {
+        let val = 0x31u8;
 
-        let program: [u8; 6] = [0x29, 0x00, 0x09, 0x01, 0x85, 0x01];
-        let program: Vec<word> = program.iter().map(|x| word::from(*x)).collect();
+        assert_eq!(val, 0x31u8);
+        assert_eq!(low_nibble(word::from(val)), 0x1u8);
+        assert_eq!(high_nibble(word::from(val)), 0x3u8);
+    }
 
+    #[test]
+    fn opcode_table_is_consistent() {
+        assert_eq!(opcodes::OPCODES.iter().filter(|op| op.official).count(), 151);
+
+        for (idx, op) in opcodes::OPCODES.iter().enumerate() {
+            let encoded = opcodes::encode(CpuVariant::Nmos6502, op.mnemonic, op.mode).unwrap();
+            if op.official {
+                assert_eq!(encoded, idx as u8, "{:?} {:?}", op.mnemonic, op.mode);
+            }
+            assert_eq!(op.bytes, 1 + op.mode.operand_bytes());
+        }
+
+        // The bit of RMB, SMB, BBR and BBS is not part of what is encoded
+        for (idx, op) in opcodes::OPCODES_65C02.iter().enumerate().filter(|(_, op)| op.official) {
+            let bit = match op.mnemonic {
+                Mnemonic::RMB | Mnemonic::SMB | Mnemonic::BBR | Mnemonic::BBS => idx as u8 & 0x70,
+                _ => 0,
+            };
+            let encoded = opcodes::encode(CpuVariant::Cmos65C02, op.mnemonic, op.mode).unwrap();
+            assert_eq!(encoded.native_value() | bit, idx as u8, "{:?} {:?}", op.mnemonic, op.mode);
+        }
+        assert!(opcodes::encode(CpuVariant::Nmos6502, Mnemonic::STZ, AddressingMode::Zeropage).is_none());
+    }
+
+    /// Runs `count` instructions of `program`, loaded at the start of the cartridge ($8000)
+    fn tests_run_program(program: &[u8], count: usize) -> System<FamicomMemory> {
+        let program: Vec<word> = program.iter().map(|x| word::from(*x)).collect();
         let mut sys = tests_init_system_resetted();
+        sys.run_programm_for(InstructionStream::from(program), count).unwrap();
+        sys
+    }
 
-        sys.run_programm_for(InstructionStream::from(program), 3);
+    #[test]
+    fn cpu_adc_sbc_binary_flags() {
+        // CLC; LDA #$50; ADC #$50 -> $A0, signed overflow, no carry
+        let sys = tests_run_program(&[0x18, 0xA9, 0x50, 0x69, 0x50], 3);
+        assert_eq!(sys.a, 0xA0u8);
+        assert!(sys.V() && sys.N() && !sys.C() && !sys.Z());
+
+        // SEC; LDA #$FF; ADC #$00 -> $00 with carry
+        let sys = tests_run_program(&[0x38, 0xA9, 0xFF, 0x69, 0x00], 3);
+        assert_eq!(sys.a, 0u8);
+        assert!(sys.C() && sys.Z() && !sys.V());
 
-        let addr = doubleword::from(0x01);
-        assert_eq!(sys.load(addr), 0x01u8);
+        // SEC; LDA #$50; SBC #$B0 -> $A0, signed overflow, borrow
+        let sys = tests_run_program(&[0x38, 0xA9, 0x50, 0xE9, 0xB0], 3);
+        assert_eq!(sys.a, 0xA0u8);
+        assert!(sys.V() && sys.N() && !sys.C());
 
+        // CLC; LDA #$05; SBC #$01 -> $03 (borrow in)
+        let sys = tests_run_program(&[0x18, 0xA9, 0x05, 0xE9, 0x01], 3);
+        assert_eq!(sys.a, 0x03u8);
+        assert!(sys.C() && !sys.V());
+    }
 
+    #[test]
+    fn cpu_adc_sbc_decimal() {
+        // SED; CLC/SEC; LDA #a; ADC/SBC #b
+        let run = |carry: bool, a: u8, instr: u8, b: u8| {
+            let sys = tests_run_program(&[0xF8, if carry { 0x38 } else { 0x18 }, 0xA9, a, instr, b], 4);
+            (sys.a.native_value(), sys.C(), sys.Z(), sys.N(), sys.V())
+        };
 
+        assert_eq!(run(false, 0x12, 0x69, 0x34), (0x46, false, false, false, false));
+        assert_eq!(run(true, 0x58, 0x69, 0x46), (0x05, true, false, true, true));
+        assert_eq!(run(false, 0x81, 0x69, 0x92), (0x73, true, false, false, true));
+        // NMOS quirks: Z from the binary sum, N and V from the half-adjusted one
+        assert_eq!(run(false, 0x99, 0x69, 0x01), (0x00, true, false, true, false));
 
-        // Instruction sequence:
-        // AND #00
-        // ORA #01
-        // STA 01
+        assert_eq!(run(true, 0x46, 0xE9, 0x12), (0x34, true, false, false, false));
+        assert_eq!(run(false, 0x40, 0xE9, 0x13), (0x26, true, false, false, false));
+        assert_eq!(run(true, 0x00, 0xE9, 0x01), (0x99, false, false, true, false));
+        assert_eq!(run(true, 0x32, 0xE9, 0x02), (0x30, true, false, false, false));
+    }
 
-        let program: [u8; 6] = [0x29, 0x00, 0x09, 0x01, 0x85, 0x01];
-        let program: Vec<word> = program.iter().map(|x| word::from(*x)).collect();
+    #[test]
+    fn cpu_2a03_ignores_decimal_mode() {
+        // SED; CLC; LDA #$09; ADC #$01; SEC; SBC #$01
+        let program: Vec<word> = [0xF8u8, 0x18, 0xA9, 0x09, 0x69, 0x01, 0x38, 0xE9, 0x01].iter().map(|x| word::from(*x)).collect();
+        let mut sys: System<FamicomMemory> = System::with_variant(CpuVariant::Ricoh2A03);
+        sys.pc = doubleword::from(0x8000u16);
+        sys.mem.push_program(InstructionStream::from(program));
+        for _ in 0..4 {
+            sys.step().unwrap();
+        }
+        assert_eq!(sys.a, 0x0Au8);
+        assert!(sys.D());
+        sys.step().unwrap();
+        sys.step().unwrap();
+        assert_eq!(sys.a, 0x09u8);
+    }
 
+    #[test]
+    fn cpu_undocumented_combined_ops() {
+        // LDA #$F0; STA $10; LAX $10; SAX $11 ($F0 & $F0)
+        let mut sys = tests_run_program(&[0xA9, 0xF0, 0x85, 0x10, 0xA7, 0x10, 0x87, 0x11], 4);
+        assert_eq!(sys.a, 0xF0u8);
+        assert_eq!(sys.x, 0xF0u8);
+        assert_eq!(sys.load(doubleword::from(0x11u16)), 0xF0u8);
+
+        // LDA #$40; STA $10; LDA #$3F; DCP $10 -> $3F in memory, equal so Z and C
+        let mut sys = tests_run_program(&[0xA9, 0x40, 0x85, 0x10, 0xA9, 0x3F, 0xC7, 0x10], 4);
+        assert_eq!(sys.load(doubleword::from(0x10u16)), 0x3Fu8);
+        assert!(sys.Z() && sys.C());
+
+        // LDA #$0F; STA $10; SEC; LDA #$20; ISC $10 -> $10 in memory, A = $20 - $10
+        let mut sys = tests_run_program(&[0xA9, 0x0F, 0x85, 0x10, 0x38, 0xA9, 0x20, 0xE7, 0x10], 5);
+        assert_eq!(sys.load(doubleword::from(0x10u16)), 0x10u8);
+        assert_eq!(sys.a, 0x10u8);
+
+        // LDA #$81; STA $10; LDA #$01; SLO $10 -> $02 in memory with C, A = $03
+        let mut sys = tests_run_program(&[0xA9, 0x81, 0x85, 0x10, 0xA9, 0x01, 0x07, 0x10], 4);
+        assert_eq!(sys.load(doubleword::from(0x10u16)), 0x02u8);
+        assert_eq!(sys.a, 0x03u8);
+        assert!(sys.C());
+
+        // LDA #$81; STA $10; SEC; LDA #$FF; RLA $10 -> $03 in memory with C, A = $03
+        let mut sys = tests_run_program(&[0xA9, 0x81, 0x85, 0x10, 0x38, 0xA9, 0xFF, 0x27, 0x10], 5);
+        assert_eq!(sys.load(doubleword::from(0x10u16)), 0x03u8);
+        assert_eq!(sys.a, 0x03u8);
+
+        // LDA #$03; STA $10; LDA #$FF; SRE $10 -> $01 in memory, A = $FE
+        let mut sys = tests_run_program(&[0xA9, 0x03, 0x85, 0x10, 0xA9, 0xFF, 0x47, 0x10], 4);
+        assert_eq!(sys.load(doubleword::from(0x10u16)), 0x01u8);
+        assert_eq!(sys.a, 0xFEu8);
+
+        // LDA #$03; STA $10; CLC; LDA #$10; RRA $10 -> $01 in memory with C, A = $10 + $01 + 1
+        let mut sys = tests_run_program(&[0xA9, 0x03, 0x85, 0x10, 0x18, 0xA9, 0x10, 0x67, 0x10], 5);
+        assert_eq!(sys.load(doubleword::from(0x10u16)), 0x01u8);
+        assert_eq!(sys.a, 0x12u8);
+    }
+
+    #[test]
+    fn cpu_undocumented_immediate_ops() {
+        // LDA #$F1; ANC #$80 -> $80, C copied from N
+        let sys = tests_run_program(&[0xA9, 0xF1, 0x0B, 0x80], 2);
+        assert_eq!(sys.a, 0x80u8);
+        assert!(sys.C() && sys.N());
+
+        // LDA #$FF; ALR #$03 -> $01 with C
+        let sys = tests_run_program(&[0xA9, 0xFF, 0x4B, 0x03], 2);
+        assert_eq!(sys.a, 0x01u8);
+        assert!(sys.C());
+
+        // SEC; LDA #$FF; ARR #$C0 -> $E0, C from bit 6, V from bit 6 ^ bit 5
+        let sys = tests_run_program(&[0x38, 0xA9, 0xFF, 0x6B, 0xC0], 3);
+        assert_eq!(sys.a, 0xE0u8);
+        assert!(sys.C() && !sys.V() && sys.N());
+        // CLC; LDA #$FF; ARR #$40 -> $20, V set
+        let sys = tests_run_program(&[0x18, 0xA9, 0xFF, 0x6B, 0x40], 3);
+        assert_eq!(sys.a, 0x20u8);
+        assert!(!sys.C() && sys.V());
+
+        // LDA #$0F; LDX #$FC; SBX #$02 -> X = $0C - $02, no borrow
+        let sys = tests_run_program(&[0xA9, 0x0F, 0xA2, 0xFC, 0xCB, 0x02], 3);
+        assert_eq!(sys.x, 0x0Au8);
+        assert!(sys.C() && !sys.Z());
+
+        // SEC; LDA #$10; SBC #$01 with the undocumented $EB
+        let sys = tests_run_program(&[0x38, 0xA9, 0x10, 0xEB, 0x01], 3);
+        assert_eq!(sys.a, 0x0Fu8);
+    }
+
+    #[test]
+    fn cpu_undocumented_nops_skip_their_operands() {
+        // NOP #$12; NOP $12; NOP $12,X; NOP $1234; LDX #$FF; NOP $12F0,X (page cross); NOP (implied); LDA #$01
+        let program = [0x80, 0x12, 0x04, 0x12, 0x14, 0x12, 0x0C, 0x34, 0x12, 0xA2, 0xFF, 0x1C, 0xF0, 0x12, 0x1A, 0xA9, 0x01];
+        let sys = tests_run_program(&program, 8);
+        assert_eq!(sys.a, 0x01u8);
+        assert_eq!(sys.pc, 0x8011u16);
+        assert_eq!(sys.cycles, 2 + 3 + 4 + 4 + 2 + 5 + 2 + 2);
+    }
+
+    #[test]
+    fn cpu_unstable_stores() {
+        // LDX #$FF; LDY #$01; SHX $02FF,Y: page crossed, $FF & $03 is stored at $0300 & $03FF -> $0300
+        let mut sys = tests_run_program(&[0xA2, 0xFF, 0xA0, 0x01, 0x9E, 0xFF, 0x02], 3);
+        assert_eq!(sys.load(doubleword::from(0x0300u16)), 0x03u8);
+
+        // LDX #$FF; LDY #$01; SHX $0200,Y: no page cross, $FF & $03 at $0201
+        let mut sys = tests_run_program(&[0xA2, 0xFF, 0xA0, 0x01, 0x9E, 0x00, 0x02], 3);
+        assert_eq!(sys.load(doubleword::from(0x0201u16)), 0x03u8);
+    }
+
+    #[test]
+    fn cpu_jam_policies() {
+        // Execute: the CPU is stuck, but time goes on
+        let mut sys = tests_run_program(&[0x02, 0xEA], 3);
+        assert!(sys.jammed);
+        assert_eq!(sys.pc, 0x8001u16);
+        assert_eq!(sys.cycles, 4);
+
+        // HaltOnJam: time stops
+        let mut halted = tests_init_system_resetted();
+        halted.set_undocumented_opcodes(UndocumentedOpcodes::HaltOnJam);
+        halted.mem.push_program(InstructionStream::from(vec![word::from(0x02u8)]));
+        assert_eq!(halted.run(), Ok(StopReason::Jammed { pc: 0x8000 }));
+        assert_eq!(halted.step(), Err(ExecError::Halted { pc: 0x8001 }));
+        assert_eq!(halted.cycles, 2);
+
+        // Reset gets the CPU going again
+        sys.reset().unwrap();
+        assert!(!sys.jammed);
+        assert_eq!(sys.step().map(|info| info.cycles), Ok(7)); // BRK at the reset vector of the empty cartridge
+    }
+
+    #[test]
+    fn cpu_trapped_undocumented_opcode() {
+        for &mode in &[ExecMode::Instruction, ExecMode::Cycle] {
+            let mut sys = tests_init_system_resetted();
+            sys.set_exec_mode(mode).unwrap();
+            sys.set_undocumented_opcodes(UndocumentedOpcodes::Trap);
+            sys.mem.push_program(InstructionStream::from(vec![word::from(0xA7u8), word::from(0x10u8)]));
+            let cycles = sys.cycles;
+            assert_eq!(sys.step(), Err(ExecError::IllegalOpcode { pc: 0x8000, opcode: 0xA7 }));
+            assert_eq!(sys.pc, 0x8000u16);
+            assert_eq!(sys.cycles, cycles);
+
+            // Once the policy changes, the opcode executes
+            sys.set_undocumented_opcodes(UndocumentedOpcodes::Execute);
+            assert_eq!(sys.step().map(|info| info.cycles), Ok(3));
+        }
+    }
+
+    #[test]
+    fn cpu_no_speculative_reads() {
+        // STA $0200; JMP $0300 at $8003; JSR $0400 at $0300; STX $2007 at $0400
+        let mut sys = tests_trace_program(&[0x8D, 0x00, 0x02, 0x4C, 0x00, 0x03], 0x00, ExecMode::Instruction);
+        sys.mem.data[0x0300..0x0303].copy_from_slice(&[word::from(0x20u8), word::zero(), word::from(0x04u8)]);
+        sys.mem.data[0x0400..0x0403].copy_from_slice(&[word::from(0x8Eu8), word::from(0x07u8), word::from(0x20u8)]);
+        for _ in 0..4 {
+            sys.step().unwrap();
+        }
+        for &target in &[0x0200u16, 0x2007] {
+            assert!(!sys.mem.log.iter().any(|&(addr, _, write)| addr == target && !write), "${:04X} was read", target);
+        }
+        // JMP and JSR do not read the code they jump to before fetching it
+        let reads_of = |target: u16| sys.mem.log.iter().filter(|&&(addr, _, write)| addr == target && !write).count();
+        assert_eq!(reads_of(0x0300), 1);
+        assert_eq!(reads_of(0x0400), 1);
+
+        // Writing to a PPU register only stores to it, so there is nothing to report
         let mut sys = tests_init_system_resetted();
+        sys.mem.push_program(InstructionStream::from(vec![word::from(0x8Du8), word::from(0x07u8), word::from(0x20u8)]));
+        assert_eq!(sys.step().map(|info| info.cycles), Ok(4));
+    }
 
-        sys.run_programm_for(InstructionStream::from(program), 3);
+    #[test]
+    fn memory_peek() {
+        let mut sys = tests_trace_program(&[0xEA], 0x00, ExecMode::Instruction);
+        sys.mem.log.clear();
+        assert_eq!(sys.mem.peek(doubleword::from(0x8000u16)).unwrap(), 0xEAu8);
+        assert!(sys.mem.log.is_empty());
 
-        let addr = doubleword::from(0x01);
-        assert_eq!(sys.load(addr), 0x01u8);
+        let mut mem = FamicomMemory::new_resetted();
+        mem.store(doubleword::from(0x0012u16), word::from(0x34u8)).unwrap();
+        assert_eq!(mem.peek(doubleword::from(0x0812u16)).unwrap(), 0x34u8); // Mirrored RAM
+        assert!(mem.peek(doubleword::from(0x2002u16)).is_none());
+        assert!(mem.peek(doubleword::from(0x5000u16)).is_none());
+    }
+
+    #[test]
+    fn step_info() {
+        for &mode in &[ExecMode::Instruction, ExecMode::Cycle] {
+            // LDA #$01; STA $0200
+            let mut sys = tests_trace_program(&[0xA9, 0x01, 0x8D, 0x00, 0x02], 0x00, mode);
+            assert_eq!(sys.step(), Ok(StepInfo { pc: 0x8000, opcode: Some(0xA9), cycles: 2 }));
+            assert_eq!(sys.step(), Ok(StepInfo { pc: 0x8002, opcode: Some(0x8D), cycles: 4 }));
+
+            // Interrupt sequences execute no opcode
+            sys.mem.irq = true;
+            assert_eq!(sys.step(), Ok(StepInfo { pc: 0x8005, opcode: None, cycles: 7 }));
+        }
+    }
+
+    #[test]
+    fn run_budgets_and_conditions() {
+        for &mode in &[ExecMode::Instruction, ExecMode::Cycle] {
+            // loop: INX; JMP loop
+            let mut sys = tests_trace_program(&[0xE8, 0x4C, 0x00, 0x80], 0x00, mode);
+            assert_eq!(sys.run_for_instructions(4), Ok(StopReason::InstructionBudget));
+            assert_eq!(sys.x, 2u8);
+            assert_eq!(sys.cycles, 10);
+
+            // 13 cycles end in the middle of the 3rd JMP, which is completed
+            assert_eq!(sys.run_for_cycles(13), Ok(StopReason::CycleBudget));
+            assert_eq!(sys.cycles, 25);
+            assert_eq!(sys.x, 5u8);
+
+            assert_eq!(sys.run_until(|sys, _| sys.x == 0x10u8), Ok(StopReason::Condition));
+            assert_eq!(sys.x, 0x10u8);
+            assert_eq!(sys.pc, 0x8001u16);
+        }
+    }
 
+    #[test]
+    fn run_breakpoints() {
+        for &mode in &[ExecMode::Instruction, ExecMode::Cycle] {
+            // loop: INX; JMP loop
+            let mut sys = tests_trace_program(&[0xE8, 0x4C, 0x00, 0x80], 0x00, mode);
+            let id = sys.add_breakpoint(Breakpoint::Execute { address: 0x8001, bank: None });
+            assert_eq!(sys.run(), Ok(StopReason::Breakpoint { id, pc: 0x8001 }));
+            assert_eq!(sys.x, 1u8);
+
+            // Resuming from a breakpoint executes its instruction
+            assert_eq!(sys.run(), Ok(StopReason::Breakpoint { id, pc: 0x8001 }));
+            assert_eq!(sys.x, 2u8);
+
+            assert_eq!(sys.remove_breakpoint(id), Some(Breakpoint::Execute { address: 0x8001, bank: None }));
+            assert_eq!(sys.remove_breakpoint(id), None);
+            assert_eq!(sys.run_for_instructions(6), Ok(StopReason::InstructionBudget));
+            assert_eq!(sys.x, 5u8);
+        }
+    }
 
+    #[test]
+    fn debugger_breakpoints() {
+        for &mode in &[ExecMode::Instruction, ExecMode::Cycle] {
+            // loop: INX; STX $10; LDA $10; JMP loop
+            let mut sys = tests_trace_program(&[0xE8, 0x86, 0x10, 0xA5, 0x10, 0x4C, 0x00, 0x80], 0x00, mode);
+            let banked = sys.add_breakpoint(Breakpoint::Execute { address: 0x8003, bank: Some(1) });
+            let write = sys.add_breakpoint(Breakpoint::Write { start: 0x0010, end: 0x0011 });
+            let read = sys.add_breakpoint(Breakpoint::Read { start: 0x000F, end: 0x0010 });
+            assert_eq!(sys.breakpoints().map(|(id, _)| id).collect::<Vec<_>>(), vec![banked, write, read]);
+
+            // Watchpoints stop after the instruction, the bank of an unbanked memory never matches
+            assert_eq!(sys.run(), Ok(StopReason::Watchpoint { id: write, pc: 0x8001, address: 0x0010, access: MemoryAccessType::Store }));
+            assert_eq!(sys.pc, 0x8003u16);
+            assert_eq!(sys.run(), Ok(StopReason::Watchpoint { id: read, pc: 0x8003, address: 0x0010, access: MemoryAccessType::Load }));
+            assert_eq!(sys.a, 1u8);
+            sys.remove_breakpoint(write);
+            sys.remove_breakpoint(read);
+
+            let jmp = sys.add_breakpoint(Breakpoint::Opcode(0x4C));
+            assert_eq!(sys.run(), Ok(StopReason::Breakpoint { id: jmp, pc: 0x8005 }));
+            sys.remove_breakpoint(jmp);
+
+            // CLI; NOP with IRQ asserted, taken after the NOP
+            let mut sys = tests_trace_program(&[0x58, 0xEA], 0x00, mode);
+            sys.p = sys.p | I_BIT;
+            sys.mem.irq = true;
+            let interrupt = sys.add_breakpoint(Breakpoint::Interrupt);
+            assert_eq!(sys.run(), Ok(StopReason::Breakpoint { id: interrupt, pc: 0x8002 }));
+            assert_eq!(sys.step().map(|info| info.opcode), Ok(None));
+        }
+    }
 
-        // Instruction sequence:
-        // AND #00
-        // ORA #01
-        // STA 01
+    #[test]
+    fn debugger_step_over_and_out() {
+        for &mode in &[ExecMode::Instruction, ExecMode::Cycle] {
+            // JSR sub; INY; sub: JSR leaf; INX; RTS; leaf: INX; RTS
+            let mut sys = tests_trace_program(&[0x20, 0x04, 0x80, 0xC8, 0x20, 0x09, 0x80, 0xE8, 0x60, 0xE8, 0x60], 0x00, mode);
+            assert_eq!(sys.step_over(), Ok(StopReason::StepComplete));
+            assert_eq!(sys.pc, 0x8003u16);
+            assert_eq!(sys.x, 2u8);
+            assert_eq!(sys.step_over(), Ok(StopReason::StepComplete));
+            assert_eq!(sys.y, 1u8);
+
+            // Out of leaf then out of sub, from the first instruction of leaf
+            let mut sys = tests_trace_program(&[0x20, 0x04, 0x80, 0xC8, 0x20, 0x09, 0x80, 0xE8, 0x60, 0xE8, 0x60], 0x00, mode);
+            sys.run_for_instructions(2).unwrap();
+            assert_eq!(sys.pc, 0x8009u16);
+            assert_eq!(sys.step_out(), Ok(StopReason::StepComplete));
+            assert_eq!(sys.pc, 0x8007u16);
+            assert_eq!(sys.step_out(), Ok(StopReason::StepComplete));
+            assert_eq!(sys.pc, 0x8003u16);
+            assert_eq!(sys.x, 2u8);
+
+            // Breakpoints in the subroutine stop a step over
+            let mut sys = tests_trace_program(&[0x20, 0x04, 0x80, 0xC8, 0x20, 0x09, 0x80, 0xE8, 0x60, 0xE8, 0x60], 0x00, mode);
+            let id = sys.add_breakpoint(Breakpoint::Execute { address: 0x8009, bank: None });
+            assert_eq!(sys.step_over(), Ok(StopReason::Breakpoint { id, pc: 0x8009 }));
+
+            // JSR rec; INY; rec: INX; CPX #$03; BEQ done; JSR rec; done: RTS
+            let recursive = [0x20, 0x04, 0x80, 0xC8, 0xE8, 0xE0, 0x03, 0xF0, 0x03, 0x20, 0x04, 0x80, 0x60];
+            let mut sys = tests_trace_program(&recursive, 0x00, mode);
+            assert_eq!(sys.step_over(), Ok(StopReason::StepComplete));
+            assert_eq!(sys.pc, 0x8003u16);
+            assert_eq!(sys.x, 3u8);
+
+            // Over the recursive call of the first level: deeper returns to $800C are run through
+            let mut sys = tests_trace_program(&recursive, 0x00, mode);
+            sys.run_for_instructions(4).unwrap();
+            assert_eq!(sys.pc, 0x8009u16);
+            let s = sys.s.native_value();
+            assert_eq!(sys.step_over(), Ok(StopReason::StepComplete));
+            assert_eq!(sys.pc, 0x800Cu16);
+            assert_eq!(sys.s, s);
+            assert_eq!(sys.x, 3u8);
+
+            // JSR sub; INY; JAM; sub: INX; RTS, with the return address pushed across $0100
+            let mut sys = tests_trace_program(&[0x20, 0x05, 0x80, 0xC8, 0x02, 0xE8, 0x60], 0x00, mode);
+            sys.s = word::from(0x01u8);
+            sys.step().unwrap();
+            assert_eq!(sys.s, 0xFFu8);
+            assert_eq!(sys.step_out(), Ok(StopReason::StepComplete));
+            assert_eq!(sys.pc, 0x8003u16);
+            assert_eq!(sys.s, 0x01u8);
+        }
+    }
 
-        let program: [u8; 6] = [0x29, 0x00, 0x09, 0x01, 0x85, 0x01];
+    #[test]
+    fn run_stop_reasons() {
+        for &mode in &[ExecMode::Instruction, ExecMode::Cycle] {
+            // INX; BNE *
+            let mut sys = tests_trace_program(&[0xE8, 0xD0, 0xFE], 0x00, mode);
+            assert_eq!(sys.run_for_instructions(10), Ok(StopReason::InstructionBudget));
+            sys.set_stop_on_trap(true);
+            assert_eq!(sys.run(), Ok(StopReason::Trap { pc: 0x8001 }));
+
+            // INX; JAM
+            let mut sys = tests_trace_program(&[0xE8, 0x02], 0x00, mode);
+            assert_eq!(sys.run(), Ok(StopReason::Jammed { pc: 0x8001 }));
+
+            // INX; LAX $10 with undocumented opcodes trapped
+            let mut sys = tests_trace_program(&[0xE8, 0xA7, 0x10], 0x00, mode);
+            sys.set_undocumented_opcodes(UndocumentedOpcodes::Trap);
+            assert_eq!(sys.run(), Ok(StopReason::IllegalOpcode { pc: 0x8001, opcode: 0xA7 }));
+            assert_eq!(sys.x, 1u8);
+        }
+    }
+
+    #[test]
+    fn save_state_round_trip() {
+        for &mode in &[ExecMode::Instruction, ExecMode::Cycle] {
+            // loop: INX; STX $10,X; ADC $10; JMP loop
+            let mut sys = tests_init_system_resetted();
+            sys.mem.push_program(InstructionStream::from(
+                [0xE8, 0x96, 0x10, 0x65, 0x10, 0x4C, 0x00, 0x80].iter().map(|x| word::from(*x as u8)).collect::<Vec<word>>()));
+            sys.set_exec_mode(mode).unwrap();
+            sys.run_for_instructions(50).unwrap();
+            if mode == ExecMode::Cycle {
+                sys.tick().unwrap(); // Mid-instruction
+            }
+            let state = sys.save_state();
+
+            let mut restored: System<FamicomMemory> = System::with_variant(CpuVariant::Cmos65C02);
+            restored.load_state(&state).unwrap();
+            assert_eq!(restored.save_state(), state);
+
+            sys.run_for_instructions(1000).unwrap();
+            restored.run_for_instructions(1000).unwrap();
+            assert_eq!(restored.save_state(), sys.save_state());
+            assert_eq!(restored.cycles, sys.cycles);
+            assert_eq!(restored.mem.peek(doubleword::from(0x0080u16)).unwrap().native_value(),
+                       sys.mem.peek(doubleword::from(0x0080u16)).unwrap().native_value());
+        }
+    }
+
+    #[test]
+    fn save_state_errors() {
+        let mut sys = tests_run_program(&[0xA9, 0x12, 0x85, 0x10], 2);
+        let state = sys.save_state();
+        let id = sys.add_breakpoint(Breakpoint::Execute { address: 0x8000, bank: None });
+        sys.run_for_instructions(1).unwrap();
+        let before = sys.save_state();
+
+        let mut bad_magic = state.clone();
+        bad_magic[0] ^= 0xFF;
+        assert_eq!(sys.load_state(&bad_magic), Err(SaveStateError::BadMagic));
+        assert_eq!(sys.load_state(&state[..4]), Err(SaveStateError::BadMagic));
+        let mut bad_version = state.clone();
+        bad_version[8] = 0x42;
+        assert_eq!(sys.load_state(&bad_version), Err(SaveStateError::UnsupportedVersion(0x42)));
+        assert_eq!(sys.load_state(&state[..state.len() - 1]), Err(SaveStateError::Truncated));
+        let mut trailing = state.clone();
+        trailing.push(0);
+        assert_eq!(sys.load_state(&trailing), Err(SaveStateError::TrailingData));
+        let mut bad_variant = state.clone();
+        bad_variant[10] = 9;
+        assert_eq!(sys.load_state(&bad_variant), Err(SaveStateError::InvalidValue("CPU variant")));
+        assert_eq!(sys.save_state(), before);
+
+        // Breakpoints survive a restore
+        sys.load_state(&state).unwrap();
+        assert_eq!(sys.a, 0x12u8);
+        assert!(sys.remove_breakpoint(id).is_some());
+    }
+
+    #[test]
+    fn trace_lines_match_nestest() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        // The first instructions of nestest: JMP $C5F5; LDX #$00; STX $00
+        let mut program = vec![0u8; 0x8000];
+        program[0x4000..0x4003].copy_from_slice(&[0x4C, 0xF5, 0xC5]);
+        program[0x45F5..0x45F9].copy_from_slice(&[0xA2, 0x00, 0x86, 0x00]);
+        let mut sys: System<FamicomMemory> = System::with_variant(CpuVariant::Ricoh2A03);
+        sys.mem.push_program(InstructionStream::from(program.iter().map(|x| word::from(*x)).collect::<Vec<word>>()));
+        sys.reset().unwrap();
+        sys.pc = doubleword::from(0xC000u16);
+
+        let lines = Rc::new(RefCell::new(Vec::new()));
+        let sink = lines.clone();
+        sys.set_tracer(Some(Box::new(move |line: &str| sink.borrow_mut().push(line.to_string()))));
+        sys.run_for_instructions(3).unwrap();
+        assert_eq!(*lines.borrow(), vec![
+            "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7",
+            "C5F5  A2 00     LDX #$00                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 30 CYC:10",
+            "C5F7  86 00     STX $00 = 00                    A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 36 CYC:12",
+        ]);
+    }
+
+    #[test]
+    fn trace_resolves_operands() {
+        // LDA ($80,X); LAX ($89),Y; JMP ($02FF); ISC $0300,X; ASL A
+        let mut sys = tests_trace_program(&[0xA1, 0x80, 0xB3, 0x89, 0x6C, 0xFF, 0x02, 0xFF, 0x00, 0x03, 0x0A], 0x02, ExecMode::Instruction);
+        sys.y = word::from(0x10u8);
+        for &(address, data) in &[(0x82u16, 0x00u8), (0x83, 0x02), (0x0200, 0x5A), (0x89, 0xF8), (0x8A, 0x02), (0x0308, 0x89),
+                                  (0x02FF, 0x34), (0x0300, 0x12), (0x0302, 0x77)] {
+            sys.mem.data[address as usize] = word::from(data);
+        }
+        let disassembly = |sys: &System<TracingMemory>| sys.trace_line()[15..48].trim_end().to_string();
+        assert_eq!(disassembly(&sys), " LDA ($80,X) @ 82 = 0200 = 5A");
+        sys.pc = doubleword::from(0x8002u16);
+        assert_eq!(disassembly(&sys), "*LAX ($89),Y = 02F8 @ 0308 = 89");
+        sys.pc = doubleword::from(0x8004u16);
+        assert_eq!(disassembly(&sys), " JMP ($02FF) = 5A34"); // NMOS page bug, the high byte comes from $0200
+        sys.pc = doubleword::from(0x8007u16);
+        assert_eq!(disassembly(&sys), "*ISB $0300,X @ 0302 = 77");
+        sys.pc = doubleword::from(0x800Au16);
+        assert_eq!(disassembly(&sys), " ASL A");
+
+        // Tracing only peeks at memory
+        sys.mem.log.clear();
+        sys.trace_line();
+        assert!(sys.mem.log.is_empty());
+    }
+
+    #[test]
+    fn cpu_bus_faults() {
+        for &mode in &[ExecMode::Instruction, ExecMode::Cycle] {
+            // STA $8000 writes to the cartridge ROM, ignored; STA $6000 unmapped, ignored too;
+            // LDA $5000 reads unmapped space, i.e. open bus; INX; STA $8000
+            let program = [0x8D, 0x00, 0x80, 0x8D, 0x00, 0x60, 0xAD, 0x00, 0x50, 0xE8, 0x8D, 0x00, 0x80];
+            let mut sys = tests_init_system_resetted();
+            sys.set_exec_mode(mode).unwrap();
+            sys.mem.push_program(InstructionStream::from(program.iter().map(|x| word::from(*x as u8)).collect::<Vec<word>>()));
+            sys.a = word::from(0x42u8);
+            assert_eq!(sys.step().map(|info| info.cycles), Ok(4));
+            assert_eq!(sys.peek(0x8000), Some(0x8D));
+            assert_eq!(sys.step().map(|info| info.cycles), Ok(4));
+            assert_eq!(sys.step().map(|info| info.cycles), Ok(4));
+            assert_eq!(sys.a, 0x50u8); // Last fetched: the high byte of the address
+            assert_eq!(sys.step().map(|info| info.cycles), Ok(2));
+            assert_eq!(sys.x, 1u8);
+
+            // Without ignoring them, stores nothing takes fault
+            sys.mem.bus_mut().set_ignore_stray_stores(false);
+            assert_eq!(sys.step(), Err(ExecError::BusFault(BusFault { address: 0x8000, access: MemoryAccessType::Store })));
+            assert_eq!(sys.pc, 0x800Du16);
+
+            // A faulting opcode fetch stops before the instruction, even when undocumented
+            // opcodes are trapped
+            sys.mem.bus_mut().set_open_bus(false);
+            sys.set_undocumented_opcodes(UndocumentedOpcodes::Trap);
+            sys.pc = doubleword::from(0x5000u16);
+            let cycles = sys.cycles;
+            assert_eq!(sys.step(), Err(ExecError::BusFault(BusFault { address: 0x5000, access: MemoryAccessType::Load })));
+            assert_eq!(sys.pc, 0x5000u16);
+            assert_eq!(sys.cycles, cycles);
+        }
+    }
+
+    fn tests_run_65c02(program: &[u8], count: usize) -> System<FamicomMemory> {
         let program: Vec<word> = program.iter().map(|x| word::from(*x)).collect();
+        let mut sys: System<FamicomMemory> = System::with_variant(CpuVariant::Cmos65C02);
+        sys.pc = doubleword::from(0x8000u16);
+        sys.run_programm_for(InstructionStream::from(program), count).unwrap();
+        sys
+    }
 
-        let mut sys = tests_init_system_resetted();
+    #[test]
+    fn cpu_65c02_new_instructions() {
+        // LDX #$12; LDY #$34; PHX; PHY; PLX; PLY -> registers swapped
+        let sys = tests_run_65c02(&[0xA2, 0x12, 0xA0, 0x34, 0xDA, 0x5A, 0xFA, 0x7A], 6);
+        assert_eq!(sys.x, 0x34u8);
+        assert_eq!(sys.y, 0x12u8);
+
+        // LDA #$FF; INC A; DEC A; BRA +2 (skips LDA #$01)
+        let sys = tests_run_65c02(&[0xA9, 0xFF, 0x1A, 0x3A, 0x80, 0x02, 0xA9, 0x01, 0xEA], 5);
+        assert_eq!(sys.a, 0xFFu8);
+        assert_eq!(sys.pc, 0x8009u16);
+        assert_eq!(sys.cycles, 2 + 2 + 2 + 3 + 2);
+
+        // LDA #$0F; STA $10; LDA #$3C; TSB $10 -> $3F, Z clear; TRB $10 -> $03; STZ $11
+        let mut sys = tests_run_65c02(&[0xA9, 0x0F, 0x85, 0x10, 0xA9, 0x3C, 0x04, 0x10, 0x14, 0x10, 0x64, 0x11], 4);
+        assert_eq!(sys.load(doubleword::from(0x10u16)), 0x3Fu8);
+        assert!(!sys.Z());
+        let mut sys = tests_run_65c02(&[0xA9, 0x0F, 0x85, 0x10, 0xA9, 0x3C, 0x04, 0x10, 0x14, 0x10, 0x64, 0x10], 6);
+        assert_eq!(sys.load(doubleword::from(0x10u16)), 0u8);
+        let mut sys = tests_run_65c02(&[0xA9, 0x0F, 0x85, 0x10, 0xA9, 0x3C, 0x04, 0x10, 0x14, 0x10], 5);
+        assert_eq!(sys.load(doubleword::from(0x10u16)), 0x03u8);
+
+        // LDA #$00; STA $20; LDA #$02; STA $21; LDA #$5A; STA ($20) -> $0200
+        let mut sys = tests_run_65c02(&[0xA9, 0x00, 0x85, 0x20, 0xA9, 0x02, 0x85, 0x21, 0xA9, 0x5A, 0x92, 0x20], 6);
+        assert_eq!(sys.load(doubleword::from(0x0200u16)), 0x5Au8);
+
+        // LDA #$40; CLV; BIT #$C0 only affects Z
+        let sys = tests_run_65c02(&[0xA9, 0x40, 0xB8, 0x89, 0xC0], 3);
+        assert!(!sys.Z() && !sys.N() && !sys.V());
+    }
 
-        sys.run_programm_for(InstructionStream::from(program), 3);
+    #[test]
+    fn cpu_65c02_bit_instructions() {
+        // SMB3 $10; RMB0 $10; BBS3 $10,+2; LDA #$01; BBR0 $10,+2; LDA #$02; NOP
+        let program = [0xB7, 0x10, 0x07, 0x10, 0xBF, 0x10, 0x02, 0xA9, 0x01, 0x0F, 0x10, 0x02, 0xA9, 0x02, 0xEA];
+        let mut sys = tests_run_65c02(&program, 4);
+        assert_eq!(sys.load(doubleword::from(0x10u16)), 0x08u8);
+        assert_eq!(sys.a, 0u8);
+        assert_eq!(sys.pc, 0x800Eu16);
+        assert_eq!(sys.cycles, 5 + 5 + 6 + 6);
+    }
 
-        let addr = doubleword::from(0x01);
-        assert_eq!(sys.load(addr), 0x01u8);
+    #[test]
+    fn cpu_65c02_jumps() {
+        // JMP ($80FF) reads its high byte from $8100 on the 65C02
+        let mut program = vec![0xEAu8; 0x200];
+        program[0..3].copy_from_slice(&[0x6C, 0xFF, 0x80]);
+        program[0xFF] = 0x34;
+        program[0x100] = 0x02;
+        let sys = tests_run_65c02(&program, 1);
+        assert_eq!(sys.pc, 0x0234u16);
+        assert_eq!(sys.cycles, 6);
+
+        // LDX #$02; JMP ($8010,X) -> target at $8012
+        program[0..5].copy_from_slice(&[0xA2, 0x02, 0x7C, 0x10, 0x80]);
+        program[0x12] = 0x78;
+        program[0x13] = 0x06;
+        let sys = tests_run_65c02(&program, 2);
+        assert_eq!(sys.pc, 0x0678u16);
     }
 
+    #[test]
+    fn cpu_65c02_decimal_mode() {
+        // SED; CLC; LDA #$99; ADC #$01 -> $00 with valid Z and N, one extra cycle
+        let sys = tests_run_65c02(&[0xF8, 0x18, 0xA9, 0x99, 0x69, 0x01], 4);
+        assert_eq!(sys.a, 0u8);
+        assert!(sys.Z() && !sys.N() && sys.C());
+        assert_eq!(sys.cycles, 2 + 2 + 2 + 3);
+
+        // SED; SEC; LDA #$00; SBC #$01 -> $99, N from the result
+        let sys = tests_run_65c02(&[0xF8, 0x38, 0xA9, 0x00, 0xE9, 0x01], 4);
+        assert_eq!(sys.a, 0x99u8);
+        assert!(sys.N() && !sys.Z() && !sys.C());
+
+        // SED; BRK clears D
+        let mut program = vec![0u8; 0x8000];
+        program[0] = 0xF8;
+        program[0x7FFE] = 0x10;
+        program[0x7FFF] = 0x80;
+        let sys = tests_run_65c02(&program, 2);
+        assert_eq!(sys.pc, 0x8010u16);
+        assert!(!sys.D());
+    }
 
     #[test]
-    fn low_nibble_test() {
-        let val = 0x31u8;
+    fn cpu_65c02_reserved_opcodes_are_nops() {
+        // NOP #imm ($02); 1 byte NOP ($03); 8 cycle NOP ($5C); NOP $1234 ($DC); LDA #$01
+        let sys = tests_run_65c02(&[0x02, 0xFF, 0x03, 0x5C, 0x34, 0x12, 0xDC, 0x34, 0x12, 0xA9, 0x01], 5);
+        assert_eq!(sys.a, 0x01u8);
+        assert_eq!(sys.pc, 0x800Bu16);
+        assert_eq!(sys.cycles, 2 + 1 + 8 + 4 + 2);
+    }
 
-        assert_eq!(val, 0x31u8);
-        assert_eq!(low_nibble(word::from(val)), 0x1u8);
-        assert_eq!(high_nibble(word::from(val)), 0x3u8);
+    #[test]
+    fn cpu_65c02_wai_and_stp() {
+        for &mode in &[ExecMode::Instruction, ExecMode::Cycle] {
+            // SEI; WAI; INX: a masked IRQ ends WAI without being serviced
+            let mut sys = tests_trace_variant(CpuVariant::Cmos65C02, &[0x78, 0xCB, 0xE8], 0x00, mode);
+            sys.step().unwrap();
+            assert_eq!(sys.step().map(|info| info.cycles), Ok(3));
+            assert_eq!(sys.step().map(|info| info.cycles), Ok(1));
+            assert_eq!(sys.x, 0u8);
+            sys.mem.irq = true;
+            sys.step().unwrap();
+            assert_eq!(sys.x, 1u8);
+            assert_eq!(sys.pc, 0x8003u16);
+
+            // STP: nothing happens until reset
+            let mut sys = tests_trace_variant(CpuVariant::Cmos65C02, &[0xDB, 0xE8], 0x00, mode);
+            sys.step().unwrap();
+            assert_eq!(sys.step(), Err(ExecError::Halted { pc: 0x8001 }));
+            assert_eq!(sys.cycles, 3);
+            assert_eq!(sys.x, 0u8);
+        }
     }
 
+    #[test]
+    fn cpu_compare_and_bit() {
+        // LDA #$40; CMP #$40; CPX #$01 (X = 0)
+        let sys = tests_run_program(&[0xA9, 0x40, 0xC9, 0x40], 2);
+        assert!(sys.C() && sys.Z() && !sys.N());
+        let sys = tests_run_program(&[0xA9, 0x40, 0xC9, 0x40, 0xE0, 0x01], 3);
+        assert!(!sys.C() && !sys.Z() && sys.N());
 
-}
+        // LDA #$C0; STA $10; LDA #$01; BIT $10
+        let sys = tests_run_program(&[0xA9, 0xC0, 0x85, 0x10, 0xA9, 0x01, 0x24, 0x10], 4);
+        assert!(sys.Z() && sys.N() && sys.V());
+        assert_eq!(sys.a, 0x01u8);
+    }
 
-struct Ram {
-    data: Box<[word; RAM_SIZE_BYTES]>,
-}
+    #[test]
+    fn cpu_branch_loop() {
+        // LDX #$05; LDY #$00; INY; DEX; BNE -4 (to INY); BEQ +0
+        let program = [0xA2, 0x05, 0xA0, 0x00, 0xC8, 0xCA, 0xD0, 0xFC, 0xF0, 0x00];
+        let sys = tests_run_program(&program, 2 + 5 * 3 + 1);
+        assert_eq!(sys.x, 0u8);
+        assert_eq!(sys.y, 5u8);
+        assert_eq!(sys.pc, 0x800Au16);
+    }
 
-impl Ram {
+    #[test]
+    fn cpu_jsr_rts() {
+        // $8000: JSR $8006; LDA #$02; BRK(unused)
+        // $8006: LDX #$07; RTS
+        let program = [0x20, 0x06, 0x80, 0xA9, 0x02, 0x00, 0xA2, 0x07, 0x60];
+        let sys = tests_run_program(&program, 4);
+        assert_eq!(sys.x, 0x07u8);
+        assert_eq!(sys.a, 0x02u8);
+        assert_eq!(sys.pc, 0x8005u16);
+    }
 
-    /// Create a new NES-size RAM instance, all filled with zeroes
-    fn new() -> Self {
-        Self {
-            data: Box::new([word::zero(); RAM_SIZE_BYTES]),
+    #[test]
+    fn cpu_stack_ops_and_transfers() {
+        // LDA #$80; PHA; LDA #$00; PLA; TAX; TXA; TAY; SEC; PHP; CLC; PLP
+        let program = [0xA9, 0x80, 0x48, 0xA9, 0x00, 0x68, 0xAA, 0x8A, 0xA8, 0x38, 0x08, 0x18, 0x28];
+        let sys = tests_run_program(&program, 11);
+        assert_eq!(sys.a, 0x80u8);
+        assert_eq!(sys.x, 0x80u8);
+        assert_eq!(sys.y, 0x80u8);
+        assert!(sys.C() && sys.N());
+        assert!(!sys.B());
+    }
+
+    #[test]
+    fn cpu_stack_is_on_page_one_and_wraps() {
+        // S starts at $00, so PHA wraps it to $FF and PLA wraps it back
+        // $8000: LDA #$AB; PHA; JSR $8009; PLA
+        // $8009: TSX; RTS
+        let program = [0xA9, 0xAB, 0x48, 0x20, 0x09, 0x80, 0x68, 0x00, 0x00, 0xBA, 0x60];
+        let mut sys = tests_run_program(&program, 4);
+        assert_eq!(sys.x, 0xFDu8);
+        assert_eq!(sys.load(doubleword::from(0x0100u16)), 0xABu8);
+        assert_eq!(sys.load(doubleword::from(0x01FFu16)), 0x80u8); // High byte of the return address first
+        assert_eq!(sys.load(doubleword::from(0x01FEu16)), 0x05u8);
+        assert_eq!(sys.load(doubleword::from(0x00FFu16)), 0u8); // Nothing written to the zero page
+
+        let sys = tests_run_program(&program, 6);
+        assert_eq!(sys.a, 0xABu8);
+        assert_eq!(sys.s, 0u8);
+        assert_eq!(sys.pc, 0x8007u16);
+    }
+
+    #[test]
+    fn cpu_shifts_and_rotates() {
+        // LDA #$81; ASL A -> $02, C; ROL A -> $05; LSR A -> $02, C; ROR A -> $81
+        let sys = tests_run_program(&[0xA9, 0x81, 0x0A], 2);
+        assert_eq!(sys.a, 0x02u8);
+        assert!(sys.C());
+        let sys = tests_run_program(&[0xA9, 0x81, 0x0A, 0x2A], 3);
+        assert_eq!(sys.a, 0x05u8);
+        assert!(!sys.C());
+        let sys = tests_run_program(&[0xA9, 0x81, 0x0A, 0x2A, 0x4A], 4);
+        assert_eq!(sys.a, 0x02u8);
+        assert!(sys.C());
+        let sys = tests_run_program(&[0xA9, 0x81, 0x0A, 0x2A, 0x4A, 0x6A], 5);
+        assert_eq!(sys.a, 0x81u8);
+        assert!(!sys.C() && sys.N());
+
+        // LDA #$40; STA $20; ASL $20; INC $20; DEC $20; DEC $20
+        let mut sys = tests_run_program(&[0xA9, 0x40, 0x85, 0x20, 0x06, 0x20, 0xE6, 0x20, 0xC6, 0x20, 0xC6, 0x20], 6);
+        assert_eq!(sys.load(doubleword::from(0x20u16)), 0x7Fu8);
+        assert!(!sys.N() && !sys.Z());
+    }
+
+    #[test]
+    fn cpu_indexed_and_indirect_addressing() {
+        // LDX #$04; LDA #$12; STA $FE,X (wraps to $02); LDY $02
+        let sys = tests_run_program(&[0xA2, 0x04, 0xA9, 0x12, 0x95, 0xFE, 0xA4, 0x02], 4);
+        assert_eq!(sys.y, 0x12u8);
+
+        // Pointer $0300 stored at $10/$11, then:
+        // LDX #$02; LDA #$AB; STA ($0E,X); LDY #$01; LDA #$CD; STA ($10),Y; LDX $0301; LDY $0300
+        let program = [
+            0xA9, 0x00, 0x85, 0x10, 0xA9, 0x03, 0x85, 0x11,
+            0xA2, 0x02, 0xA9, 0xAB, 0x81, 0x0E, 0xA0, 0x01, 0xA9, 0xCD, 0x91, 0x10,
+            0xAE, 0x01, 0x03, 0xAC, 0x00, 0x03,
+        ];
+        let sys = tests_run_program(&program, 12);
+        assert_eq!(sys.x, 0xCDu8);
+        assert_eq!(sys.y, 0xABu8);
+
+        // LDY #$03; LDX $01FE,Y (reads $0201); LDA #$05; STA $0201 is done first
+        let program = [0xA9, 0x05, 0x8D, 0x01, 0x02, 0xA0, 0x03, 0xBE, 0xFE, 0x01];
+        let sys = tests_run_program(&program, 4);
+        assert_eq!(sys.x, 0x05u8);
+    }
+
+    #[test]
+    fn cpu_counts_cycles() {
+        // LDA #$01 (2); STA $0200 (4); LDX #$FF (2); LDA $0201,X (4 + 1); STA $0201,X (5); NOP (2)
+        let program: [u8; 14] = [0xA9, 0x01, 0x8D, 0x00, 0x02, 0xA2, 0xFF, 0xBD, 0x01, 0x02, 0x9D, 0x01, 0x02, 0xEA];
+        let program: Vec<word> = program.iter().map(|x| word::from(*x)).collect();
+        let mut sys = tests_init_system_resetted();
+        sys.mem.push_program(InstructionStream::from(program));
+
+        let cycles: Vec<u8> = (0..6).map(|_| sys.step().unwrap().cycles).collect();
+        assert_eq!(cycles, vec![2, 4, 2, 5, 5, 2]);
+        assert_eq!(sys.cycles, 20);
+
+        // LDX #$00; LDA $0201,X does not cross a page
+        let sys = tests_run_program(&[0xA2, 0x00, 0xBD, 0x01, 0x02], 2);
+        assert_eq!(sys.cycles, 6);
+    }
+
+    #[test]
+    fn cpu_counts_branch_cycles() {
+        // Not taken (2), taken on the same page (3)
+        let sys = tests_run_program(&[0xA9, 0x01, 0xF0, 0x10], 2);
+        assert_eq!(sys.cycles, 4);
+        let sys = tests_run_program(&[0xA9, 0x01, 0xD0, 0x10], 2);
+        assert_eq!(sys.cycles, 5);
+
+        // Taken to another page (4): BNE at $80F0 jumps to $8112
+        let mut program = vec![0xEAu8; 0x200];
+        program[0] = 0x4C; // JMP $80F0
+        program[1] = 0xF0;
+        program[2] = 0x80;
+        program[0xF0] = 0xD0;
+        program[0xF1] = 0x20;
+        let sys = tests_run_program(&program, 2);
+        assert_eq!(sys.pc, 0x8112u16);
+        assert_eq!(sys.cycles, 3 + 4);
+    }
+
+    #[test]
+    fn cpu_jmp_indirect_page_bug() {
+        // Pointer at $02FF: low byte at $02FF, high byte read from $0200 (not $0300)
+        // LDA #$09; STA $02FF; LDA #$80; STA $0200; LDA #$FF; STA $0300; JMP ($02FF)
+        let program = [
+            0xA9, 0x09, 0x8D, 0xFF, 0x02, 0xA9, 0x80, 0x8D, 0x00, 0x02,
+            0xA9, 0xFF, 0x8D, 0x00, 0x03, 0x6C, 0xFF, 0x02,
+        ];
+        let sys = tests_run_program(&program, 7);
+        assert_eq!(sys.pc, 0x8009u16);
+    }
+
+    #[test]
+    fn cpu_brk_and_rti() {
+        // BRK at $8000 jumps through $FFFE to $8010, which does RTI
+        let mut program = vec![0u8; 0x8000];
+        program[0x0000] = 0x00; // BRK
+        program[0x0002] = 0xEA; // NOP
+        program[0x0010] = 0x40; // RTI
+        program[0x7FFE] = 0x10;
+        program[0x7FFF] = 0x80;
+
+        let sys = tests_run_program(&program, 1);
+        assert_eq!(sys.pc, 0x8010u16);
+        assert!(sys.I());
+
+        let sys = tests_run_program(&program, 2);
+        assert_eq!(sys.pc, 0x8002u16); // BRK skips its padding byte
+        assert!(!sys.I() && !sys.B());
+    }
+
+    /// Flat 64K memory recording every bus access, to check the cycle-stepped core
+    struct TracingMemory {
+        data: Vec<word>,
+        /// (address, data, is_write) of every access, in order
+        log: Vec<(u16, u8, bool)>,
+        nmi: bool,
+        irq: bool,
+    }
+
+    impl IO6502 for TracingMemory {
+        fn new_resetted() -> Self {
+            Self {
+                data: vec![word::zero(); 0x10000],
+                log: Vec::new(),
+                nmi: false,
+                irq: false,
+            }
+        }
+
+        fn reset(&mut self) {
+            self.data.iter_mut().for_each(|x| *x = word::zero());
+            self.log.clear();
+        }
+
+        fn push_program(&mut self, program: InstructionStream) {
+            for (idx, byte) in program.stream.iter().enumerate() {
+                self.data[0x8000 + idx] = *byte;
+            }
+        }
+
+        fn store(&mut self, address: doubleword, data: word) -> Result<(), BusFault> {
+            self.log.push((address.native_value(), data.native_value(), true));
+            self.data[address.as_addr()] = data;
+            Ok(())
+        }
+
+        fn load(&mut self, address: doubleword) -> Result<word, BusFault> {
+            let ret = self.data[address.as_addr()];
+            self.log.push((address.native_value(), ret.native_value(), false));
+            Ok(ret)
+        }
+
+        fn peek(&self, address: doubleword) -> Option<word> {
+            Some(self.data[address.as_addr()])
+        }
+
+        fn nmi_asserted(&self) -> bool {
+            self.nmi
+        }
+
+        fn irq_asserted(&self) -> bool {
+            self.irq
         }
     }
 
-    fn write(&mut self, address: doubleword, data: word) {
-        let address: u16 = address.native_value();
-        debug_assert!(address <= 0x07ff);
-        self.data[address as usize] = data;
+    fn tests_trace_program(program: &[u8], x: u8, mode: ExecMode) -> System<TracingMemory> {
+        tests_trace_variant(CpuVariant::Nmos6502, program, x, mode)
     }
 
-    fn read(&self, address: doubleword) -> word {
-        debug_assert!(address.native_value() <= 0x07ff);
-        self.data[address.as_addr()]
+    fn tests_trace_variant(variant: CpuVariant, program: &[u8], x: u8, mode: ExecMode) -> System<TracingMemory> {
+        let program: Vec<word> = program.iter().map(|x| word::from(*x)).collect();
+        let mut sys: System<TracingMemory> = System::with_variant(variant);
+        sys.mem.push_program(InstructionStream::from(program));
+        sys.pc = doubleword::from(0x8000u16);
+        sys.s = word::from(0x40u8);
+        sys.x = word::from(x);
+        sys.set_exec_mode(mode).unwrap();
+        sys
     }
 
-    fn reset(&mut self) {
-        self.data.iter_mut().for_each(|w| *w = word::zero())
+    #[test]
+    fn cycle_mode_matches_instruction_mode() {
+        // Arbitrary but deterministic memory contents, vectors and pointers included
+        let background: Vec<word> = (0..0x10000usize).map(|addr| word::from((addr * 7 + 3) as u8)).collect();
+        let variants = [(CpuVariant::Nmos6502, &opcodes::OPCODES), (CpuVariant::Cmos65C02, &opcodes::OPCODES_65C02)];
+        let all_opcodes = variants.iter().flat_map(|(variant, table)| table.iter().enumerate().map(move |(idx, op)| (*variant, idx, op)));
+        for (variant, idx, _) in all_opcodes.filter(|(_, _, op)| op.mnemonic != Mnemonic::JAM) {
+            for &(index, p) in &[(0x00u8, 0x00u8), (0x01, 0xFF), (0xF0, 0xC3)] {
+                let mut systems: Vec<System<TracingMemory>> = [ExecMode::Instruction, ExecMode::Cycle].iter().map(|&mode| {
+                    let mut sys = tests_trace_variant(variant, &[], index, mode);
+                    sys.mem.data = background.clone();
+                    sys.mem.push_program(InstructionStream::from(vec![word::from(idx as u8), word::from(0x34u8), word::from(0x12u8)]));
+                    sys.y = word::from(index.wrapping_mul(3));
+                    sys.a = word::from(0x5Au8);
+                    sys.p = word::from(p);
+                    sys
+                }).collect();
+
+                let cycles: Vec<u8> = systems.iter_mut().map(|sys| sys.step().unwrap().cycles).collect();
+                let (instr, cycle) = (&systems[0], &systems[1]);
+                let context = format!("{:?} opcode {:02X}, index {:02X}, p {:02X}", variant, idx, index, p);
+                assert_eq!(cycles[0], cycles[1], "{}", context);
+                assert_eq!(instr.cycles, cycle.cycles, "{}", context);
+                assert_eq!(cycle.mem.log.len(), cycles[1] as usize, "one bus access per cycle, {}", context);
+                let registers = |sys: &System<TracingMemory>| [sys.a, sys.x, sys.y, sys.s, sys.p].iter().map(|r| r.native_value()).collect::<Vec<u8>>();
+                assert_eq!(registers(instr), registers(cycle), "{}", context);
+                assert_eq!(instr.pc.native_value(), cycle.pc.native_value(), "{}", context);
+                // Both start from the same memory, so only written addresses can differ
+                let written = instr.mem.log.iter().chain(cycle.mem.log.iter()).filter(|access| access.2).map(|access| access.0 as usize);
+                for addr in written {
+                    assert_eq!(instr.mem.data[addr].native_value(), cycle.mem.data[addr].native_value(), "memory at {:04X}, {}", addr, context);
+                }
+            }
+        }
     }
-}
 
-struct Cartridge {
-    program: Box<[word; 0x8000]>,
-}
+    #[test]
+    fn cycle_mode_bus_accesses() {
+        // LDA $12F0,X without page cross: no dummy read
+        let mut sys = tests_trace_program(&[0xBD, 0xF0, 0x12], 0x01, ExecMode::Cycle);
+        sys.step().unwrap();
+        let addrs: Vec<u16> = sys.mem.log.iter().map(|x| x.0).collect();
+        assert_eq!(addrs, vec![0x8000, 0x8001, 0x8002, 0x12F1]);
+
+        // LDA $12F0,X with page cross: dummy read from the unfixed address
+        let mut sys = tests_trace_program(&[0xBD, 0xF0, 0x12], 0x20, ExecMode::Cycle);
+        sys.step().unwrap();
+        let addrs: Vec<u16> = sys.mem.log.iter().map(|x| x.0).collect();
+        assert_eq!(addrs, vec![0x8000, 0x8001, 0x8002, 0x1210, 0x1310]);
+
+        // STA $12F0,X always does the dummy read, even without page cross
+        let mut sys = tests_trace_program(&[0x9D, 0xF0, 0x12], 0x01, ExecMode::Cycle);
+        sys.a = word::from(0x42u8);
+        sys.step().unwrap();
+        assert_eq!(sys.mem.log[3..], [(0x12F1, 0, false), (0x12F1, 0x42, true)]);
+
+        // INC $12F0,X: dummy read, read, write of the old value, write of the new one
+        let mut sys = tests_trace_program(&[0xFE, 0xF0, 0x12], 0x20, ExecMode::Cycle);
+        sys.mem.data[0x1310] = word::from(0x41u8);
+        sys.step().unwrap();
+        assert_eq!(sys.mem.log[3..], [(0x1210, 0, false), (0x1310, 0x41, false), (0x1310, 0x41, true), (0x1310, 0x42, true)]);
+
+        // JSR $1234: dummy stack read, then PCH and PCL of the last operand byte
+        let mut sys = tests_trace_program(&[0x20, 0x34, 0x12], 0x00, ExecMode::Cycle);
+        sys.step().unwrap();
+        assert_eq!(sys.mem.log, vec![(0x8000, 0x20, false), (0x8001, 0x34, false), (0x0140, 0, false),
+            (0x0140, 0x80, true), (0x013F, 0x02, true), (0x8002, 0x12, false)]);
+        assert_eq!(sys.pc, 0x1234u16);
+    }
+
+    /// Program at $8000 with the NMI handler at $9000, the IRQ/BRK handler at $9100 and RESET at $8000
+    fn tests_interrupt_program(program: &[u8], mode: ExecMode) -> System<TracingMemory> {
+        let mut sys = tests_trace_program(program, 0x00, mode);
+        for (addr, val) in &[(0xFFFAusize, 0x00u8), (0xFFFB, 0x90), (0xFFFC, 0x00), (0xFFFD, 0x80), (0xFFFE, 0x00), (0xFFFF, 0x91)] {
+            sys.mem.data[*addr] = word::from(*val);
+        }
+        sys.mem.data[0x9000] = word::from(0x40u8); // RTI
+        sys.mem.data[0x9100] = word::from(0x40u8); // RTI
+        sys
+    }
 
-impl Cartridge {
-    pub fn new_zeroed() -> Self {
-        Self {
-            program: Box::new([word::zero(); 0x8000]),
+    #[test]
+    fn reset_loads_the_reset_vector() {
+        let mut sys = tests_interrupt_program(&[0xEA], ExecMode::Instruction);
+        sys.pc = doubleword::from(0x1234u16);
+        sys.s = word::zero();
+        sys.reset().unwrap();
+        assert_eq!(sys.pc, 0x8000u16);
+        assert_eq!(sys.s, 0xFDu8);
+        assert!(sys.I());
+        assert_eq!(sys.cycles, 7);
+        assert!(sys.mem.log.iter().all(|access| !access.2)); // Nothing is pushed
+    }
+
+    #[test]
+    fn irq_is_level_triggered_and_masked_by_i() {
+        for &mode in &[ExecMode::Instruction, ExecMode::Cycle] {
+            // CLI; NOP; NOP
+            let mut sys = tests_interrupt_program(&[0x58, 0xEA, 0xEA], mode);
+            sys.set_I();
+            sys.mem.irq = true;
+            assert_eq!(sys.step().map(|info| info.cycles), Ok(2)); // I is set, CLI runs
+            // CLI clears I after polling interrupts, the NOP after it runs first
+            assert_eq!(sys.step().map(|info| info.opcode), Ok(Some(0xEA)));
+            assert_eq!(sys.step().map(|info| info.cycles), Ok(7));
+            assert_eq!(sys.pc, 0x9100u16);
+            assert!(sys.I());
+            // Return address is the interrupted instruction, P is pushed with B clear
+            assert_eq!(sys.mem.data[0x0140], 0x80u8);
+            assert_eq!(sys.mem.data[0x013F], 0x02u8);
+            assert_eq!(sys.mem.data[0x013E], 0x20u8);
+
+            // RTI clears I before polling, so the still asserted IRQ is taken again at once
+            sys.step().unwrap();
+            assert_eq!(sys.pc, 0x8002u16);
+            assert_eq!(sys.step().map(|info| info.cycles), Ok(7));
+            assert_eq!(sys.pc, 0x9100u16);
+
+            sys.mem.irq = false;
+            sys.step().unwrap();
+            sys.step().unwrap();
+            assert_eq!(sys.pc, 0x8003u16);
+
+            // CLI; SEI; NOP: SEI still polls with I clear, the IRQ is taken before the NOP
+            let mut sys = tests_interrupt_program(&[0x58, 0x78, 0xEA], mode);
+            sys.set_I();
+            sys.mem.irq = true;
+            sys.run_for_instructions(2).unwrap();
+            assert_eq!(sys.step().map(|info| info.opcode), Ok(None));
+            assert_eq!(sys.mem.data[0x013F], 0x02u8);
+            assert_eq!(sys.mem.data[0x013E], 0x24u8); // Pushed with I set
+
+            // LDA #$00; PHA; PLP; NOP: PLP clearing I is late too
+            let mut sys = tests_interrupt_program(&[0xA9, 0x00, 0x48, 0x28, 0xEA], mode);
+            sys.set_I();
+            sys.mem.irq = true;
+            sys.run_for_instructions(3).unwrap();
+            assert!(!sys.I());
+            assert_eq!(sys.step().map(|info| info.opcode), Ok(Some(0xEA)));
+            assert_eq!(sys.step().map(|info| info.opcode), Ok(None));
         }
     }
 
-    pub fn push_program(&mut self, program: InstructionStream) {
-        let mut byte_idx = 0;
-        for byte in program.stream {
-            self.program[byte_idx] = byte;
-            byte_idx+=1;
+    #[test]
+    fn nmi_is_edge_triggered() {
+        for &mode in &[ExecMode::Instruction, ExecMode::Cycle] {
+            let mut sys = tests_interrupt_program(&[0xEA, 0xEA, 0xEA], mode);
+            sys.set_I(); // NMI can not be masked
+            sys.mem.nmi = true;
+            assert_eq!(sys.step().map(|info| info.cycles), Ok(7));
+            assert_eq!(sys.pc, 0x9000u16);
+            sys.step().unwrap();
+            assert_eq!(sys.pc, 0x8000u16);
+
+            // Still asserted: no new edge
+            sys.step().unwrap();
+            assert_eq!(sys.pc, 0x8001u16);
+
+            sys.mem.nmi = false;
+            sys.step().unwrap();
+            sys.mem.nmi = true;
+            sys.step().unwrap();
+            assert_eq!(sys.pc, 0x9000u16);
         }
     }
 
-    pub fn read(&self, address: doubleword) -> word {
-        self.program[address.as_addr() as usize]
+    #[test]
+    fn brk_pushes_b_and_can_be_hijacked_by_nmi() {
+        for &mode in &[ExecMode::Instruction, ExecMode::Cycle] {
+            let mut sys = tests_interrupt_program(&[0x00, 0xFF, 0xEA], mode);
+            sys.step().unwrap();
+            assert_eq!(sys.pc, 0x9100u16);
+            assert_eq!(sys.mem.data[0x013E], 0x30u8);
+        }
+
+        // NMI asserted while BRK pushes its return address: the NMI vector is used, B stays set
+        let mut sys = tests_interrupt_program(&[0x00, 0xFF, 0xEA], ExecMode::Cycle);
+        for _ in 0..3 {
+            sys.tick().unwrap();
+        }
+        sys.mem.nmi = true;
+        sys.set_exec_mode(ExecMode::Instruction).unwrap();
+        assert_eq!(sys.pc, 0x9000u16);
+        assert_eq!(sys.mem.data[0x013E], 0x30u8);
+        assert!(!sys.nmi_pending);
+
+        // Too late once the vector is being fetched: the NMI is only taken once BRK is done
+        let mut sys = tests_interrupt_program(&[0x00, 0xFF, 0xEA], ExecMode::Cycle);
+        for _ in 0..6 {
+            sys.tick().unwrap();
+        }
+        sys.mem.nmi = true;
+        sys.step().unwrap();
+        assert_eq!(sys.pc, 0x9100u16);
+        sys.step().unwrap();
+        assert_eq!(sys.pc, 0x9000u16);
+    }
+
+    #[test]
+    fn cycle_mode_can_stop_mid_instruction() {
+        // INC $0200 is 6 cycles; switching back to instruction mode finishes it first
+        let mut sys = tests_trace_program(&[0xEE, 0x00, 0x02, 0xE8], 0x00, ExecMode::Cycle);
+        for _ in 0..3 {
+            sys.tick().unwrap();
+        }
+        assert_eq!(sys.cycles, 3);
+        assert_eq!(sys.mem.data[0x0200], 0u8);
+
+        sys.set_exec_mode(ExecMode::Instruction).unwrap();
+        assert_eq!(sys.cycles, 6);
+        assert_eq!(sys.mem.data[0x0200], 1u8);
+        assert_eq!(sys.step().map(|info| info.cycles), Ok(2));
+        assert_eq!(sys.x, 1u8);
     }
 }
 
-enum MemoryAccessType {
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryAccessType {
     Store,
     Load,
 }
 
+#[cfg(test)]
 #[inline]
 fn low_nibble(byte: word) -> u8 {
     //no need to worry about endianness since it's a single byte
     byte.native_value() & 0x0f
 }
 
+#[cfg(test)]
 #[inline]
 fn high_nibble(byte: word) -> u8 {
     (byte.native_value() & 0xf0) >> 4
 }
 
 /// Generic representation of the IO part of a system connected to a 6502 CPU
-trait IO6502 {
+pub trait IO6502 {
     fn new_resetted() -> Self;
 
     fn reset(&mut self);
 
     fn push_program(&mut self, program: InstructionStream);
 
-    fn store(&mut self, address: doubleword, data: word);
+    fn store(&mut self, address: doubleword, data: word) -> Result<(), BusFault>;
 
-    fn load(&mut self, address: doubleword) -> word;
+    fn load(&mut self, address: doubleword) -> Result<word, BusFault>;
+
+    /// Reads memory without any side effect, for debuggers and tracers. None when the address
+    /// cannot be read that way, e.g. a register that changes state when it is read
+    fn peek(&self, address: doubleword) -> Option<word>;
+
+    /// Bank mapped at `address`, for memories with bank switching. Used by breakpoints
+    fn bank(&self, _address: doubleword) -> Option<u8> {
+        None
+    }
+
+    /// State of the NMI input, true when asserted. The CPU reacts when it goes from
+    /// deasserted to asserted
+    fn nmi_asserted(&self) -> bool {
+        false
+    }
+
+    /// State of the IRQ input, true when asserted. The CPU is interrupted for as long as it
+    /// stays asserted and the I flag is clear
+    fn irq_asserted(&self) -> bool {
+        false
+    }
 }
 
-struct FamicomMemory {
-    internal_ram: Ram,
-    cart: Cartridge,
+/// The Famicom CPU address space: 2K of RAM, PPU and APU registers, and a 32K cartridge ROM
+/// where programs are loaded. Peripherals can be added on the bus. Reads nothing answers
+/// return the last value on the bus, as on the real thing
+pub struct FamicomMemory {
+    bus: Bus,
 }
 
-impl IO6502 for FamicomMemory {
+/// Controller ports at $4016 and $4017 with nothing plugged in. Only the low 5 bits are driven
+struct ControllerPorts;
 
-    fn new_resetted() -> Self {
+impl Device for ControllerPorts {
+    fn load(&mut self, offset: doubleword) -> Option<word> {
+        self.peek(offset)
+    }
 
-        let mut ret = Self {
-            internal_ram: Ram::new(),
-            cart: Cartridge::new_zeroed(),
-        };
+    /// The strobe of $4016, ignored, and the APU frame counter at $4017
+    fn store(&mut self, _offset: doubleword, _data: word) -> bool {
+        true
+    }
 
-        ret.internal_ram.reset();
-        ret
+    fn peek(&self, _offset: doubleword) -> Option<word> {
+        Some(word::zero())
+    }
+
+    fn undriven_bits(&self, _offset: doubleword) -> u8 {
+        0xE0
+    }
+}
+
+impl SaveState for ControllerPorts {
+    fn save(&self, _out: &mut StateWriter) {}
+
+    fn restore(&mut self, _input: &mut StateReader) -> Result<(), SaveStateError> {
+        Ok(())
+    }
+}
+
+impl FamicomMemory {
+    pub fn bus(&self) -> &Bus {
+        &self.bus
+    }
+
+    pub fn bus_mut(&mut self) -> &mut Bus {
+        &mut self.bus
+    }
+}
+
+impl IO6502 for FamicomMemory {
+
+    fn new_resetted() -> Self {
+        let mut bus = Bus::new();
+        let ram = bus.add_ram(RAM_SIZE_BYTES);
+        bus.map(ram, 0x0000..=0x1FFF, 0x07FF);
+        // Nothing answers reads from the PPU, APU and IO registers yet
+        let ppu = bus.add_device(Unemulated);
+        bus.map(ppu, 0x2000..=0x3FFF, 0x0007);
+        let apu = bus.add_device(Unemulated);
+        bus.map(apu, 0x4000..=0x401F, 0x001F); // APU and IO, then test mode
+        let controllers = bus.add_device(ControllerPorts);
+        bus.map(controllers, 0x4016..=0x4017, 0x0001);
+        let cart = bus.add_rom(0x8000);
+        bus.map(cart, 0x8000..=0xFFFF, 0x7FFF);
+        bus.set_program_origin(0x8000);
+        bus.set_open_bus(true);
+        // Writes to the cartridge ROM and to $4018-$7FFF are legal, nothing takes them
+        bus.set_ignore_stray_stores(true);
+        Self { bus }
     }
 
     fn reset(&mut self) {
-        self.internal_ram.reset();
+        self.bus.reset();
     }
 
     fn push_program(&mut self, program: InstructionStream) {
+        self.bus.push_program(program);
+    }
 
-        self.cart.push_program(program);
-
-        
+    #[inline]
+    fn store(&mut self, address: doubleword, data: word) -> Result<(), BusFault> {
+        self.bus.store(address, data)
     }
 
-    fn store(&mut self, address: doubleword, data: word) {
-        let test_val = self.access(address, MemoryAccessType::Store, Some(data));
-        debug_assert!(test_val.is_none());
+    #[inline]
+    fn load(&mut self, address: doubleword) -> Result<word, BusFault> {
+        self.bus.load(address)
     }
 
-    fn load(&mut self, address: doubleword) -> word {
-        self.access(address, MemoryAccessType::Load, None).expect("Reading memory failed.")
+    fn peek(&self, address: doubleword) -> Option<word> {
+        self.bus.peek(address)
     }
 
+    fn bank(&self, address: doubleword) -> Option<u8> {
+        self.bus.bank(address)
+    }
 
-}
+    #[inline]
+    fn nmi_asserted(&self) -> bool {
+        self.bus.nmi_asserted()
+    }
 
-impl FamicomMemory {
-    fn access(&mut self, address: doubleword, tpe: MemoryAccessType, data: Option<word>) -> Option<word> {
-        let addr = address.native_value();
-        match addr {
-            0x0000..=0x1FFF => {
-                let real_address = address.native_value() % 0x800; // Clamp mirrored RAM addresses to the real ones
-                match tpe {
-                    MemoryAccessType::Load => Some(self.internal_ram.read(doubleword::from(real_address))),
-                    MemoryAccessType::Store => {
-                        self.internal_ram.write(doubleword::from(real_address), word::from(data.expect("access function got a store request without a value")));
-                        None
-                    },
-                }
-            }, // RAM (repeated)
-            0x2000..=0x3FFF => None, // PPU (repeated)
-            0x4000..=0x4017 => None, // APU and IO
-            0x4018..=0x401F => None, // test Mode
-            0x8000..=0xFFFF => { // cartridge
-                match tpe {
-                    MemoryAccessType::Load => Some(self.cart.read(doubleword::from(addr - 0x8000u16))),
-                    MemoryAccessType::Store => panic!("Error: trying to make a store on the cartridge ROM"),
-                }
-            }, 
-            _ => panic!(), // FIXME: complete range
-        }
+    #[inline]
+    fn irq_asserted(&self) -> bool {
+        self.bus.irq_asserted()
     }
 }
 
-struct System<T: IO6502> {
+pub struct System<T: IO6502> {
+    variant: CpuVariant,
+    /// How undocumented opcodes are handled
+    undocumented: UndocumentedOpcodes,
+    /// Set by a JAM opcode, only a reset gets the CPU going again
+    jammed: bool,
+    /// Set by the 65C02 WAI, until an interrupt input is asserted
+    waiting: bool,
+    /// Set by the 65C02 STP, only a reset gets the CPU going again
+    stopped: bool,
+    /// First bus fault of the current step, reported once the step is over
+    bus_fault: Option<BusFault>,
+
     a: word,
     x: word,
     y: word,
@@ -330,174 +1499,443 @@ struct System<T: IO6502> {
     /// Processor status register
     p: word,
 
+    /// Number of cycles elapsed since the CPU was created
+    cycles: u64,
+
+    /// Whether `step` runs whole instructions or goes through the cycle-stepped core
+    exec_mode: ExecMode,
+    /// Progress of the current instruction in cycle-stepped mode
+    cycle_state: CycleState,
+
+    /// Level of the NMI input at the last poll, to detect its edges
+    nmi_line: bool,
+    /// Set on an NMI edge, cleared when the NMI vector is fetched
+    nmi_pending: bool,
+    /// I as the interrupt poll of the last instruction saw it, when that instruction was CLI,
+    /// SEI or PLP. IRQs are masked by it rather than by I at the next instruction boundary
+    delayed_i: Option<bool>,
+
+    /// Breakpoints and watchpoints, see `debugger`
+    debugger: Debugger,
+    /// Whether runs stop on an instruction jumping to itself
+    stop_on_trap: bool,
+    /// Receives the trace line of every instruction, see `trace`
+    tracer: Option<Tracer>,
+
     mem: T,
 }
 
-const C_BIT: u8 = (1 << 0);
-const Z_BIT: u8 = (1 << 1);
-const I_BIT: u8 = (1 << 2);
-const D_BIT: u8 = (1 << 3);
-const B_BIT: u8 = (1 << 4);
-const V_BIT: u8 = (1 << 6);
-const N_BIT: u8 = (1 << 7);
+const C_BIT: u8 = 1 << 0;
+const Z_BIT: u8 = 1 << 1;
+const I_BIT: u8 = 1 << 2;
+const D_BIT: u8 = 1 << 3;
+const B_BIT: u8 = 1 << 4;
+const V_BIT: u8 = 1 << 6;
+const N_BIT: u8 = 1 << 7;
+/// Bit 5 of P is not connected and always reads as 1 when P is pushed
+const U_BIT: u8 = 1 << 5;
+
+const NMI_VECTOR: u16 = 0xFFFA;
+const RESET_VECTOR: u16 = 0xFFFC;
+const IRQ_VECTOR: u16 = 0xFFFE;
 
 enum AddSubMode {
     Add,
     Sub,
 }
 
+/// Which member of the 6502 family is emulated
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CpuVariant {
+    /// Original NMOS 6502
+    Nmos6502,
+    /// NES / Famicom CPU: an NMOS 6502 with decimal mode disconnected
+    Ricoh2A03,
+    /// WDC 65C02, including the Rockwell bit instructions
+    Cmos65C02,
+}
+
+impl CpuVariant {
+    #[inline]
+    /// Whether the D flag switches ADC and SBC to BCD arithmetic
+    fn has_decimal_mode(self) -> bool {
+        match self {
+            CpuVariant::Nmos6502 | CpuVariant::Cmos65C02 => true,
+            CpuVariant::Ricoh2A03 => false,
+        }
+    }
+}
+
+/// What the CPU does when it meets an undocumented opcode
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UndocumentedOpcodes {
+    /// Execute them like the NMOS 6502 does. JAM opcodes freeze the CPU until the next reset,
+    /// the clock still running
+    Execute,
+    /// Execute them, but JAM opcodes halt the emulation instead of burning cycles
+    HaltOnJam,
+    /// Treat any undocumented opcode as an error
+    Trap,
+}
+
+/// Value ORed with A by the unstable XAA and LAX #imm. It depends on the chip and its temperature,
+/// $EE is the value given by "No More Secrets" (NMOS 6510 Unintended Opcodes)
+const UNSTABLE_MAGIC: u8 = 0xEE;
+
 impl<T: IO6502> System<T> {
 
+    #[cfg(test)]
     fn new_resetted() -> Self {
+        Self::with_variant(CpuVariant::Nmos6502)
+    }
 
-        let ret = Self {
+    /// A `variant` CPU over freshly reset memory
+    pub fn with_variant(variant: CpuVariant) -> Self {
+        Self::with_memory(variant, T::new_resetted())
+    }
+
+    /// A `variant` CPU over `mem`, e.g. a `Bus` with devices already mapped
+    pub fn with_memory(variant: CpuVariant, mem: T) -> Self {
+
+        Self {
+            variant,
+            undocumented: UndocumentedOpcodes::Execute,
+            jammed: false,
+            waiting: false,
+            stopped: false,
+            bus_fault: None,
             a: word::zero(),
             x: word::zero(),
             y: word::zero(),
             pc: doubleword::zero(),
             s: word::zero(),
             p: word::zero(),
-            mem: T::new_resetted(),
-        };
-        ret
+            cycles: 0,
+            exec_mode: ExecMode::Instruction,
+            cycle_state: CycleState::new(),
+            nmi_line: false,
+            nmi_pending: false,
+            delayed_i: None,
+            debugger: Debugger::new(),
+            stop_on_trap: false,
+            tracer: None,
+            mem,
+        }
+    }
+
+    #[cfg(test)]
+    /// Run program only for a specific number of instructions before returning (mostly intended for debug)
+    fn run_programm_for(&mut self, stream: InstructionStream, count: usize) -> Result<(), ExecError> {
+        self.mem.push_program(stream);
+
+        for _ in 0..count {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    /// Takes the branch if `val` is true. Expects PC to already point to the next instruction.
+    /// Returns the extra cycles taken: 1 for a taken branch, 2 if it also lands on another page
+    fn branch_on(&mut self, val: bool, offset: word) -> u8 {
+        if !val {
+            return 0;
+        }
+        let target = self.relative_address(offset);
+        let penalty = 1 + Self::crosses_page(self.pc, target) as u8;
+        self.pc = target;
+        penalty
+    }
+
+    #[inline]
+    fn crosses_page(lhs: doubleword, rhs: doubleword) -> bool {
+        lhs.to_words()[1].native_value() != rhs.to_words()[1].native_value()
+    }
+
+    /// RESET sequence: S goes down by 3 as if PC and P were pushed, but nothing is written.
+    /// Interrupts are disabled and PC is loaded from the reset vector. A, X and Y are left untouched
+    pub fn reset(&mut self) -> Result<(), ExecError> {
+        self.s = self.s.cl_sub(word::from(3u8));
+        self.p = self.p | I_BIT | U_BIT;
+        self.pc = self.load_doubleword(doubleword::from(RESET_VECTOR));
+        self.nmi_pending = false;
+        self.delayed_i = None;
+        self.jammed = false;
+        self.waiting = false;
+        self.stopped = false;
+        self.cycle_state = CycleState::new();
+        self.cycles += 7;
+        self.take_bus_fault()
+    }
+
+    pub fn set_undocumented_opcodes(&mut self, policy: UndocumentedOpcodes) {
+        self.undocumented = policy;
+    }
+
+    #[inline]
+    /// Looks `instr` up in the opcode table of the emulated variant
+    fn decode(&self, instr: word) -> &'static Opcode {
+        opcodes::decode_for(self.variant, instr)
+    }
+
+    #[inline]
+    fn is_65c02(&self) -> bool {
+        self.variant == CpuVariant::Cmos65C02
+    }
+
+    #[inline]
+    /// The 65C02 takes one more cycle for ADC and SBC in decimal mode
+    fn decimal_penalty(&self, mnemonic: Mnemonic) -> bool {
+        self.is_65c02() && self.D() && matches!(mnemonic, Mnemonic::ADC | Mnemonic::SBC)
+    }
+
+    #[inline]
+    /// Called while WAI is waiting. Any asserted interrupt input ends the wait, even an IRQ
+    /// masked by I, in which case execution simply resumes after WAI
+    fn wake_up(&mut self) -> bool {
+        self.poll_nmi();
+        if self.nmi_pending || self.mem.irq_asserted() {
+            self.waiting = false;
+        }
+        !self.waiting
+    }
+
+    #[inline]
+    /// Applies the undocumented opcode policy, before `instr`, fetched from `pc`, is executed
+    fn check_undocumented(&self, instr: word, pc: doubleword) -> Result<(), ExecError> {
+        match !self.decode(instr).official && self.undocumented == UndocumentedOpcodes::Trap {
+            true => Err(ExecError::IllegalOpcode { pc: pc.native_value(), opcode: instr.native_value() }),
+            false => Ok(()),
+        }
     }
 
-    /// Run program only for a specific number of instructions before returning (mostly intended for debug)
-    fn run_programm_for(&mut self, stream: InstructionStream, count: usize) {
-        self.mem.push_program(stream);
-
-        for x in 0..count {
-            println!("instrr count: {}", x);
-            self.advance_exec();
+    #[inline]
+    /// A cycle of a jammed CPU. It keeps reading $FFFF, unless the policy is to halt
+    /// in which case time stops. Returns the number of cycles taken
+    fn jammed_cycle(&mut self) -> Result<u8, ExecError> {
+        match self.undocumented {
+            UndocumentedOpcodes::HaltOnJam => Err(ExecError::Halted { pc: self.pc.native_value() }),
+            _ => {
+                self.load(doubleword::from(0xFFFFu16));
+                self.cycles += 1;
+                self.take_bus_fault()?;
+                Ok(1)
+            },
         }
     }
 
+    #[inline]
+    /// Reports the first bus fault since the last call, if any
+    fn take_bus_fault(&mut self) -> Result<(), ExecError> {
+        match self.bus_fault.take() {
+            Some(fault) => Err(fault.into()),
+            None => Ok(()),
+        }
+    }
 
-    fn run_program(&mut self, stream: InstructionStream) {
-        self.mem.push_program(stream);
+    #[inline]
+    /// Samples the NMI input, latching an NMI on its deasserted to asserted edge
+    fn poll_nmi(&mut self) {
+        let line = self.mem.nmi_asserted();
+        if line && !self.nmi_line {
+            self.nmi_pending = true;
+        }
+        self.nmi_line = line;
     }
 
-    fn run(&mut self) {
-        // General idea: fetch next instruction, execute it, wait a certain amount of time, start over
+    #[inline]
+    /// True if a hardware interrupt must be serviced before the next instruction
+    fn interrupt_pending(&self) -> bool {
+        self.nmi_pending || (self.mem.irq_asserted() && !self.delayed_i.unwrap_or(self.I()))
+    }
 
-        loop {
-            self.advance_exec();
-            // sleep(1);
-        }
+    #[inline]
+    /// Called once `instr` is fetched to be executed. CLI, SEI and PLP change I after the
+    /// interrupt poll of their last cycle, so IRQs see the change one instruction late. RTI
+    /// restores I before that poll, taking effect at once
+    fn latch_irq_mask(&mut self, instr: word) {
+        self.delayed_i = match instr.native_value() {
+            0x28 | 0x58 | 0x78 => Some(self.I()), // PLP, CLI, SEI
+            _ => None,
+        };
     }
 
     #[inline]
-    fn branch_on(&mut self, val: bool)  {
-        match val {
-            true => self.relative_jump(),
-            false => self.advance_pc_2(),
+    /// Vector used by BRK, IRQ and NMI. A pending NMI takes over whatever started the sequence
+    fn interrupt_vector(&mut self) -> doubleword {
+        match self.nmi_pending {
+            true => {
+                self.nmi_pending = false;
+                doubleword::from(NMI_VECTOR)
+            },
+            false => doubleword::from(IRQ_VECTOR),
         }
     }
 
-    #[inline]
-    fn reset(&mut self) {
-        self.a = word::zero();
-        self.x = word::zero();
-        self.y = word::zero();
-        self.s = word::zero();
-        self.p = word::zero();
-        self.pc = doubleword::zero();
+    /// Pushes the return address and P, then jumps through the interrupt vector. B is only set in
+    /// the pushed P for BRK. An NMI arriving before the vector is fetched hijacks the sequence
+    fn enter_interrupt(&mut self, return_address: doubleword, brk: bool) {
+        self.push_doubleword(return_address);
+        let p = match brk {
+            true => self.p | B_BIT | U_BIT,
+            false => (self.p & !B_BIT) | U_BIT,
+        };
+        self.push_word(p);
+        self.set_I();
+        if self.is_65c02() {
+            self.clear_D();
+        }
+        self.poll_nmi();
+        let vector = self.interrupt_vector();
+        self.pc = self.load_doubleword(vector);
     }
 
 
 
-    #[inline]
-    fn illegal_op(instr: word) {
-        panic!("Error: illegal opcode {}", instr.cpu_value());
+    /// Undocumented opcodes are checked before being executed, and every mnemonic is handled
+    /// by the helper its addressing mode and access type lead to: getting here is a bug
+    fn misdecoded(instr: word) -> ! {
+        unreachable!("opcode ${:02X} was dispatched to the wrong handler", instr.native_value());
     }
 
     #[inline]
+    /// Failed stores are recorded, to be reported at the end of the step
     fn store(&mut self, address: doubleword, data: word) {
-        self.mem.store(address, data);
+        self.debugger.watch(address, MemoryAccessType::Store);
+        if let Err(fault) = self.mem.store(address, data) {
+            self.bus_fault.get_or_insert(fault);
+        }
     }
 
     #[inline]
+    /// Failed loads read as 0 and are recorded, to be reported at the end of the step
     fn load(&mut self, address: doubleword) -> word {
-        self.mem.load(address)
-    }
-
-    /// Load from memory, intepreting the value as a signed 16-bit address offset
-    fn load_offset(&mut self, address: doubleword) -> i16 {
-        self.mem.load(address).native_value() as i16
-
+        self.debugger.watch(address, MemoryAccessType::Load);
+        match self.mem.load(address) {
+            Ok(val) => val,
+            Err(fault) => {
+                self.bus_fault.get_or_insert(fault);
+                word::zero()
+            },
+        }
     }
 
     #[inline]
     fn load_doubleword(&mut self, address: doubleword) -> doubleword {
-        let lo = self.mem.load(address);
-        let hi = self.mem.load(address + 1u8);
+        let lo = self.load(address);
+        let hi = self.load(address.cl_add(word::from(1u8)));
 
         doubleword::from_words(hi, lo)
     }
 
     #[inline]
-    fn advance_exec(&mut self) {
-        let next_instr = self.mem.load(self.pc);
-        self.alternate_exec(next_instr)
+    /// Executes the next instruction, or services a pending interrupt.
+    /// A jammed or waiting CPU only runs a single cycle
+    pub fn step(&mut self) -> Result<StepInfo, ExecError> {
+        let pc = self.pc;
+        let idle = |cycles| StepInfo { pc: pc.native_value(), opcode: None, cycles };
+        if self.jammed {
+            return self.jammed_cycle().map(idle);
+        }
+        if self.stopped {
+            return Err(ExecError::Halted { pc: pc.native_value() });
+        }
+        if self.waiting && !self.wake_up() {
+            self.cycles += 1;
+            return Ok(idle(1));
+        }
+        if self.tracer.is_some() && self.cycle_state.at_instruction_boundary() {
+            self.poll_nmi();
+            if !self.interrupt_pending() {
+                self.trace();
+            }
+        }
+        match self.exec_mode {
+            ExecMode::Instruction => {
+                self.poll_nmi();
+                let (opcode, cycles) = match self.interrupt_pending() {
+                    true => {
+                        self.delayed_i = None;
+                        self.enter_interrupt(self.pc, false);
+                        (None, 7)
+                    },
+                    false => {
+                        let next_instr = self.load(self.pc);
+                        self.take_bus_fault()?;
+                        self.check_undocumented(next_instr, self.pc)?;
+                        self.latch_irq_mask(next_instr);
+                        (Some(next_instr.native_value()), self.alternate_exec(next_instr))
+                    },
+                };
+                self.cycles += cycles as u64;
+                self.take_bus_fault()?;
+                Ok(StepInfo { pc: pc.native_value(), opcode, cycles })
+            },
+            ExecMode::Cycle => self.cycle_step(),
+        }
+    }
+
+    #[inline]
+    /// Address of the stack slot S currently points to. The stack lives on page $01
+    fn stack_address(&self) -> doubleword {
+        doubleword::from_words(word::from(0x01u8), self.s)
     }
 
     #[inline]
+    /// Stores at the current slot then decrements S, wrapping within page $01
     fn push_word(&mut self, data: word) {
-        self.mem.store(self.s.as_doubleword(), data);
-        self.s = self.s + 1; // TODO: might need to add a modulo / wraparound for stack? Check 6502 docs
+        self.store(self.stack_address(), data);
+        self.s = self.s.cl_sub(word::from(1u8));
     }
 
     #[inline]
+    /// Pushes the high byte first, one byte at a time like the CPU does
     fn push_doubleword(&mut self, data: doubleword) {
-        let words = data.to_words();
-        self.mem.store(self.s.as_doubleword(), words[0]);
-        self.mem.store(self.s.as_doubleword() + 1u8, words[1]);
-        self.s = self.s + 2;
+        let [lo, hi] = data.to_words();
+        self.push_word(hi);
+        self.push_word(lo);
     }
 
     #[inline]
-    /// More comonly called 'pop'
+    /// More comonly called 'pop'. Increments S then loads, wrapping within page $01
     fn pull_word(&mut self) -> word {
-        self.s = self.s - 1u8;
-        self.load(self.s.as_doubleword())
-
+        self.s = self.s.cl_add(word::from(1u8));
+        self.load(self.stack_address())
     }
 
     #[inline]
     /// More comonly called 'pop'
     fn pull_doubleword(&mut self) -> doubleword {
-        self.s = self.s - 2u8;
-        self.load_doubleword(self.s.as_doubleword())
+        let lo = self.pull_word();
+        let hi = self.pull_word();
+        doubleword::from_words(hi, lo)
     }
 
     // These 3 advance functions might seem a bit overkill,
     // but they are in case advancing ends up being more
     // complicated that this
 
+    #[cfg(test)]
     #[inline]
     /// Advance program execution by 1 byte
     fn advance_pc_1(&mut self) {
         self.pc = self.pc + 1u8;
     }
 
+    #[cfg(test)]
     #[inline]
     /// Advance program execution by 2 bytes
     fn advance_pc_2(&mut self) {
         self.pc = self.pc + 2u8;
     }
 
+    #[cfg(test)]
     #[inline]
     /// Advance program execution by 3 bytes
     fn advance_pc_3(&mut self) {
         self.pc = self.pc + 3u8;
     }
 
-    #[inline]
-    /// This function assumes that the jump offset is available at PC + 1
-    fn relative_jump(&mut self) {
-        self.pc = self.pc + self.load_offset(self.pc + 1u8);
-    }
-
     #[inline]
     #[allow(non_snake_case)]
     fn C(&self) -> bool {
@@ -570,15 +2008,6 @@ impl<T: IO6502> System<T> {
         self.p = word::from(self.p.native_value() & !I_BIT);
     }
 
-    #[inline]
-    #[allow(non_snake_case)]
-    fn update_I(&mut self, val: bool) {
-        match val {
-            true => self.set_I(),
-            false => self.clear_I(),
-        }
-    }
-
     #[inline]
     #[allow(non_snake_case)]
     fn D(&self) -> bool {
@@ -597,42 +2026,13 @@ impl<T: IO6502> System<T> {
         self.p = word::from(self.p.native_value() & !D_BIT);
     }
 
-    #[inline]
-    #[allow(non_snake_case)]
-    fn update_D(&mut self, val: bool) {
-        match val {
-            true => self.set_D(),
-            false => self.clear_D(),
-        }
-    }
-
+    #[cfg(test)]
     #[inline]
     #[allow(non_snake_case)]
     fn B(&self) -> bool {
         (self.p.native_value() & B_BIT) == B_BIT
     }
 
-    #[inline]
-    #[allow(non_snake_case)]
-    fn set_B(&mut self) {
-        self.p = word::from(self.p.native_value() | B_BIT);
-    }
-
-    #[inline]
-    #[allow(non_snake_case)]
-    fn clear_B(&mut self) {
-        self.p = word::from(self.p.native_value() & !B_BIT);
-    }
-
-    #[inline]
-    #[allow(non_snake_case)]
-    fn update_B(&mut self, val: bool) {
-        match val {
-            true => self.set_B(),
-            false => self.clear_B(),
-        }
-    }
-
     #[inline]
     #[allow(non_snake_case)]
     fn V(&self) -> bool {
@@ -690,95 +2090,9 @@ impl<T: IO6502> System<T> {
     // =============== HELPERS FUNCTIONS FOR RETRIEVING VALUES ===============
 
     #[inline]
-    fn indirect_address(&mut self) -> doubleword {
-        let (hi, lo) = (self.load(self.pc + 2u16), self.load(self.pc + 1u16));
-        doubleword::from_words(hi, lo)
-    }
-
-    #[inline]
-    /// Loads word from zeropage immediate + value at Y
-    fn indirect_value(&mut self) -> word {
-        let addr = self.indirect_address();
-        let val = self.load(addr);
-        val
-    }
-
-    #[inline]
-    fn indirect_address_x(&mut self) -> doubleword {
-        self.immediate_value().cl_add(self.x).as_doubleword()
-    }
-
-    #[inline]
-    /// Used for ind addressing type with X
-    fn indirect_value_x(&mut self) -> word {
-        let addr = self.indirect_address_x();
-        let val = self.load(addr);
-        val
-    }
-
-    #[inline]
-    /// Used for ind addressing type with Y
-    fn indirect_value_y(&mut self) -> word {
-        let addr = self.immediate_value();
-        let val = self.load(addr.as_doubleword()) + self.y;
-        val
-    }
-
-    #[inline]
-    /// Convenience function to access # (immediate) value at pc + 1. Also used for zero-page addresses
-    fn immediate_value(&mut self) -> word {
-        self.load(self.pc + 1u8)
-    }
-
-    fn immediate_address(&mut self) -> doubleword {
-        self.load(self.pc + 1u8).as_doubleword()
-    }
-
-    #[inline]
-    /// Loads word from zeropage address + value at X
-    fn zeropage_address(&mut self) -> doubleword {
-        self.immediate_value().as_doubleword()
-    }
-
-    // TODO: check if X, Y offset in zero page is offset for address or for value at that address
-
-    #[inline]
-    /// Loads word from zeropage address + value at X
-    fn zeropage_address_x(&mut self) -> doubleword {
-        self.zeropage_address() + self.x.as_doubleword()
-    }
-
-    #[inline]
-    /// Loads word from zeropage address + value at Y
-    fn zeropage_address_y(&mut self) -> doubleword {
-        self.zeropage_address() + self.x.as_doubleword()
-    }
-
-    #[inline]
-    /// Used for loading the value for zpg instructions
-    fn zeropage_value(&mut self) -> word {
-        let addr = self.zeropage_address();
-        self.load(addr)
-    }
-
-    #[inline]
-    /// Loads word from zeropage immediate + value at X
-    fn zeropage_value_x(&mut self) -> word {
-
-        let addr = self.zeropage_address_x();
-        self.load(addr)
-    }
-
-    #[inline]
-    /// Loads word from zeropage immediate + value at Y
-    fn zeropage_value_y(&mut self) -> word {
-        let addr = self.zeropage_address_y();
-        self.load(addr)
-    }
-
-    #[inline]
-    fn relative_value(&mut self) -> doubleword {
-        self.pc + (self.immediate_value().as_i16())
+    /// Branch target for a signed offset, relative to the current PC
+    fn relative_address(&self, offset: word) -> doubleword {
+        self.pc.cl_add(doubleword::from(offset.native_value_signed() as u16))
     }
 
     #[inline]
@@ -800,34 +2114,26 @@ impl<T: IO6502> System<T> {
         }
     }
 
-    #[inline]
-    /// 
-    // FIXME: Test it
-    fn absolute_address(&mut self) -> doubleword {
-        let hi = self.load(self.pc + 2u16);
-        let lo = self.load(self.pc + 1u16);
-        doubleword::from_words(hi, lo)
-    }
-
-    #[inline]
-    /// 
-    fn absolute_value(&mut self) -> word {
-        let addr = self.absolute_address();
-        self.load(addr)
-    }
-
     // =============== CONVENIENCE FUNCTIONS / MACROS FOR COMPUTATIONS ===============
 
     #[inline]
     /// Performs the 6502 compare operation: a substraction folowed by the updates of N, Z and C flags
     fn compare(&mut self, lhs: word, rhs: word) {
-        // TODO: check if this correct, Flag behaviour is not clear yet
-        let (res, did_overflow) = lhs.native_value().overflowing_sub(rhs.native_value());
+        // C is set when no borrow happened, i.e. lhs >= rhs (unsigned). V is left untouched
+        let (res, did_borrow) = lhs.native_value().overflowing_sub(rhs.native_value());
 
-        self.update_V(did_overflow);
+        self.update_C(!did_borrow);
         self.update_flags_zn(word::from(res));
     }
 
+    #[inline]
+    /// BIT: Z from A & M, while N and V are copied from bits 7 and 6 of M
+    fn bit(&mut self, val: word) {
+        self.update_Z((self.a & val) == 0u8);
+        self.update_N(val.bit_at(7).unwrap());
+        self.update_V(val.bit_at(6).unwrap());
+    }
+
     #[inline]
     fn or(&mut self, lhs: word, rhs: word) -> word {
         let ret = lhs | rhs;
@@ -850,36 +2156,122 @@ impl<T: IO6502> System<T> {
         ret
     }
 
-    #[inline]
-    /// Same as EOR
-    fn xor(&mut self, lhs: word, rhs: word) -> word {
-        self.eor(lhs, rhs)
-    }
-
 
     #[inline]
     /// Convencience function for all carry-type ops
     fn op_carry(&mut self, val: word, mode: AddSubMode) -> word {
-        let c = self.C() as i16;
-        let m = val.native_value_signed() as i16;
-        let a = self.a.native_value_signed() as i16;
+        if !(self.D() && self.variant.has_decimal_mode()) {
+            return self.op_carry_binary(val, mode);
+        }
+        match mode {
+            AddSubMode::Add => self.adc_decimal(val),
+            AddSubMode::Sub => self.sbc_decimal(val),
+        }
+    }
 
-        let (res, did_overflow) = match mode {
-            AddSubMode::Add => c.overflowing_add(m),
-            AddSubMode::Sub => c.overflowing_sub(m),
-        };
-        let (res2, did_overflow_2) = match mode {
-            AddSubMode::Add => res.overflowing_add(a),
-            AddSubMode::Sub => res.overflowing_sub(a),
+    #[inline]
+    fn op_carry_binary(&mut self, val: word, mode: AddSubMode) -> word {
+        // SBC is an ADC of the one's complement of the operand, the carry acting as an inverted borrow
+        let m = match mode {
+            AddSubMode::Add => val,
+            AddSubMode::Sub => val ^ 0xFFu8,
         };
-        
-        self.update_C((res2 & CARRY_BIT) != 0);
-        self.update_V(did_overflow | did_overflow_2);
-        let ret = word::from(res2);
+        let res = self.a.as_i16() + m.as_i16() + self.C() as i16;
+        let ret = word::from(res);
+
+        self.update_C((res & CARRY_BIT) != 0);
+        // Overflow happens when both operands have the same sign, and the result has a different one
+        self.update_V(((self.a ^ ret) & (m ^ ret)).bit_at(7).unwrap());
         self.update_flags_zn(ret);
         ret
     }
 
+    /// ARR: AND with the operand then ROR A, C and V coming from the adder. With D set the NMOS
+    /// 6502 also applies a BCD fixup, N being the old carry and V the change of bit 6 (as described in 64doc)
+    fn arr(&mut self, val: word) -> word {
+        let t = (self.a & val).native_value();
+        let mut res = (t >> 1) | ((self.C() as u8) << 7);
+        self.update_flags_zn(word::from(res));
+
+        if !(self.D() && self.variant.has_decimal_mode()) {
+            self.update_C(res & 0x40 != 0);
+            self.update_V(((res >> 6) ^ (res >> 5)) & 1 != 0);
+            return word::from(res);
+        }
+
+        self.update_V((t ^ res) & 0x40 != 0);
+        let (hi, lo) = (t >> 4, t & 0x0F);
+        if lo + (lo & 1) > 5 {
+            res = (res & 0xF0) | (res.wrapping_add(0x06) & 0x0F);
+        }
+        let carry = hi + (hi & 1) > 5;
+        if carry {
+            res = res.wrapping_add(0x60);
+        }
+        self.update_C(carry);
+        word::from(res)
+    }
+
+    /// BCD addition as done by the NMOS 6502. Z comes from the binary sum, while N and V are
+    /// taken before the high nibble is adjusted. Invalid BCD operands give the same results as the hardware
+    fn adc_decimal(&mut self, val: word) -> word {
+        let a = self.a.native_value() as u16;
+        let m = val.native_value() as u16;
+        let c = self.C() as u16;
+
+        let mut lo = (a & 0x0F) + (m & 0x0F) + c;
+        if lo >= 0x0A {
+            lo = ((lo + 0x06) & 0x0F) + 0x10;
+        }
+        let mut res = (a & 0xF0) + (m & 0xF0) + lo;
+
+        self.update_Z((a + m + c) & 0xFF == 0);
+        self.update_N(res & 0x80 != 0);
+        self.update_V(!(a ^ m) & (a ^ res) & 0x80 != 0);
+        if res >= 0xA0 {
+            res += 0x60;
+        }
+        self.update_C(res >= 0x100);
+        if self.is_65c02() {
+            // The 65C02 fixed N and Z
+            self.update_flags_zn(word::from(res as u8));
+        }
+        word::from(res as u8)
+    }
+
+    /// BCD subtraction as done by the NMOS 6502. All flags are the ones of the binary subtraction
+    fn sbc_decimal(&mut self, val: word) -> word {
+        let a = self.a.native_value() as i16;
+        let m = val.native_value() as i16;
+        let c = self.C() as i16;
+        self.op_carry_binary(val, AddSubMode::Sub);
+
+        if self.is_65c02() {
+            // The 65C02 adjusts the binary difference, and sets N and Z from the result
+            let lo = (a & 0x0F) - (m & 0x0F) + c - 1;
+            let mut res = a - m + c - 1;
+            if res < 0 {
+                res -= 0x60;
+            }
+            if lo < 0 {
+                res -= 0x06;
+            }
+            let ret = word::from(res as u8);
+            self.update_flags_zn(ret);
+            return ret;
+        }
+
+        let mut lo = (a & 0x0F) - (m & 0x0F) + c - 1;
+        if lo < 0 {
+            lo = ((lo - 0x06) & 0x0F) - 0x10;
+        }
+        let mut res = (a & 0xF0) - (m & 0xF0) + lo;
+        if res < 0 {
+            res -= 0x60;
+        }
+        word::from(res as u8)
+    }
+
     #[inline]
     /// ADC convenience function
     fn adc(&mut self, val: word) -> word {
@@ -939,1353 +2331,353 @@ impl<T: IO6502> System<T> {
         ret
     }
 
-    // Re-implementation of decoding, using the aaabbbcc layout of opcodes
-    fn alternate_exec(&mut self, instr: word) {
-        
-        let mut operand: Option<word> = None;
-        let mut address: Option<doubleword>  = None;
-        let mut pc_offset = 0u16;
+    // =============== INSTRUCTION SEMANTICS, SHARED BY BOTH EXECUTION MODES ===============
+
+    /// Executes an instruction that reads its operand (from memory or as an immediate)
+    fn op_read(&mut self, instr: word, val: word) {
+        let opcode = self.decode(instr);
+        match opcode.mnemonic {
+            Mnemonic::ADC => self.a = self.add_carry(val),
+            Mnemonic::AND => self.a = self.and(self.a, val),
+            // BIT # of the 65C02 only affects Z
+            Mnemonic::BIT if opcode.mode == AddressingMode::Immediate => self.update_Z((self.a & val).native_value() == 0),
+            Mnemonic::BIT => self.bit(val),
+            Mnemonic::CMP => self.compare(self.a, val),
+            Mnemonic::CPX => self.compare(self.x, val),
+            Mnemonic::CPY => self.compare(self.y, val),
+            Mnemonic::EOR => self.a = self.eor(self.a, val),
+            Mnemonic::LDA => {
+                self.a = val;
+                self.update_flags_zn(self.a);
+            },
+            Mnemonic::LDX => {
+                self.x = val;
+                self.update_flags_zn(self.x);
+            },
+            Mnemonic::LDY => {
+                self.y = val;
+                self.update_flags_zn(self.y);
+            },
+            Mnemonic::ORA => self.a = self.or(self.a, val),
+            Mnemonic::SBC => self.a = self.sub_carry(val),
+            Mnemonic::NOP => (),
+
+            // Undocumented
+            Mnemonic::ALR => {
+                let res = self.a & val;
+                self.a = self.lsr(res);
+            },
+            Mnemonic::ANC => {
+                self.a = self.and(self.a, val);
+                self.update_C(self.N());
+            },
+            Mnemonic::ARR => self.a = self.arr(val),
+            Mnemonic::LAS => {
+                let res = val & self.s;
+                self.a = res;
+                self.x = res;
+                self.s = res;
+                self.update_flags_zn(res);
+            },
+            Mnemonic::LAX => {
+                // The immediate version is unstable, it goes through A with some bits forced on
+                let res = match opcode.mode {
+                    AddressingMode::Immediate => (self.a | UNSTABLE_MAGIC) & val,
+                    _ => val,
+                };
+                self.a = res;
+                self.x = res;
+                self.update_flags_zn(res);
+            },
+            Mnemonic::SBX => {
+                // CMP-like subtraction from A & X, D and V are ignored
+                let lhs = self.a & self.x;
+                self.compare(lhs, val);
+                self.x = lhs.cl_sub(val);
+            },
+            Mnemonic::XAA => {
+                self.a = (self.a | UNSTABLE_MAGIC) & self.x & val;
+                self.update_flags_zn(self.a);
+            },
+            _ => Self::misdecoded(instr),
+        }
+    }
 
-        // Compute operand
-        match instr.bbb() {
-            0 => {
-                match instr.cc() {
-                    0 => {
-                        match instr.aaa() {
-                            1 => {
-                                operand = Some(self.absolute_value());
-                                address = Some(self.absolute_address());
-                                pc_offset += 3;
-                            },
-                            5 | 6 | 7 => {
-                                operand = Some(self.immediate_value());
-                                address = Some(self.immediate_address());
-                                pc_offset += 2;
-                            },
-                            _ => (),
-                        }
-                    }
-                    1 => { // all X, ind
-                        operand = Some(self.indirect_value_x());
-                        address = Some(self.indirect_address_x());
-                        pc_offset += 3;
-                    }
-                    2 => {
-                        if instr.aaa() == 5 {
-                            operand = Some(self.immediate_value());
-                            address = Some(self.immediate_address());
-                            pc_offset += 2;
-                        }
-                    }
-                    _ => unimplemented!(),
-                }
+    /// Executes a read-modify-write instruction, returning the value to write back
+    fn op_modify(&mut self, instr: word, val: word) -> word {
+        match self.decode(instr).mnemonic {
+            Mnemonic::ASL => self.asl(val),
+            Mnemonic::LSR => self.lsr(val),
+            Mnemonic::ROL => self.rol(val),
+            Mnemonic::ROR => self.ror(val),
+            Mnemonic::DEC => {
+                let res = val.cl_sub(word::from(1u8));
+                self.update_flags_zn(res);
+                res
+            },
+            Mnemonic::INC => {
+                let res = val.cl_add(word::from(1u8));
+                self.update_flags_zn(res);
+                res
             },
-            1 => { // zpg
-                // TODO: some aaa, cc combinations should not do anything
-                operand = Some(self.zeropage_value());
-                address = Some(self.zeropage_address());
-                pc_offset += 2;
-            }
 
-            2 => { // imm (#) or implied
-                match instr.cc() {
-                    1 => { // TODO: one aaa value should not do anything
-                        operand = Some(self.immediate_value());
-                        address = Some(self.immediate_address());
-                        pc_offset += 2;
-                    }, 
-                    
-                    _ => (),
-                }
-            }
+            // Undocumented: a shift or increment followed by an operation on A
+            Mnemonic::DCP => {
+                let res = val.cl_sub(word::from(1u8));
+                self.compare(self.a, res);
+                res
+            },
+            Mnemonic::ISC => {
+                let res = val.cl_add(word::from(1u8));
+                self.a = self.sub_carry(res);
+                res
+            },
+            Mnemonic::RLA => {
+                let res = self.rol(val);
+                self.a = self.and(self.a, res);
+                res
+            },
+            Mnemonic::RRA => {
+                let res = self.ror(val);
+                self.a = self.add_carry(res);
+                res
+            },
+            Mnemonic::SLO => {
+                let res = self.asl(val);
+                self.a = self.or(self.a, res);
+                res
+            },
+            Mnemonic::SRE => {
+                let res = self.lsr(val);
+                self.a = self.eor(self.a, res);
+                res
+            },
 
-            3 => { // abs (or ind)
-                
-
-                if instr.aaa() == 2 && instr.cc() == 0 { // only indirect instruction with b == 3
-                    operand = Some(self.indirect_value());
-                    address = Some(self.indirect_address());
-                    pc_offset += 3;
-                } else {
-                    operand = Some(self.absolute_value());
-                    address = Some(self.absolute_address());
-                    pc_offset += 3;
-                }
-            }
+            // 65C02
+            Mnemonic::RMB => val & !Self::bit_mask(instr),
+            Mnemonic::SMB => val | Self::bit_mask(instr),
+            Mnemonic::TRB => {
+                self.update_Z((self.a & val).native_value() == 0);
+                val & !self.a.native_value()
+            },
+            Mnemonic::TSB => {
+                self.update_Z((self.a & val).native_value() == 0);
+                val | self.a
+            },
+            _ => Self::misdecoded(instr),
+        }
+    }
 
-            4 => {
-                match instr.cc() {
-                    1 => { // ind, Y
-                        operand = Some(self.indirect_value_x());
-                        address = Some(self.indirect_address_x());
-                        pc_offset += 3;
-                        unimplemented!();
-                    }
-                    _ => (),
-                }
+    /// Returns the address and value written by a store instruction. `address` is the effective
+    /// address, and `page_crossed` tells if indexing carried into its high byte.
+    /// The unstable SHA, SHX, SHY and TAS AND the value with the high byte of the base address + 1,
+    /// and when indexing crosses a page that value also replaces the high byte of the address
+    fn op_write(&mut self, instr: word, address: doubleword, page_crossed: bool) -> (doubleword, word) {
+        let [lo, hi] = address.to_words();
+        let base_hi_plus_one = match page_crossed {
+            true => hi,
+            false => hi.cl_add(word::from(1u8)),
+        };
+        let unstable = |val: word| {
+            let val = val & base_hi_plus_one;
+            match page_crossed {
+                true => (doubleword::from_words(val, lo), val),
+                false => (address, val),
             }
-            _ => unimplemented!(),
-        }
+        };
 
+        match self.decode(instr).mnemonic {
+            Mnemonic::STA => (address, self.a),
+            Mnemonic::STX => (address, self.x),
+            Mnemonic::STY => (address, self.y),
+            Mnemonic::SAX => (address, self.a & self.x),
+            Mnemonic::STZ => (address, word::zero()),
+            Mnemonic::SHA => unstable(self.a & self.x),
+            Mnemonic::SHX => unstable(self.x),
+            Mnemonic::SHY => unstable(self.y),
+            Mnemonic::TAS => {
+                self.s = self.a & self.x;
+                unstable(self.s)
+            },
+            _ => Self::misdecoded(instr),
+        }
+    }
 
+    /// Executes an instruction working on registers only (implied and accumulator addressing)
+    fn op_implied(&mut self, instr: word) {
+        let opcode = self.decode(instr);
+        match opcode.mnemonic {
+            Mnemonic::ASL | Mnemonic::LSR | Mnemonic::ROL | Mnemonic::ROR
+            | Mnemonic::INC | Mnemonic::DEC => self.a = self.op_modify(instr, self.a),
+            Mnemonic::CLC => self.clear_C(),
+            Mnemonic::CLD => self.clear_D(),
+            Mnemonic::CLI => self.clear_I(),
+            Mnemonic::CLV => self.clear_V(),
+            Mnemonic::DEX => {
+                self.x = self.x.cl_sub(word::from(1u8));
+                self.update_flags_zn(self.x);
+            },
+            Mnemonic::DEY => {
+                self.y = self.y.cl_sub(word::from(1u8));
+                self.update_flags_zn(self.y);
+            },
+            Mnemonic::INX => {
+                self.x = self.x.cl_add(word::from(1u8));
+                self.update_flags_zn(self.x);
+            },
+            Mnemonic::INY => {
+                self.y = self.y.cl_add(word::from(1u8));
+                self.update_flags_zn(self.y);
+            },
+            Mnemonic::NOP => (),
+            Mnemonic::SEC => self.set_C(),
+            Mnemonic::SED => self.set_D(),
+            Mnemonic::SEI => self.set_I(),
+            Mnemonic::TAX => {
+                self.x = self.a;
+                self.update_flags_zn(self.x);
+            },
+            Mnemonic::TAY => {
+                self.y = self.a;
+                self.update_flags_zn(self.y);
+            },
+            Mnemonic::TSX => {
+                self.x = self.s;
+                self.update_flags_zn(self.x);
+            },
+            Mnemonic::TXA => {
+                self.a = self.x;
+                self.update_flags_zn(self.a);
+            },
+            Mnemonic::TXS => self.s = self.x, // Does not affect flags
+            Mnemonic::TYA => {
+                self.a = self.y;
+                self.update_flags_zn(self.a);
+            },
+            _ => Self::misdecoded(instr),
+        }
+    }
 
+    #[inline]
+    /// Bit tested or modified by the 65C02 RMB, SMB, BBR and BBS, encoded in their opcode
+    fn bit_mask(instr: word) -> u8 {
+        1 << ((instr.native_value() >> 4) & 0x07)
+    }
 
-        // operation itself
-        match instr.cc() {
-            0 => { // Lots of special instructions
-                match instr.aaa() {
-                    0 => {
-                        match instr.bbb() {
-                            0 => { // INSTR_HERE
-                                unimplemented!()
-                            },
-                            0 => { // INSTR_HERE
-                                unimplemented!()
-                            },
-                            0 => { // INSTR_HERE
-                                unimplemented!()
-                            },
-                            0 => { // INSTR_HERE
-                                unimplemented!()
-                            },
-                            _ => unimplemented!(), // Illegal
-                            
-                        }
-                    },
-                    1 => {
-                        match instr.bbb() {
-                            0 => { // INSTR_HERE
-                                unimplemented!()
-                            },
-                            1 | 3 => { // INSTR_HERE
-                                unimplemented!()
-                            },
-                            0 => { // INSTR_HERE
-                                unimplemented!()
-                            },
-                            0 => { // INSTR_HERE
-                                unimplemented!()
-                            },
-                            0 => { // INSTR_HERE
-                                unimplemented!()
-                            },
-                            _ => unimplemented!(), // Illegal
-                        }
-                    },
-                    2 => {
-                        match instr.bbb() {
-                            0 => { // INSTR_HERE
-                                unimplemented!()
-                            },
-                            0 => { // INSTR_HERE
-                                unimplemented!()
-                            },
-                            0 => { // INSTR_HERE
-                                unimplemented!()
-                            },
-                            0 => { // INSTR_HERE
-                                unimplemented!()
-                            },
-                            0 => { // INSTR_HERE
-                                unimplemented!()
-                            },
-                            _ => unimplemented!(), // Illegal
-                        }
-                    },
-                    3 => {
-                        match instr.bbb() {
-                            0 => { // INSTR_HERE
-                                unimplemented!()
-                            },
-                            0 => { // INSTR_HERE
-                                unimplemented!()
-                            },
-                            0 => { // INSTR_HERE
-                                unimplemented!()
-                            },
-                            0 => { // INSTR_HERE
-                                unimplemented!()
-                            },
-                            0 => { // INSTR_HERE
-                                unimplemented!()
-                            },
-                            _ => unimplemented!(), // Illegal
-                        }
-                    },
-                    4 => {
-                        match instr.bbb() {
-                            0 => { // INSTR_HERE
-                                unimplemented!()
-                            },
-                            1 | 3 | 5 => { // INSTR_HERE
-                                unimplemented!()
-                            },
-                            0 => { // INSTR_HERE
-                                unimplemented!()
-                            },
-                            _ => unimplemented!(), // Illegal
-                        }
-                    },
-                    5 => {
-                        match instr.bbb() {
-                            0 | 1 | 3 | 5 | 7 => { // INSTR_HERE
-                                unimplemented!()
-                            },
-                            0 => { // INSTR_HERE
-                                unimplemented!()
-                            },
-                            0 => { // INSTR_HERE
-                                unimplemented!()
-                            },
-                            _ => unimplemented!(), // Illegal
-                        }
-                    },
-                    6 => {
-                        match instr.bbb() {
-                            0 | 1 | 3 => { // INSTR_HERE
-                                unimplemented!()
-                            },
-                            0 => { // INSTR_HERE
-                                unimplemented!()
-                            },
-                            0 => { // INSTR_HERE
-                                unimplemented!()
-                            },
-                            0 => { // INSTR_HERE
-                                unimplemented!()
-                            },
-                            _ => unimplemented!(), // Illegal
-                        }
-                    },
-                    7 => {
-                        match instr.bbb() {
-                            0 | 1 | 3 => { // INSTR_HERE
-                                unimplemented!()
-                            },
-                            0 => { // INSTR_HERE
-                                unimplemented!()
-                            },
-                            0 => { // INSTR_HERE
-                                unimplemented!()
-                            },
-                            0 => { // INSTR_HERE
-                                unimplemented!()
-                            },
-                            _ => unimplemented!(), // Illegal
-                        }
-                    },
-                    _ => panic!("Error: word::aaa() gave a number greater than 7")
-                }
-            },
+    #[inline]
+    /// Condition of a branch instruction
+    fn branch_condition(&self, mnemonic: Mnemonic) -> bool {
+        match mnemonic {
+            Mnemonic::BCC => !self.C(),
+            Mnemonic::BCS => self.C(),
+            Mnemonic::BEQ => self.Z(),
+            Mnemonic::BMI => self.N(),
+            Mnemonic::BNE => !self.Z(),
+            Mnemonic::BPL => !self.N(),
+            Mnemonic::BVC => !self.V(),
+            Mnemonic::BVS => self.V(),
+            Mnemonic::BRA => true,
+            _ => unreachable!("{:?} is not a branch", mnemonic),
+        }
+    }
 
+    // Decoding is driven by the opcode table shared with the assembler and the disassembler.
+    // Returns the number of cycles taken by the instruction
+    fn alternate_exec(&mut self, instr: word) -> u8 {
 
-            1 => {
-                match instr.aaa() {
-                    0 => { // ORA
-                        self.a = self.or(self.a, operand.unwrap());
-                    },
-                    1 => { // AND
-                        self.and(self.a, operand.unwrap());
-                    },
-                    2 => { // EOR
-                        self.eor(self.a, operand.unwrap());
-                    },
-                    3 => { // ADC
-                        self.add_carry(operand.unwrap());
-                    },
-                    4 => { // STA
-                        self.store(address.unwrap(), self.a);
-                    },
-                    5 => { // LDA
-                        self.a = operand.unwrap();
-                    },
-                    6 => {
-                        self.compare(self.a, operand.unwrap());
-                    },
-                    7 => {
-                        self.sub_carry(operand.unwrap());
-                    },
-                    _ => panic!("Error: word::aaa() gave a number greater than 7")
-                }
-            }
+        let opcode = self.decode(instr);
+        let mut branch_penalty = 0u8;
+        let decimal_penalty = self.decimal_penalty(opcode.mnemonic);
 
+        // Compute operand
+        let ctx = AddressingContext { pc: self.pc, x: self.x, y: self.y, fixed_indirect_jmp: self.is_65c02() };
+        let effective = addressing::effective_address(opcode.mode, ctx, |addr| self.load(addr));
+        let address = effective.map(|ea| ea.address);
+        let page_crossed = effective.is_some_and(|ea| ea.page_crossed);
+        // Only the reads the instruction performs are done: stores and jumps never read their
+        // target, which matters for IO registers. The branch offset of BBR and BBS is read once PC has been advanced
+        let reads_operand = match opcode.mnemonic {
+            Mnemonic::JMP | Mnemonic::JSR => false,
+            mnemonic => mnemonic.access() != Access::Write,
+        };
+        let operand = address.filter(|_| reads_operand).map(|addr| self.load(addr));
 
+        // From here on, PC points to the next instruction (this is what branches and JSR expect)
+        self.pc = self.pc.cl_add(doubleword::from(opcode.bytes as u16));
 
-            2 => {
-                match instr.aaa() {
-                    0 => { // ASL
-                        unimplemented!();
-                    },
-                    1 => { // ROL
-                        unimplemented!();
-                    },
-                    2 => { // LSR
-                        unimplemented!();
-                    },
-                    3 => { // ROR
-                        unimplemented!();
-                    },
-                    4 => {
-                        match instr.bbb() {
-                            1 | 3 | 5 => { // STX
-
-                            },
-                            2 => { // TXA
-                                unimplemented!();
-                            }
-                            6 => { // TXS
-
-                            },
-                            _=> { // Illegal
-                                unimplemented!();
-                            }
-                        }
-                    },
-                    5 => {
-
-                        match instr.bbb() {
-                            0 | 1 | 3 | 5 | 7 => { // LDX
-                                self.x = operand.unwrap();
-                            },
-                            2 => { // TAX (unavoidable)
-                                unimplemented!()
-                            },
-                            6 => { // TSX
-                                unimplemented!()
-                            }
-                            _ => unimplemented!(),
-                        }
-                        
+        // operation itself
+        match opcode.mnemonic {
+            Mnemonic::BCC | Mnemonic::BCS | Mnemonic::BEQ | Mnemonic::BMI | Mnemonic::BNE
+            | Mnemonic::BPL | Mnemonic::BVC | Mnemonic::BVS | Mnemonic::BRA => {
+                branch_penalty = self.branch_on(self.branch_condition(opcode.mnemonic), operand.unwrap());
+            },
+            Mnemonic::BBR | Mnemonic::BBS => {
+                let bit_set = (operand.unwrap() & Self::bit_mask(instr)).native_value() != 0;
+                let offset = self.load(self.pc.cl_sub(doubleword::from(1u16)));
+                branch_penalty = self.branch_on(bit_set == (opcode.mnemonic == Mnemonic::BBS), offset);
+            },
+            Mnemonic::BRK => {
+                // BRK skips its padding byte
+                self.enter_interrupt(self.pc.cl_add(doubleword::from(1u16)), true);
+            },
+            Mnemonic::JAM => self.jammed = true,
+            Mnemonic::JMP => self.pc = address.unwrap(),
+            Mnemonic::JSR => {
+                // Push (Next Instruction Address) - 1 to stay canonical with the original implementation.
+                // RTS will then go back to that address + 1
+                self.push_doubleword(self.pc.cl_sub(doubleword::from(1u16)));
+                self.pc = address.unwrap();
+            },
+            Mnemonic::PHA => self.push_word(self.a),
+            Mnemonic::PHP => self.push_word(self.p | B_BIT | U_BIT),
+            Mnemonic::PHX => self.push_word(self.x),
+            Mnemonic::PHY => self.push_word(self.y),
+            Mnemonic::PLA => {
+                self.a = self.pull_word();
+                self.update_flags_zn(self.a);
+            },
+            Mnemonic::PLP => {
+                let p = self.pull_word();
+                self.p = (p & !B_BIT) | U_BIT;
+            },
+            Mnemonic::PLX => {
+                self.x = self.pull_word();
+                self.update_flags_zn(self.x);
+            },
+            Mnemonic::PLY => {
+                self.y = self.pull_word();
+                self.update_flags_zn(self.y);
+            },
+            Mnemonic::RTI => {
+                let p = self.pull_word();
+                self.p = (p & !B_BIT) | U_BIT;
+                self.pc = self.pull_doubleword();
+            },
+            Mnemonic::RTS => self.pc = self.pull_doubleword().cl_add(doubleword::from(1u16)),
+            Mnemonic::STP => self.stopped = true,
+            Mnemonic::WAI => self.waiting = true,
+            _ => match opcode.mode {
+                AddressingMode::Implied | AddressingMode::Accumulator => self.op_implied(instr),
+                _ => match opcode.mnemonic.access() {
+                    Access::Read => self.op_read(instr, operand.unwrap()),
+                    Access::Write => {
+                        let (addr, val) = self.op_write(instr, address.unwrap(), page_crossed);
+                        self.store(addr, val);
                     },
-                    6 => {
-                        match instr.bbb() {
-                            1 | 3 | 5 | 7 => { // DEC
-                                unimplemented!();
-                            }
-                            2 => { // DEX
-                                unimplemented!();
-                            }
-                            _ => unimplemented!()  // Illegal
-                        }
+                    Access::ReadModifyWrite => {
+                        let val = self.op_modify(instr, operand.unwrap());
+                        self.store(address.unwrap(), val);
                     },
-                    7 => {
-                        match instr.bbb() {
-                            1 | 3 | 5 | 7 => { // INC
-                                unimplemented!()
-                            },
-                            2 => { // NOP
-                                unimplemented!()
-                            },
-                            _ => unimplemented!() // Illegal
-                        }
-                    }
-                    _ => panic!("Error: word::aaa() gave a number greater than 7")
-                }
-            }
-            _ => unimplemented!(), // Illegal, c == 3 is never used in original 6502
-        }
-        self.pc = self.pc + pc_offset;
-    }
-
-    // fn exec(&mut self, instr: word) {
-    //     // Matrix evaluation inspired by https://www.masswerk.at/6502/6502_instruction_set.html
-    //     match low_nibble(instr) {
-    //         0x0 => {
-    //             match high_nibble(instr) {
-    //                 0x0 => { // BRK
-    //                     unimplemented!();
-    //                 },
-    //                 0x1 => { // BPL
-    //                     unimplemented!();
-    //                 },
-    //                 0x2 => { // JSR
-    //                     // Push (Next Instruction Address) -1 to stay canoncial with original implementation.
-    //                     // RTS will then go back to that address + 1
-    //                     self.push_doubleword(self.pc + 2u8); 
-    //                     let lo = self.mem.load(self.pc + 1u8);
-    //                     let hi = self.mem.load(self.pc + 2u8);
-    //                     self.pc = doubleword::from_words(hi, lo);
-    //                 },
-    //                 0x3 => { // BMI
-    //                     self.branch_on(self.N());
-    //                 },
-    //                 0x4 => { // RTI
-    //                     unimplemented!();
-    //                 },
-    //                 0x5 => { // BVC
-    //                     self.branch_on(!self.V());
-    //                 },
-    //                 0x6 => { // RTS
-    //                     self.pc = self.pull_doubleword();
-    //                     self.advance_pc_1();
-    //                 },
-    //                 0x7 => { // BVS
-    //                     self.branch_on(self.V());
-    //                 },
-    //                 0x8 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x9 => { // BCC
-    //                     self.branch_on(!self.C());
-    //                 },
-    //                 0xA => { // LDY #
-    //                     let addr = self.load(self.pc + 1u8);
-    //                     self.y = self.load(addr.as_doubleword());
-    //                 },
-    //                 0xB => { // BCS
-    //                     self.branch_on(self.C());
-    //                 },
-    //                 0xC => { // CPY #
-    //                     let rhs = self.load(self.pc + 1u8);
-    //                     self.compare(self.y, rhs);
-    //                     self.advance_pc_2();
-    //                 },
-    //                 0xD => { // BNE
-    //                     self.branch_on(!self.Z());
-    //                 },
-    //                 0xE => { // CPX #
-    //                     let rhs = self.load(self.pc + 1u8);
-    //                     self.compare(self.x, rhs);
-    //                     self.advance_pc_2();
-    //                 },
-    //                 0xF => { // BEQ rel
-    //                     self.branch_on(self.Z());
-    //                 },
-    //                 _ => panic!("Error: high_nibble() failed to convert to single hexadecimal number (i.e.) <= 0xF"),
-
-    //             }
-    //         },
-    //         0x1 => { // ind addressing
-    //             // TODO: factor out common code
-    //             match high_nibble(instr) {
-    //                 0x0 => { // ORA X
-    //                     let val = self.indirect_value_x();
-    //                     self.a = self.a | val;
-    //                     self.update_flags_zn(self.a);
-    //                 },
-    //                 0x1 => { // ORA Y
-    //                     let val = self.indirect_value_y();
-    //                     self.a = self.a | val;
-    //                     self.update_flags_zn(self.a);
-    //                 },
-    //                 0x2 => { // AND X
-    //                     let val = self.indirect_value_x();
-    //                     self.a = self.a & val;
-    //                     self.update_flags_zn(self.a);
-    //                 },
-    //                 0x3 => { // AND Y
-    //                     let val = self.indirect_value_y();
-    //                     self.a = self.a & val;
-    //                     self.update_flags_zn(self.a);
-    //                 },
-    //                 0x4 => { // EOR X
-    //                     let val = self.indirect_value_x();
-    //                     self.a = self.a ^ val;
-    //                     self.update_flags_zn(self.a);
-    //                 },
-    //                 0x5 => { // EOR Y
-    //                     let val = self.indirect_value_y();
-    //                     self.a = self.a ^ val;
-    //                     self.update_flags_zn(self.a);
-    //                 },
-    //                 0x6 => { // ADC X
-    //                     let val = self.indirect_value_x();
-    //                     self.add_carry(val);
-    //                 },
-    //                 0x7 => { // ADC Y
-    //                     let val = self.indirect_value_y();
-    //                     self.add_carry(val);
-    //                 },
-    //                 0x8 => { // STA X
-    //                     let addr = self.indirect_value_x().as_doubleword();
-    //                     self.store(addr, self.a);
-    //                 },
-    //                 0x9 => { // STA Y
-    //                     let addr = self.indirect_value_y().as_doubleword();
-    //                     self.store(addr, self.a);
-    //                 },
-    //                 0xA => { // LDA X
-    //                     let addr = self.indirect_value_x().as_doubleword();
-    //                     self.a = self.load(addr);
-    //                 },
-    //                 0xB => { // LDA Y
-    //                     let addr = self.indirect_value_y().as_doubleword();
-    //                     self.a = self.load(addr);
-    //                 },
-    //                 0xC => { // CMP X
-    //                     let val = self.indirect_value_x();
-    //                     self.compare(self.a, val);
-    //                 },
-    //                 0xD => { // CMP Y
-    //                     let val = self.indirect_value_y();
-    //                     self.compare(self.a, val);
-    //                 },
-    //                 0xE => { // SBC X
-    //                     let addr = self.indirect_value_x();
-    //                     self.sbc(addr);
-    //                 },
-    //                 0xF => { // SBC Y
-    //                     let addr = self.indirect_value_y();
-    //                     self.sbc(addr);
-    //                 },
-    //                 _ => panic!("Error: high_nibble() failed to convert to single hexadecimal number (i.e.) <= 0xF"),
-
-    //             }
-    //             self.advance_pc_2();
-    //         },
-    //         0x2 => {
-    //             match high_nibble(instr) {
-    //                 0x0 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x1 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x2 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x3 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x4 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x5 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x6 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x7 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x8 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x9 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xA => { // LDX #
-    //                     self.x = self.immediate_value();
-    //                     self.advance_pc_2();
-    //                 },
-    //                 0xB => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xC => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xD => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xE => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xF => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 _ => panic!("Error: high_nibble() failed to convert to single hexadecimal number (i.e.) <= 0xF"),
-
-    //             }
-    //         },
-    //         0x3 => {
-    //             match high_nibble(instr) {
-    //                 0x0 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x1 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x2 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x3 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x4 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x5 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x6 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x7 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x8 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x9 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xA => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xB => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xC => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xD => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xE => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xF => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 _ => panic!("Error: high_nibble() failed to convert to single hexadecimal number (i.e.) <= 0xF"),
-
-    //             }
-    //         },
-    //         0x4 => { // zpg
-    //             match high_nibble(instr) {
-    //                 0x0 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x1 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x2 => { // BIT
-    //                     let val = self.load(self.pc + 1u8);
-    //                     let n_flag = (val & 0b10000000).native_value() != 0;
-    //                     let v_flag = (val & 0b01000000).native_value() != 0;
-    //                     self.update_N(n_flag);
-    //                     self.update_V(v_flag);
-    //                     let and = self.a & val;
-    //                     self.update_Z(and.native_value() == 0);
-    //                     self.advance_pc_2();
-    //                 },
-    //                 0x3 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x4 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x5 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x6 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x7 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x8 => { // STY zpg
-    //                     let addr = self.zeropage_address();
-    //                     self.store(addr, self.y);
-    //                     self.advance_pc_2();
-    //                 },
-    //                 0x9 => { // STY zpg X
-    //                     let addr = self.zeropage_address_x();
-    //                     self.store(addr, self.y);
-    //                     self.advance_pc_2();
-    //                 },
-    //                 0xA => { // LDY zpg
-    //                     let addr = self.zeropage_address();
-    //                     self.y = self.load(addr);
-    //                     self.update_flags_zn(self.y);
-    //                     self.advance_pc_2();
-    //                 },
-    //                 0xB => { // LDY zpg X
-    //                     let val = self.zeropage_value_x();
-    //                     self.y = val;
-    //                     self.update_flags_zn(self.y);
-    //                     self.advance_pc_2();
-    //                 },
-    //                 0xC => { // CPY zpg
-    //                     let val = self.zeropage_value();
-    //                     self.compare(self.y, val);
-    //                     self.advance_pc_2();
-    //                 },
-    //                 0xD => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xE => { // CPX zpg
-    //                     let val = self.zeropage_value();
-    //                     self.compare(self.x, val);
-    //                     self.advance_pc_2();
-    //                 },
-    //                 0xF => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 _ => panic!("Error: high_nibble() failed to convert to single hexadecimal number (i.e.) <= 0xF"),
-
-    //             }
-    //         },
-    //         0x5 => { // zpg
-    //             match high_nibble(instr) {
-    //                 0x0 => { // ORA
-    //                     let val = self.zeropage_value();
-    //                     self.a = self.or(self.a, val);
-    //                     self.advance_pc_2();
-    //                 },
-    //                 0x1 => { // ORA X
-    //                     let val = self.zeropage_value_x();
-    //                     self.a = self.or(self.a, val);
-    //                     self.advance_pc_2();
-    //                 },
-    //                 0x2 => { // AND
-    //                     let val = self.zeropage_value();
-    //                     self.a = self.and(self.a, val);
-    //                     self.advance_pc_2();
-    //                 },
-    //                 0x3 => { // AND X
-    //                     let addr = self.zeropage_value_x();
-    //                     self.a = self.and(self.a, addr);
-    //                     self.advance_pc_2();
-    //                 },
-    //                 0x4 => { // EOR
-    //                     let val = self.zeropage_value();
-    //                     self.a = self.eor(self.a, val);
-    //                     self.advance_pc_2();
-    //                 },
-    //                 0x5 => { // EOR X
-    //                     let val = self.zeropage_value_x();
-    //                     self.a = self.eor(self.a, val);
-    //                     self.advance_pc_2();
-    //                 },
-    //                 0x6 => { // ADC
-    //                     let val = self.zeropage_value();
-    //                     self.a = self.add_carry(val);
-    //                     self.advance_pc_2();
-    //                 },
-    //                 0x7 => { // ADC X
-    //                     let val = self.zeropage_value_x();
-    //                     self.a = self.add_carry(val);
-    //                     self.advance_pc_2();
-    //                 },
-    //                 0x8 => { // STA
-    //                     let addr = self.zeropage_address();
-    //                     self.store(addr, self.a);
-    //                     self.advance_pc_2();
-    //                 },
-    //                 0x9 => { // STA X
-    //                     let addr = self.zeropage_address_x();
-    //                     self.store(addr, self.a);
-    //                     self.advance_pc_2();
-    //                 },
-    //                 0xA => { // LDA
-    //                     let val = self.zeropage_value();
-    //                     self.a = val;
-    //                     self.advance_pc_2();
-    //                 },
-    //                 0xB => { // LDA X
-    //                     let val = self.zeropage_value_x();
-    //                     self.a = val;
-    //                     self.advance_pc_2();
-    //                 },
-    //                 0xC => { // CMP
-    //                     let val = self.zeropage_value();
-    //                     self.compare(self.a, val);
-    //                     self.advance_pc_2();
-    //                 },
-    //                 0xD => { // CMP X
-    //                     let val = self.zeropage_value_x();
-    //                     self.compare(self.a, val);
-    //                     self.advance_pc_2();
-    //                 },
-    //                 0xE => { // SBC
-    //                     let val = self.zeropage_value();
-    //                     self.a = self.sub_carry(val);
-    //                     self.advance_pc_2();
-    //                 },
-    //                 0xF => { // SBC X
-    //                     let val = self.zeropage_value_x();
-    //                     self.a = self.sub_carry(val);
-    //                     self.advance_pc_2();
-    //                 },
-    //                 _ => panic!("Error: high_nibble() failed to convert to single hexadecimal number (i.e.) <= 0xF"),
-
-    //             }
-    //         },
-    //         0x6 => { // zpg
-    //             // TODO: factor out common code here(e.g. self.zeropage_value())
-    //             match high_nibble(instr) {
-    //                 0x0 => { // ASL
-    //                     let zpg = self.zeropage_value();
-    //                     let new_val = self.asl(zpg);
-    //                     let addr = self.zeropage_address();
-    //                     self.store(addr, new_val);
-    //                 },
-    //                 0x1 => { // ASL X
-    //                     let zpg = self.zeropage_value_x();
-    //                     let new_val = self.asl(zpg);
-    //                     let addr = self.zeropage_address_x();
-    //                     self.store(addr, new_val);
-    //                 },
-    //                 0x2 => { // ROL
-    //                     let zpg = self.zeropage_value();
-    //                     let new_val = self.rol(zpg);
-    //                     let addr = self.zeropage_address();
-    //                     self.store(addr, new_val);
-    //                 },
-    //                 0x3 => { // ROL X
-    //                     let zpg = self.zeropage_value_x();
-    //                     let new_val = self.rol(zpg);
-    //                     let addr = self.zeropage_address_x();
-    //                     self.store(addr, new_val);
-    //                 },
-    //                 0x4 => { // LSR
-    //                     let zpg = self.zeropage_value();
-    //                     let new_val = self.lsr(zpg);
-    //                     let addr = self.zeropage_address();
-    //                     self.store(addr, new_val);
-    //                 },
-    //                 0x5 => { // LSR X
-    //                     let zpg = self.zeropage_value_x();
-    //                     let new_val = self.lsr(zpg);
-    //                     let addr = self.zeropage_address_x();
-    //                     self.store(addr, new_val);
-    //                 },
-    //                 0x6 => { // ROR
-    //                     let zpg = self.zeropage_value();
-    //                     let new_val = self.ror(zpg);
-    //                     let addr = self.zeropage_address();
-    //                     self.store(addr, new_val);
-    //                 },
-    //                 0x7 => { // ROR X
-    //                     let zpg = self.zeropage_value_x();
-    //                     let new_val = self.ror(zpg);
-    //                     let addr = self.zeropage_address_x();
-    //                     self.store(addr, new_val);
-    //                 },
-    //                 0x8 => { // STX
-    //                     let addr = self.zeropage_address();
-    //                     self.store(addr, self.x);
-    //                 },
-    //                 0x9 => { // STX Y
-    //                     let addr = self.zeropage_address_y();
-    //                     self.store(addr, self.x);
-    //                 },
-    //                 0xA => { // LDX
-    //                     self.x = self.zeropage_value();
-    //                 },
-    //                 0xB => { // LDX Y
-    //                     self.x = self.zeropage_value_y();
-    //                 },
-    //                 0xC => { // DEC
-    //                     let addr = self.zeropage_address();
-    //                     let val = self.zeropage_value();
-    //                     self.store(addr, val - 1);
-    //                 },
-    //                 0xD => { // DEC X
-    //                     let addr = self.zeropage_address_x();
-    //                     let val = self.zeropage_value_x();
-    //                     self.store(addr, val - 1);
-    //                 },
-    //                 0xE => { // INC
-    //                     let addr = self.zeropage_address();
-    //                     let val = self.zeropage_value();
-    //                     self.store(addr, val + 1);
-    //                 },
-    //                 0xF => { // INC X
-    //                     let addr = self.zeropage_address_x();
-    //                     let val = self.zeropage_value_x();
-    //                     self.store(addr, val + 1);
-    //                 },
-    //                 _ => panic!("Error: high_nibble() failed to convert to single hexadecimal number (i.e.) <= 0xF"),
-
-    //             }
-    //         },
-    //         0x7 => { // ALL ILLEGAL
-    //             match high_nibble(instr) {
-    //                 0x0 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x1 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x2 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x3 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x4 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x5 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x6 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x7 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x8 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x9 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xA => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xB => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xC => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xD => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xE => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xF => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 _ => panic!("Error: high_nibble() failed to convert to single hexadecimal number (i.e.) <= 0xF"),
-
-    //             }
-    //         },
-    //         0x8 => { // impl
-    //             match high_nibble(instr) {
-    //                 0x0 => { // PHP
-    //                     self.push_word(self.p);
-    //                 },
-    //                 0x1 => { // CLC
-    //                     self.clear_C();
-    //                 },
-    //                 0x2 => { // PLP
-    //                     self.p = self.pull_word();
-    //                 },
-    //                 0x3 => { // SEC
-    //                     self.set_C();
-    //                 },
-    //                 0x4 => { // PHA
-    //                     self.push_word(self.a);
-    //                 },
-    //                 0x5 => { // CLI
-    //                     self.clear_I();
-    //                 },
-    //                 0x6 => { // PLA
-    //                     self.a = self.pull_word();
-    //                 },
-    //                 0x7 => { // SEI
-    //                     self.set_I();
-    //                 },
-    //                 0x8 => { // DEY
-    //                     self.y = self.y - 1;
-    //                 },
-    //                 0x9 => { // TYA
-    //                     self.a = self.y;
-    //                 },
-    //                 0xA => { // TAY
-    //                     self.y = self.a;
-    //                 },
-    //                 0xB => { // CLV
-    //                     self.clear_V()
-    //                 },
-    //                 0xC => { // INY
-    //                     self.y = self.y + 1;
-    //                 },
-    //                 0xD => { // CLD
-    //                     // Unused on NES
-    //                 },
-    //                 0xE => { // INX
-    //                     self.x = self.x + 1;
-    //                 },
-    //                 0xF => { // SED
-    //                     // Unused on NES
-    //                 },
-    //                 _ => panic!("Error: high_nibble() failed to convert to single hexadecimal number (i.e.) <= 0xF"),
-
-    //             }
-    //         },
-    //         0x9 => { // abs
-    //             match high_nibble(instr) {
-    //                 0x0 => { // ORA #
-    //                     let val = self.immediate_value();
-    //                     self.a = self.or(self.a, val);
-    //                     self.advance_pc_2();
-    //                 },
-    //                 0x1 => { // ORA Y
-    //                     self.a = self.or(self.a, self.y);
-    //                     unimplemented!();
-    //                 },
-    //                 0x2 => { // AND #
-    //                     let val = self.immediate_value();
-    //                     self.a = self.and(self.a, val);
-    //                     self.advance_pc_2();
-    //                 },
-    //                 0x3 => { // AND Y
-    //                     unimplemented!();
-    //                 },
-    //                 0x4 => { // EOR #
-    //                     unimplemented!();
-    //                 },
-    //                 0x5 => { // EOR Y
-    //                     unimplemented!();
-    //                 },
-    //                 0x6 => { // ADC #
-    //                     unimplemented!();
-    //                 },
-    //                 0x7 => { // ADC Y
-    //                     unimplemented!();
-    //                 },
-    //                 0x8 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x9 => { // STA Y
-    //                     unimplemented!();
-    //                 },
-    //                 0xA => { // LDA #
-    //                     unimplemented!();
-    //                 },
-    //                 0xB => { // LDA Y
-    //                     unimplemented!();
-    //                 },
-    //                 0xC => { // CMP #
-    //                     unimplemented!();
-    //                 },
-    //                 0xD => { // CMP Y
-    //                     unimplemented!();
-    //                 },
-    //                 0xE => { // SBS #
-    //                     unimplemented!();
-    //                 },
-    //                 0xF => { // SBS Y
-    //                     unimplemented!();
-    //                 },
-    //                 _ => panic!("Error: high_nibble() failed to convert to single hexadecimal number (i.e.) <= 0xF"),
-
-    //             }
-    //         },
-    //         0xA => {
-    //             match high_nibble(instr) {
-    //                 0x0 => { // ASL A
-    //                     unimplemented!();
-    //                 },
-    //                 0x1 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x2 => { // ROL A
-    //                     unimplemented!();
-    //                 },
-    //                 0x3 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x4 => { // LSR A
-    //                     unimplemented!();
-    //                 },
-    //                 0x5 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x6 => { // ROR A
-    //                     unimplemented!();
-    //                 },
-    //                 0x7 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x8 => { // TXA
-    //                     unimplemented!();
-    //                 },
-    //                 0x9 => { // TXS
-    //                     unimplemented!();
-    //                 },
-    //                 0xA => { // TAX
-    //                     unimplemented!();
-    //                 },
-    //                 0xB => { // TSX
-    //                     unimplemented!();
-    //                 },
-    //                 0xC => { // DEX
-    //                     unimplemented!();
-    //                 },
-    //                 0xD => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xE => { // NOP
-    //                     unimplemented!();
-    //                 },
-    //                 0xF => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 _ => panic!("Error: high_nibble() failed to convert to single hexadecimal number (i.e.) <= 0xF"),
-
-    //             }
-    //         },
-    //         0xB => {
-    //             match high_nibble(instr) {
-    //                 0x0 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x1 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x2 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x3 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x4 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x5 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x6 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x7 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x8 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x9 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xA => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xB => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xC => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xD => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xE => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xF => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 _ => panic!("Error: high_nibble() failed to convert to single hexadecimal number (i.e.) <= 0xF"),
-
-    //             }
-    //         },
-    //         0xC => { // abs
-    //             match high_nibble(instr) {
-    //                 0x0 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x1 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x2 => { // BIT
-    //                     unimplemented!();
-    //                 },
-    //                 0x3 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x4 => { // JMP
-    //                     unimplemented!();
-    //                 },
-    //                 0x5 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x6 => { // JMP ind
-    //                     unimplemented!();
-    //                 },
-    //                 0x7 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x8 => { // STY
-    //                     unimplemented!();
-    //                 },
-    //                 0x9 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xA => { // LDY
-    //                     unimplemented!();
-    //                 },
-    //                 0xB => { // LDY X
-    //                     unimplemented!();
-    //                 },
-    //                 0xC => { // CPY
-    //                     unimplemented!();
-    //                 },
-    //                 0xD => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xE => { // CPX
-    //                     unimplemented!();
-    //                 },
-    //                 0xF => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 _ => panic!("Error: high_nibble() failed to convert to single hexadecimal number (i.e.) <= 0xF"),
-
-    //             }
-    //         },
-    //         0xD => { // abs
-    //             match high_nibble(instr) {
-    //                 0x0 => { // ORA
-    //                     unimplemented!();
-    //                 },
-    //                 0x1 => { // ORA X
-    //                     unimplemented!();
-    //                 },
-    //                 0x2 => { // AND
-    //                     unimplemented!();
-    //                 },
-    //                 0x3 => { // AND X
-    //                     unimplemented!();
-    //                 },
-    //                 0x4 => { // EOR
-    //                     unimplemented!();
-    //                 },
-    //                 0x5 => { // EOR X
-    //                     unimplemented!();
-    //                 },
-    //                 0x6 => { // ADC
-    //                     unimplemented!();
-    //                 },
-    //                 0x7 => { // ADC X
-    //                     unimplemented!();
-    //                 },
-    //                 0x8 => { // STA
-    //                     unimplemented!();
-    //                 },
-    //                 0x9 => { // STA X
-    //                     unimplemented!();
-    //                 },
-    //                 0xA => { // LDA
-    //                     unimplemented!();
-    //                 },
-    //                 0xB => { // LDA X
-    //                     unimplemented!();
-    //                 },
-    //                 0xC => { // CMP
-    //                     unimplemented!();
-    //                 },
-    //                 0xD => { //  CMP X
-    //                     unimplemented!();
-    //                 },
-    //                 0xE => { // SBC
-    //                     unimplemented!();
-    //                 },
-    //                 0xF => { // SBC X
-    //                     unimplemented!();
-    //                 },
-    //                 _ => panic!("Error: high_nibble() failed to convert to single hexadecimal number (i.e.) <= 0xF"),
-
-    //             }
-    //         },
-    //         0xE => {
-    //             match high_nibble(instr) {
-    //                 0x0 => { // ASL
-    //                     unimplemented!();
-    //                 },
-    //                 0x1 => { // ASL X
-    //                     unimplemented!();
-    //                 },
-    //                 0x2 => { // ROL
-    //                     unimplemented!();
-    //                 },
-    //                 0x3 => { // ROL X
-    //                     unimplemented!();
-    //                 },
-    //                 0x4 => { // LSR
-    //                     unimplemented!();
-    //                 },
-    //                 0x5 => { // LSR X
-    //                     unimplemented!();
-    //                 },
-    //                 0x6 => { // ROR
-    //                     unimplemented!();
-    //                 },
-    //                 0x7 => { // ROR X
-    //                     unimplemented!();
-    //                 },
-    //                 0x8 => { // STX
-    //                     unimplemented!();
-    //                 },
-    //                 0x9 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xA => { // LDX
-    //                     unimplemented!();
-    //                 },
-    //                 0xB => { // LDX Y
-    //                     unimplemented!();
-    //                 },
-    //                 0xC => { // DEC
-    //                     unimplemented!();
-    //                 },
-    //                 0xD => { // DEC X
-    //                     unimplemented!();
-    //                 },
-    //                 0xE => { // INC
-    //                     unimplemented!();
-    //                 },
-    //                 0xF => { // INC X
-    //                     unimplemented!();
-    //                 },
-    //                 _ => panic!("Error: high_nibble() failed to convert to single hexadecimal number (i.e.) <= 0xF"),
-
-    //             }
-    //         },
-    //         0xF => {
-    //             match high_nibble(instr) {
-    //                 0x0 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x1 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x2 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x3 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x4 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x5 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x6 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x7 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x8 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0x9 => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xA => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xB => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xC => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xD => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xE => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 0xF => { // Illegal
-    //                     unimplemented!();
-    //                 },
-    //                 _ => panic!("Error: high_nibble() failed to convert to single hexadecimal number (i.e.) <= 0xF"),
-
-    //             }
-    //         },
-    //         _ => panic!("Error: low_nibble() failed to convert to single hexadecimal number (i.e.) <= 0xF"),
-    //     }
-    // }
+                },
+            },
+        }
+
+        // Branches use the page_penalty flag for their own penalties
+        match opcode.mode {
+            AddressingMode::Relative | AddressingMode::ZeropageRelative => opcode.cycles + branch_penalty,
+            _ => opcode.cycles + (opcode.page_penalty && page_crossed) as u8 + decimal_penalty as u8,
+        }
+    }
 
     
 }
\ No newline at end of file
@@ -0,0 +1,66 @@
+//! nestest, the CPU test ROM of the NES: run from $C000 without a PPU, it goes through every
+//! official and most undocumented opcodes. Its golden log, captured on Nintendulator, is compared
+//! line by line with our trace. Files are looked up in `test-roms/nestest` (see the README there).
+//! They are not vendored yet and the test has not been run against the golden log, so it is
+//! ignored by default: `cargo test -- --ignored nestest` once they are in place.
+
+use std::cell::RefCell;
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+use super::datastructures::word;
+use super::datastructures::doubleword;
+use super::datastructures::InstructionStream;
+use super::CpuVariant;
+use super::FamicomMemory;
+use super::System;
+use super::IO6502;
+
+/// Entry point of the automated mode, which needs no PPU
+const AUTOMATION_START: u16 = 0xC000;
+
+const INES_MAGIC: &[u8; 4] = b"NES\x1A";
+const INES_HEADER_LEN: usize = 16;
+const PRG_BANK_LEN: usize = 0x4000;
+
+fn read_test_file(name: &str) -> Vec<u8> {
+    let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "test-roms", "nestest", name].iter().collect();
+    fs::read(&path).unwrap_or_else(|err| panic!("{}: {} (see test-roms/nestest/README.md)", path.display(), err))
+}
+
+/// The $8000-$FFFF image of an NROM cartridge, a single 16K bank being mirrored
+fn program_image(rom: &[u8]) -> Vec<word> {
+    assert!(rom.len() >= INES_HEADER_LEN && &rom[..4] == INES_MAGIC, "nestest.nes is not an iNES file");
+    let banks = rom[4] as usize;
+    assert!(banks == 1 || banks == 2, "unexpected PRG size of {} banks", banks);
+    let prg = &rom[INES_HEADER_LEN..INES_HEADER_LEN + banks * PRG_BANK_LEN];
+    prg.iter().cycle().take(2 * PRG_BANK_LEN).map(|b| word::from(*b)).collect()
+}
+
+#[test]
+#[ignore = "needs nestest.nes and nestest.log in test-roms/nestest"]
+fn nestest_golden_log() {
+    let (rom, log) = (read_test_file("nestest.nes"), read_test_file("nestest.log"));
+    let expected: Vec<String> = String::from_utf8_lossy(&log).lines().map(|line| line.trim_end().to_string()).collect();
+
+    let mut sys: System<FamicomMemory> = System::with_variant(CpuVariant::Ricoh2A03);
+    sys.mem.push_program(InstructionStream::from(program_image(&rom)));
+    sys.reset().unwrap();
+    sys.pc = doubleword::from(AUTOMATION_START);
+
+    let lines = Rc::new(RefCell::new(Vec::new()));
+    let sink = lines.clone();
+    sys.set_tracer(Some(Box::new(move |line: &str| sink.borrow_mut().push(line.to_string()))));
+
+    while lines.borrow().len() < expected.len() {
+        // Stores to the APU, IO registers and cartridge are taken or ignored, reads answered
+        // by open bus: any error is a bug
+        if let Err(err) = sys.step() {
+            panic!("nestest stopped at line {}: {}", lines.borrow().len(), err);
+        }
+    }
+
+    for (idx, (expected, actual)) in expected.iter().zip(lines.borrow().iter()).enumerate() {
+        assert!(expected == actual, "trace diverges at line {}\nexpected: {}\n     got: {}", idx + 1, expected, actual);
+    }
+}
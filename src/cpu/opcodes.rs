@@ -0,0 +1,752 @@
+use super::datastructures::word;
+use super::C_BIT;
+use super::Z_BIT;
+use super::I_BIT;
+use super::D_BIT;
+use super::V_BIT;
+use super::N_BIT;
+use super::CpuVariant;
+
+/// The 13 addressing modes of the NMOS 6502, followed by the ones added by the 65C02
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressingMode {
+    Implied,
+    Accumulator,
+    Immediate,
+    Zeropage,
+    ZeropageX,
+    ZeropageY,
+    Relative,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndirectX,
+    IndirectY,
+
+    // 65C02 only
+    /// (zp)
+    ZeropageIndirect,
+    /// (abs,X), for JMP
+    AbsoluteIndexedIndirect,
+    /// zp,rel, for BBR and BBS
+    ZeropageRelative,
+}
+
+impl AddressingMode {
+    /// Number of bytes following the opcode
+    pub const fn operand_bytes(self) -> u8 {
+        match self {
+            AddressingMode::Implied | AddressingMode::Accumulator => 0,
+            AddressingMode::Absolute | AddressingMode::AbsoluteX | AddressingMode::AbsoluteY | AddressingMode::Indirect
+            | AddressingMode::AbsoluteIndexedIndirect | AddressingMode::ZeropageRelative => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// Instruction mnemonics, documented ones first, followed by the undocumented NMOS ones and the 65C02 additions
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum Mnemonic {
+    ADC, AND, ASL, BCC, BCS, BEQ, BIT, BMI, BNE, BPL, BRK, BVC, BVS, CLC,
+    CLD, CLI, CLV, CMP, CPX, CPY, DEC, DEX, DEY, EOR, INC, INX, INY, JMP,
+    JSR, LDA, LDX, LDY, LSR, NOP, ORA, PHA, PHP, PLA, PLP, ROL, ROR, RTI,
+    RTS, SBC, SEC, SED, SEI, STA, STX, STY, TAX, TAY, TSX, TXA, TXS, TYA,
+
+    // Undocumented
+    ALR, ANC, ARR, DCP, ISC, JAM, LAS, LAX, RLA, RRA, SAX, SBX, SHA, SHX,
+    SHY, SLO, SRE, TAS, XAA,
+
+    // 65C02. The bit of RMB, SMB, BBR and BBS is given by bits 4 to 6 of their opcode
+    BBR, BBS, BRA, PHX, PHY, PLX, PLY, RMB, SMB, STP, STZ, TRB, TSB, WAI,
+}
+
+/// How an instruction uses the memory at its effective address
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadModifyWrite,
+}
+
+impl Mnemonic {
+    /// Mask of the P flags an instruction can modify. Pushing B on the stack is not considered a modification
+    pub const fn flags_affected(self) -> u8 {
+        match self {
+            Mnemonic::ADC | Mnemonic::SBC | Mnemonic::ARR | Mnemonic::ISC | Mnemonic::RRA => N_BIT | V_BIT | Z_BIT | C_BIT,
+            Mnemonic::ASL | Mnemonic::LSR | Mnemonic::ROL | Mnemonic::ROR | Mnemonic::CMP | Mnemonic::CPX
+            | Mnemonic::CPY | Mnemonic::ALR | Mnemonic::ANC | Mnemonic::DCP | Mnemonic::RLA | Mnemonic::SBX
+            | Mnemonic::SLO | Mnemonic::SRE => N_BIT | Z_BIT | C_BIT,
+            Mnemonic::AND | Mnemonic::EOR | Mnemonic::ORA | Mnemonic::DEC | Mnemonic::DEX | Mnemonic::DEY
+            | Mnemonic::INC | Mnemonic::INX | Mnemonic::INY | Mnemonic::LDA | Mnemonic::LDX | Mnemonic::LDY
+            | Mnemonic::PLA | Mnemonic::TAX | Mnemonic::TAY | Mnemonic::TSX | Mnemonic::TXA | Mnemonic::TYA
+            | Mnemonic::LAS | Mnemonic::LAX | Mnemonic::XAA | Mnemonic::PLX | Mnemonic::PLY => N_BIT | Z_BIT,
+            Mnemonic::BIT => N_BIT | V_BIT | Z_BIT,
+            Mnemonic::PLP | Mnemonic::RTI => N_BIT | V_BIT | D_BIT | I_BIT | Z_BIT | C_BIT,
+            Mnemonic::CLC | Mnemonic::SEC => C_BIT,
+            Mnemonic::CLD | Mnemonic::SED => D_BIT,
+            Mnemonic::CLI | Mnemonic::SEI | Mnemonic::BRK => I_BIT,
+            Mnemonic::CLV => V_BIT,
+            Mnemonic::TRB | Mnemonic::TSB => Z_BIT,
+            _ => 0,
+        }
+    }
+
+    /// Memory access pattern of the instruction. Control flow and stack instructions do not
+    /// go through an effective address, and are reported as reads
+    pub const fn access(self) -> Access {
+        match self {
+            Mnemonic::STA | Mnemonic::STX | Mnemonic::STY | Mnemonic::SAX | Mnemonic::SHA
+            | Mnemonic::SHX | Mnemonic::SHY | Mnemonic::TAS | Mnemonic::STZ => Access::Write,
+            Mnemonic::ASL | Mnemonic::LSR | Mnemonic::ROL | Mnemonic::ROR | Mnemonic::DEC | Mnemonic::INC
+            | Mnemonic::DCP | Mnemonic::ISC | Mnemonic::RLA | Mnemonic::RRA | Mnemonic::SLO | Mnemonic::SRE
+            | Mnemonic::TRB | Mnemonic::TSB | Mnemonic::RMB | Mnemonic::SMB => Access::ReadModifyWrite,
+            _ => Access::Read,
+        }
+    }
+}
+
+/// Everything there is to know about a single opcode
+#[derive(Clone, Copy, Debug)]
+pub struct Opcode {
+    pub mnemonic: Mnemonic,
+    pub mode: AddressingMode,
+    /// Length of the instruction in bytes, opcode included
+    pub bytes: u8,
+    /// Base number of cycles, without page crossing or branch penalties
+    pub cycles: u8,
+    /// One extra cycle is taken when indexing crosses a page (or when a branch is taken)
+    pub page_penalty: bool,
+    /// Mask of the P flags modified by the instruction
+    pub flags: u8,
+    /// Part of the documented instruction set
+    pub official: bool,
+}
+
+const fn op(mnemonic: Mnemonic, mode: AddressingMode, cycles: u8, page_penalty: bool) -> Opcode {
+    Opcode {
+        mnemonic,
+        mode,
+        bytes: 1 + mode.operand_bytes(),
+        cycles,
+        page_penalty,
+        flags: mnemonic.flags_affected(),
+        official: true,
+    }
+}
+
+const fn ill(mnemonic: Mnemonic, mode: AddressingMode, cycles: u8, page_penalty: bool) -> Opcode {
+    let mut ret = op(mnemonic, mode, cycles, page_penalty);
+    ret.official = false;
+    ret
+}
+
+impl Opcode {
+    /// For the few opcodes that modify less flags than the other ones of their mnemonic
+    const fn only_flags(mut self, flags: u8) -> Opcode {
+        self.flags = flags;
+        self
+    }
+}
+
+/// The NMOS 6502 opcode matrix, indexed by opcode. This is the single source of truth for the
+/// CPU decoder, the assembler and the disassembler
+pub static OPCODES: [Opcode; 256] = {
+    use Mnemonic::*;
+    use AddressingMode::*;
+    [
+        /* 0x00 */ op(BRK, Implied, 7, false),
+        /* 0x01 */ op(ORA, IndirectX, 6, false),
+        /* 0x02 */ ill(JAM, Implied, 2, false),
+        /* 0x03 */ ill(SLO, IndirectX, 8, false),
+        /* 0x04 */ ill(NOP, Zeropage, 3, false),
+        /* 0x05 */ op(ORA, Zeropage, 3, false),
+        /* 0x06 */ op(ASL, Zeropage, 5, false),
+        /* 0x07 */ ill(SLO, Zeropage, 5, false),
+        /* 0x08 */ op(PHP, Implied, 3, false),
+        /* 0x09 */ op(ORA, Immediate, 2, false),
+        /* 0x0A */ op(ASL, Accumulator, 2, false),
+        /* 0x0B */ ill(ANC, Immediate, 2, false),
+        /* 0x0C */ ill(NOP, Absolute, 4, false),
+        /* 0x0D */ op(ORA, Absolute, 4, false),
+        /* 0x0E */ op(ASL, Absolute, 6, false),
+        /* 0x0F */ ill(SLO, Absolute, 6, false),
+
+        /* 0x10 */ op(BPL, Relative, 2, true),
+        /* 0x11 */ op(ORA, IndirectY, 5, true),
+        /* 0x12 */ ill(JAM, Implied, 2, false),
+        /* 0x13 */ ill(SLO, IndirectY, 8, false),
+        /* 0x14 */ ill(NOP, ZeropageX, 4, false),
+        /* 0x15 */ op(ORA, ZeropageX, 4, false),
+        /* 0x16 */ op(ASL, ZeropageX, 6, false),
+        /* 0x17 */ ill(SLO, ZeropageX, 6, false),
+        /* 0x18 */ op(CLC, Implied, 2, false),
+        /* 0x19 */ op(ORA, AbsoluteY, 4, true),
+        /* 0x1A */ ill(NOP, Implied, 2, false),
+        /* 0x1B */ ill(SLO, AbsoluteY, 7, false),
+        /* 0x1C */ ill(NOP, AbsoluteX, 4, true),
+        /* 0x1D */ op(ORA, AbsoluteX, 4, true),
+        /* 0x1E */ op(ASL, AbsoluteX, 7, false),
+        /* 0x1F */ ill(SLO, AbsoluteX, 7, false),
+
+        /* 0x20 */ op(JSR, Absolute, 6, false),
+        /* 0x21 */ op(AND, IndirectX, 6, false),
+        /* 0x22 */ ill(JAM, Implied, 2, false),
+        /* 0x23 */ ill(RLA, IndirectX, 8, false),
+        /* 0x24 */ op(BIT, Zeropage, 3, false),
+        /* 0x25 */ op(AND, Zeropage, 3, false),
+        /* 0x26 */ op(ROL, Zeropage, 5, false),
+        /* 0x27 */ ill(RLA, Zeropage, 5, false),
+        /* 0x28 */ op(PLP, Implied, 4, false),
+        /* 0x29 */ op(AND, Immediate, 2, false),
+        /* 0x2A */ op(ROL, Accumulator, 2, false),
+        /* 0x2B */ ill(ANC, Immediate, 2, false),
+        /* 0x2C */ op(BIT, Absolute, 4, false),
+        /* 0x2D */ op(AND, Absolute, 4, false),
+        /* 0x2E */ op(ROL, Absolute, 6, false),
+        /* 0x2F */ ill(RLA, Absolute, 6, false),
+
+        /* 0x30 */ op(BMI, Relative, 2, true),
+        /* 0x31 */ op(AND, IndirectY, 5, true),
+        /* 0x32 */ ill(JAM, Implied, 2, false),
+        /* 0x33 */ ill(RLA, IndirectY, 8, false),
+        /* 0x34 */ ill(NOP, ZeropageX, 4, false),
+        /* 0x35 */ op(AND, ZeropageX, 4, false),
+        /* 0x36 */ op(ROL, ZeropageX, 6, false),
+        /* 0x37 */ ill(RLA, ZeropageX, 6, false),
+        /* 0x38 */ op(SEC, Implied, 2, false),
+        /* 0x39 */ op(AND, AbsoluteY, 4, true),
+        /* 0x3A */ ill(NOP, Implied, 2, false),
+        /* 0x3B */ ill(RLA, AbsoluteY, 7, false),
+        /* 0x3C */ ill(NOP, AbsoluteX, 4, true),
+        /* 0x3D */ op(AND, AbsoluteX, 4, true),
+        /* 0x3E */ op(ROL, AbsoluteX, 7, false),
+        /* 0x3F */ ill(RLA, AbsoluteX, 7, false),
+
+        /* 0x40 */ op(RTI, Implied, 6, false),
+        /* 0x41 */ op(EOR, IndirectX, 6, false),
+        /* 0x42 */ ill(JAM, Implied, 2, false),
+        /* 0x43 */ ill(SRE, IndirectX, 8, false),
+        /* 0x44 */ ill(NOP, Zeropage, 3, false),
+        /* 0x45 */ op(EOR, Zeropage, 3, false),
+        /* 0x46 */ op(LSR, Zeropage, 5, false),
+        /* 0x47 */ ill(SRE, Zeropage, 5, false),
+        /* 0x48 */ op(PHA, Implied, 3, false),
+        /* 0x49 */ op(EOR, Immediate, 2, false),
+        /* 0x4A */ op(LSR, Accumulator, 2, false),
+        /* 0x4B */ ill(ALR, Immediate, 2, false),
+        /* 0x4C */ op(JMP, Absolute, 3, false),
+        /* 0x4D */ op(EOR, Absolute, 4, false),
+        /* 0x4E */ op(LSR, Absolute, 6, false),
+        /* 0x4F */ ill(SRE, Absolute, 6, false),
+
+        /* 0x50 */ op(BVC, Relative, 2, true),
+        /* 0x51 */ op(EOR, IndirectY, 5, true),
+        /* 0x52 */ ill(JAM, Implied, 2, false),
+        /* 0x53 */ ill(SRE, IndirectY, 8, false),
+        /* 0x54 */ ill(NOP, ZeropageX, 4, false),
+        /* 0x55 */ op(EOR, ZeropageX, 4, false),
+        /* 0x56 */ op(LSR, ZeropageX, 6, false),
+        /* 0x57 */ ill(SRE, ZeropageX, 6, false),
+        /* 0x58 */ op(CLI, Implied, 2, false),
+        /* 0x59 */ op(EOR, AbsoluteY, 4, true),
+        /* 0x5A */ ill(NOP, Implied, 2, false),
+        /* 0x5B */ ill(SRE, AbsoluteY, 7, false),
+        /* 0x5C */ ill(NOP, AbsoluteX, 4, true),
+        /* 0x5D */ op(EOR, AbsoluteX, 4, true),
+        /* 0x5E */ op(LSR, AbsoluteX, 7, false),
+        /* 0x5F */ ill(SRE, AbsoluteX, 7, false),
+
+        /* 0x60 */ op(RTS, Implied, 6, false),
+        /* 0x61 */ op(ADC, IndirectX, 6, false),
+        /* 0x62 */ ill(JAM, Implied, 2, false),
+        /* 0x63 */ ill(RRA, IndirectX, 8, false),
+        /* 0x64 */ ill(NOP, Zeropage, 3, false),
+        /* 0x65 */ op(ADC, Zeropage, 3, false),
+        /* 0x66 */ op(ROR, Zeropage, 5, false),
+        /* 0x67 */ ill(RRA, Zeropage, 5, false),
+        /* 0x68 */ op(PLA, Implied, 4, false),
+        /* 0x69 */ op(ADC, Immediate, 2, false),
+        /* 0x6A */ op(ROR, Accumulator, 2, false),
+        /* 0x6B */ ill(ARR, Immediate, 2, false),
+        /* 0x6C */ op(JMP, Indirect, 5, false),
+        /* 0x6D */ op(ADC, Absolute, 4, false),
+        /* 0x6E */ op(ROR, Absolute, 6, false),
+        /* 0x6F */ ill(RRA, Absolute, 6, false),
+
+        /* 0x70 */ op(BVS, Relative, 2, true),
+        /* 0x71 */ op(ADC, IndirectY, 5, true),
+        /* 0x72 */ ill(JAM, Implied, 2, false),
+        /* 0x73 */ ill(RRA, IndirectY, 8, false),
+        /* 0x74 */ ill(NOP, ZeropageX, 4, false),
+        /* 0x75 */ op(ADC, ZeropageX, 4, false),
+        /* 0x76 */ op(ROR, ZeropageX, 6, false),
+        /* 0x77 */ ill(RRA, ZeropageX, 6, false),
+        /* 0x78 */ op(SEI, Implied, 2, false),
+        /* 0x79 */ op(ADC, AbsoluteY, 4, true),
+        /* 0x7A */ ill(NOP, Implied, 2, false),
+        /* 0x7B */ ill(RRA, AbsoluteY, 7, false),
+        /* 0x7C */ ill(NOP, AbsoluteX, 4, true),
+        /* 0x7D */ op(ADC, AbsoluteX, 4, true),
+        /* 0x7E */ op(ROR, AbsoluteX, 7, false),
+        /* 0x7F */ ill(RRA, AbsoluteX, 7, false),
+
+        /* 0x80 */ ill(NOP, Immediate, 2, false),
+        /* 0x81 */ op(STA, IndirectX, 6, false),
+        /* 0x82 */ ill(NOP, Immediate, 2, false),
+        /* 0x83 */ ill(SAX, IndirectX, 6, false),
+        /* 0x84 */ op(STY, Zeropage, 3, false),
+        /* 0x85 */ op(STA, Zeropage, 3, false),
+        /* 0x86 */ op(STX, Zeropage, 3, false),
+        /* 0x87 */ ill(SAX, Zeropage, 3, false),
+        /* 0x88 */ op(DEY, Implied, 2, false),
+        /* 0x89 */ ill(NOP, Immediate, 2, false),
+        /* 0x8A */ op(TXA, Implied, 2, false),
+        /* 0x8B */ ill(XAA, Immediate, 2, false),
+        /* 0x8C */ op(STY, Absolute, 4, false),
+        /* 0x8D */ op(STA, Absolute, 4, false),
+        /* 0x8E */ op(STX, Absolute, 4, false),
+        /* 0x8F */ ill(SAX, Absolute, 4, false),
+
+        /* 0x90 */ op(BCC, Relative, 2, true),
+        /* 0x91 */ op(STA, IndirectY, 6, false),
+        /* 0x92 */ ill(JAM, Implied, 2, false),
+        /* 0x93 */ ill(SHA, IndirectY, 6, false),
+        /* 0x94 */ op(STY, ZeropageX, 4, false),
+        /* 0x95 */ op(STA, ZeropageX, 4, false),
+        /* 0x96 */ op(STX, ZeropageY, 4, false),
+        /* 0x97 */ ill(SAX, ZeropageY, 4, false),
+        /* 0x98 */ op(TYA, Implied, 2, false),
+        /* 0x99 */ op(STA, AbsoluteY, 5, false),
+        /* 0x9A */ op(TXS, Implied, 2, false),
+        /* 0x9B */ ill(TAS, AbsoluteY, 5, false),
+        /* 0x9C */ ill(SHY, AbsoluteX, 5, false),
+        /* 0x9D */ op(STA, AbsoluteX, 5, false),
+        /* 0x9E */ ill(SHX, AbsoluteY, 5, false),
+        /* 0x9F */ ill(SHA, AbsoluteY, 5, false),
+
+        /* 0xA0 */ op(LDY, Immediate, 2, false),
+        /* 0xA1 */ op(LDA, IndirectX, 6, false),
+        /* 0xA2 */ op(LDX, Immediate, 2, false),
+        /* 0xA3 */ ill(LAX, IndirectX, 6, false),
+        /* 0xA4 */ op(LDY, Zeropage, 3, false),
+        /* 0xA5 */ op(LDA, Zeropage, 3, false),
+        /* 0xA6 */ op(LDX, Zeropage, 3, false),
+        /* 0xA7 */ ill(LAX, Zeropage, 3, false),
+        /* 0xA8 */ op(TAY, Implied, 2, false),
+        /* 0xA9 */ op(LDA, Immediate, 2, false),
+        /* 0xAA */ op(TAX, Implied, 2, false),
+        /* 0xAB */ ill(LAX, Immediate, 2, false),
+        /* 0xAC */ op(LDY, Absolute, 4, false),
+        /* 0xAD */ op(LDA, Absolute, 4, false),
+        /* 0xAE */ op(LDX, Absolute, 4, false),
+        /* 0xAF */ ill(LAX, Absolute, 4, false),
+
+        /* 0xB0 */ op(BCS, Relative, 2, true),
+        /* 0xB1 */ op(LDA, IndirectY, 5, true),
+        /* 0xB2 */ ill(JAM, Implied, 2, false),
+        /* 0xB3 */ ill(LAX, IndirectY, 5, true),
+        /* 0xB4 */ op(LDY, ZeropageX, 4, false),
+        /* 0xB5 */ op(LDA, ZeropageX, 4, false),
+        /* 0xB6 */ op(LDX, ZeropageY, 4, false),
+        /* 0xB7 */ ill(LAX, ZeropageY, 4, false),
+        /* 0xB8 */ op(CLV, Implied, 2, false),
+        /* 0xB9 */ op(LDA, AbsoluteY, 4, true),
+        /* 0xBA */ op(TSX, Implied, 2, false),
+        /* 0xBB */ ill(LAS, AbsoluteY, 4, true),
+        /* 0xBC */ op(LDY, AbsoluteX, 4, true),
+        /* 0xBD */ op(LDA, AbsoluteX, 4, true),
+        /* 0xBE */ op(LDX, AbsoluteY, 4, true),
+        /* 0xBF */ ill(LAX, AbsoluteY, 4, true),
+
+        /* 0xC0 */ op(CPY, Immediate, 2, false),
+        /* 0xC1 */ op(CMP, IndirectX, 6, false),
+        /* 0xC2 */ ill(NOP, Immediate, 2, false),
+        /* 0xC3 */ ill(DCP, IndirectX, 8, false),
+        /* 0xC4 */ op(CPY, Zeropage, 3, false),
+        /* 0xC5 */ op(CMP, Zeropage, 3, false),
+        /* 0xC6 */ op(DEC, Zeropage, 5, false),
+        /* 0xC7 */ ill(DCP, Zeropage, 5, false),
+        /* 0xC8 */ op(INY, Implied, 2, false),
+        /* 0xC9 */ op(CMP, Immediate, 2, false),
+        /* 0xCA */ op(DEX, Implied, 2, false),
+        /* 0xCB */ ill(SBX, Immediate, 2, false),
+        /* 0xCC */ op(CPY, Absolute, 4, false),
+        /* 0xCD */ op(CMP, Absolute, 4, false),
+        /* 0xCE */ op(DEC, Absolute, 6, false),
+        /* 0xCF */ ill(DCP, Absolute, 6, false),
+
+        /* 0xD0 */ op(BNE, Relative, 2, true),
+        /* 0xD1 */ op(CMP, IndirectY, 5, true),
+        /* 0xD2 */ ill(JAM, Implied, 2, false),
+        /* 0xD3 */ ill(DCP, IndirectY, 8, false),
+        /* 0xD4 */ ill(NOP, ZeropageX, 4, false),
+        /* 0xD5 */ op(CMP, ZeropageX, 4, false),
+        /* 0xD6 */ op(DEC, ZeropageX, 6, false),
+        /* 0xD7 */ ill(DCP, ZeropageX, 6, false),
+        /* 0xD8 */ op(CLD, Implied, 2, false),
+        /* 0xD9 */ op(CMP, AbsoluteY, 4, true),
+        /* 0xDA */ ill(NOP, Implied, 2, false),
+        /* 0xDB */ ill(DCP, AbsoluteY, 7, false),
+        /* 0xDC */ ill(NOP, AbsoluteX, 4, true),
+        /* 0xDD */ op(CMP, AbsoluteX, 4, true),
+        /* 0xDE */ op(DEC, AbsoluteX, 7, false),
+        /* 0xDF */ ill(DCP, AbsoluteX, 7, false),
+
+        /* 0xE0 */ op(CPX, Immediate, 2, false),
+        /* 0xE1 */ op(SBC, IndirectX, 6, false),
+        /* 0xE2 */ ill(NOP, Immediate, 2, false),
+        /* 0xE3 */ ill(ISC, IndirectX, 8, false),
+        /* 0xE4 */ op(CPX, Zeropage, 3, false),
+        /* 0xE5 */ op(SBC, Zeropage, 3, false),
+        /* 0xE6 */ op(INC, Zeropage, 5, false),
+        /* 0xE7 */ ill(ISC, Zeropage, 5, false),
+        /* 0xE8 */ op(INX, Implied, 2, false),
+        /* 0xE9 */ op(SBC, Immediate, 2, false),
+        /* 0xEA */ op(NOP, Implied, 2, false),
+        /* 0xEB */ ill(SBC, Immediate, 2, false),
+        /* 0xEC */ op(CPX, Absolute, 4, false),
+        /* 0xED */ op(SBC, Absolute, 4, false),
+        /* 0xEE */ op(INC, Absolute, 6, false),
+        /* 0xEF */ ill(ISC, Absolute, 6, false),
+
+        /* 0xF0 */ op(BEQ, Relative, 2, true),
+        /* 0xF1 */ op(SBC, IndirectY, 5, true),
+        /* 0xF2 */ ill(JAM, Implied, 2, false),
+        /* 0xF3 */ ill(ISC, IndirectY, 8, false),
+        /* 0xF4 */ ill(NOP, ZeropageX, 4, false),
+        /* 0xF5 */ op(SBC, ZeropageX, 4, false),
+        /* 0xF6 */ op(INC, ZeropageX, 6, false),
+        /* 0xF7 */ ill(ISC, ZeropageX, 6, false),
+        /* 0xF8 */ op(SED, Implied, 2, false),
+        /* 0xF9 */ op(SBC, AbsoluteY, 4, true),
+        /* 0xFA */ ill(NOP, Implied, 2, false),
+        /* 0xFB */ ill(ISC, AbsoluteY, 7, false),
+        /* 0xFC */ ill(NOP, AbsoluteX, 4, true),
+        /* 0xFD */ op(SBC, AbsoluteX, 4, true),
+        /* 0xFE */ op(INC, AbsoluteX, 7, false),
+        /* 0xFF */ ill(ISC, AbsoluteX, 7, false),
+    ]
+};
+
+/// The WDC 65C02 opcode matrix, Rockwell bit instructions included. Opcodes that do nothing
+/// on the 65C02 are NOPs of various lengths, and are marked as undocumented
+pub static OPCODES_65C02: [Opcode; 256] = {
+    use Mnemonic::*;
+    use AddressingMode::*;
+    [
+        /* 0x00 */ op(BRK, Implied, 7, false),
+        /* 0x01 */ op(ORA, IndirectX, 6, false),
+        /* 0x02 */ ill(NOP, Immediate, 2, false),
+        /* 0x03 */ ill(NOP, Implied, 1, false),
+        /* 0x04 */ op(TSB, Zeropage, 5, false),
+        /* 0x05 */ op(ORA, Zeropage, 3, false),
+        /* 0x06 */ op(ASL, Zeropage, 5, false),
+        /* 0x07 */ op(RMB, Zeropage, 5, false),
+        /* 0x08 */ op(PHP, Implied, 3, false),
+        /* 0x09 */ op(ORA, Immediate, 2, false),
+        /* 0x0A */ op(ASL, Accumulator, 2, false),
+        /* 0x0B */ ill(NOP, Implied, 1, false),
+        /* 0x0C */ op(TSB, Absolute, 6, false),
+        /* 0x0D */ op(ORA, Absolute, 4, false),
+        /* 0x0E */ op(ASL, Absolute, 6, false),
+        /* 0x0F */ op(BBR, ZeropageRelative, 5, false),
+
+        /* 0x10 */ op(BPL, Relative, 2, true),
+        /* 0x11 */ op(ORA, IndirectY, 5, true),
+        /* 0x12 */ op(ORA, ZeropageIndirect, 5, false),
+        /* 0x13 */ ill(NOP, Implied, 1, false),
+        /* 0x14 */ op(TRB, Zeropage, 5, false),
+        /* 0x15 */ op(ORA, ZeropageX, 4, false),
+        /* 0x16 */ op(ASL, ZeropageX, 6, false),
+        /* 0x17 */ op(RMB, Zeropage, 5, false),
+        /* 0x18 */ op(CLC, Implied, 2, false),
+        /* 0x19 */ op(ORA, AbsoluteY, 4, true),
+        /* 0x1A */ op(INC, Accumulator, 2, false),
+        /* 0x1B */ ill(NOP, Implied, 1, false),
+        /* 0x1C */ op(TRB, Absolute, 6, false),
+        /* 0x1D */ op(ORA, AbsoluteX, 4, true),
+        /* 0x1E */ op(ASL, AbsoluteX, 6, true),
+        /* 0x1F */ op(BBR, ZeropageRelative, 5, false),
+
+        /* 0x20 */ op(JSR, Absolute, 6, false),
+        /* 0x21 */ op(AND, IndirectX, 6, false),
+        /* 0x22 */ ill(NOP, Immediate, 2, false),
+        /* 0x23 */ ill(NOP, Implied, 1, false),
+        /* 0x24 */ op(BIT, Zeropage, 3, false),
+        /* 0x25 */ op(AND, Zeropage, 3, false),
+        /* 0x26 */ op(ROL, Zeropage, 5, false),
+        /* 0x27 */ op(RMB, Zeropage, 5, false),
+        /* 0x28 */ op(PLP, Implied, 4, false),
+        /* 0x29 */ op(AND, Immediate, 2, false),
+        /* 0x2A */ op(ROL, Accumulator, 2, false),
+        /* 0x2B */ ill(NOP, Implied, 1, false),
+        /* 0x2C */ op(BIT, Absolute, 4, false),
+        /* 0x2D */ op(AND, Absolute, 4, false),
+        /* 0x2E */ op(ROL, Absolute, 6, false),
+        /* 0x2F */ op(BBR, ZeropageRelative, 5, false),
+
+        /* 0x30 */ op(BMI, Relative, 2, true),
+        /* 0x31 */ op(AND, IndirectY, 5, true),
+        /* 0x32 */ op(AND, ZeropageIndirect, 5, false),
+        /* 0x33 */ ill(NOP, Implied, 1, false),
+        /* 0x34 */ op(BIT, ZeropageX, 4, false),
+        /* 0x35 */ op(AND, ZeropageX, 4, false),
+        /* 0x36 */ op(ROL, ZeropageX, 6, false),
+        /* 0x37 */ op(RMB, Zeropage, 5, false),
+        /* 0x38 */ op(SEC, Implied, 2, false),
+        /* 0x39 */ op(AND, AbsoluteY, 4, true),
+        /* 0x3A */ op(DEC, Accumulator, 2, false),
+        /* 0x3B */ ill(NOP, Implied, 1, false),
+        /* 0x3C */ op(BIT, AbsoluteX, 4, true),
+        /* 0x3D */ op(AND, AbsoluteX, 4, true),
+        /* 0x3E */ op(ROL, AbsoluteX, 6, true),
+        /* 0x3F */ op(BBR, ZeropageRelative, 5, false),
+
+        /* 0x40 */ op(RTI, Implied, 6, false),
+        /* 0x41 */ op(EOR, IndirectX, 6, false),
+        /* 0x42 */ ill(NOP, Immediate, 2, false),
+        /* 0x43 */ ill(NOP, Implied, 1, false),
+        /* 0x44 */ ill(NOP, Zeropage, 3, false),
+        /* 0x45 */ op(EOR, Zeropage, 3, false),
+        /* 0x46 */ op(LSR, Zeropage, 5, false),
+        /* 0x47 */ op(RMB, Zeropage, 5, false),
+        /* 0x48 */ op(PHA, Implied, 3, false),
+        /* 0x49 */ op(EOR, Immediate, 2, false),
+        /* 0x4A */ op(LSR, Accumulator, 2, false),
+        /* 0x4B */ ill(NOP, Implied, 1, false),
+        /* 0x4C */ op(JMP, Absolute, 3, false),
+        /* 0x4D */ op(EOR, Absolute, 4, false),
+        /* 0x4E */ op(LSR, Absolute, 6, false),
+        /* 0x4F */ op(BBR, ZeropageRelative, 5, false),
+
+        /* 0x50 */ op(BVC, Relative, 2, true),
+        /* 0x51 */ op(EOR, IndirectY, 5, true),
+        /* 0x52 */ op(EOR, ZeropageIndirect, 5, false),
+        /* 0x53 */ ill(NOP, Implied, 1, false),
+        /* 0x54 */ ill(NOP, ZeropageX, 4, false),
+        /* 0x55 */ op(EOR, ZeropageX, 4, false),
+        /* 0x56 */ op(LSR, ZeropageX, 6, false),
+        /* 0x57 */ op(RMB, Zeropage, 5, false),
+        /* 0x58 */ op(CLI, Implied, 2, false),
+        /* 0x59 */ op(EOR, AbsoluteY, 4, true),
+        /* 0x5A */ op(PHY, Implied, 3, false),
+        /* 0x5B */ ill(NOP, Implied, 1, false),
+        /* 0x5C */ ill(NOP, Absolute, 8, false),
+        /* 0x5D */ op(EOR, AbsoluteX, 4, true),
+        /* 0x5E */ op(LSR, AbsoluteX, 6, true),
+        /* 0x5F */ op(BBR, ZeropageRelative, 5, false),
+
+        /* 0x60 */ op(RTS, Implied, 6, false),
+        /* 0x61 */ op(ADC, IndirectX, 6, false),
+        /* 0x62 */ ill(NOP, Immediate, 2, false),
+        /* 0x63 */ ill(NOP, Implied, 1, false),
+        /* 0x64 */ op(STZ, Zeropage, 3, false),
+        /* 0x65 */ op(ADC, Zeropage, 3, false),
+        /* 0x66 */ op(ROR, Zeropage, 5, false),
+        /* 0x67 */ op(RMB, Zeropage, 5, false),
+        /* 0x68 */ op(PLA, Implied, 4, false),
+        /* 0x69 */ op(ADC, Immediate, 2, false),
+        /* 0x6A */ op(ROR, Accumulator, 2, false),
+        /* 0x6B */ ill(NOP, Implied, 1, false),
+        /* 0x6C */ op(JMP, Indirect, 6, false),
+        /* 0x6D */ op(ADC, Absolute, 4, false),
+        /* 0x6E */ op(ROR, Absolute, 6, false),
+        /* 0x6F */ op(BBR, ZeropageRelative, 5, false),
+
+        /* 0x70 */ op(BVS, Relative, 2, true),
+        /* 0x71 */ op(ADC, IndirectY, 5, true),
+        /* 0x72 */ op(ADC, ZeropageIndirect, 5, false),
+        /* 0x73 */ ill(NOP, Implied, 1, false),
+        /* 0x74 */ op(STZ, ZeropageX, 4, false),
+        /* 0x75 */ op(ADC, ZeropageX, 4, false),
+        /* 0x76 */ op(ROR, ZeropageX, 6, false),
+        /* 0x77 */ op(RMB, Zeropage, 5, false),
+        /* 0x78 */ op(SEI, Implied, 2, false),
+        /* 0x79 */ op(ADC, AbsoluteY, 4, true),
+        /* 0x7A */ op(PLY, Implied, 4, false),
+        /* 0x7B */ ill(NOP, Implied, 1, false),
+        /* 0x7C */ op(JMP, AbsoluteIndexedIndirect, 6, false),
+        /* 0x7D */ op(ADC, AbsoluteX, 4, true),
+        /* 0x7E */ op(ROR, AbsoluteX, 6, true),
+        /* 0x7F */ op(BBR, ZeropageRelative, 5, false),
+
+        /* 0x80 */ op(BRA, Relative, 2, true),
+        /* 0x81 */ op(STA, IndirectX, 6, false),
+        /* 0x82 */ ill(NOP, Immediate, 2, false),
+        /* 0x83 */ ill(NOP, Implied, 1, false),
+        /* 0x84 */ op(STY, Zeropage, 3, false),
+        /* 0x85 */ op(STA, Zeropage, 3, false),
+        /* 0x86 */ op(STX, Zeropage, 3, false),
+        /* 0x87 */ op(SMB, Zeropage, 5, false),
+        /* 0x88 */ op(DEY, Implied, 2, false),
+        /* 0x89 */ op(BIT, Immediate, 2, false).only_flags(Z_BIT),
+        /* 0x8A */ op(TXA, Implied, 2, false),
+        /* 0x8B */ ill(NOP, Implied, 1, false),
+        /* 0x8C */ op(STY, Absolute, 4, false),
+        /* 0x8D */ op(STA, Absolute, 4, false),
+        /* 0x8E */ op(STX, Absolute, 4, false),
+        /* 0x8F */ op(BBS, ZeropageRelative, 5, false),
+
+        /* 0x90 */ op(BCC, Relative, 2, true),
+        /* 0x91 */ op(STA, IndirectY, 6, false),
+        /* 0x92 */ op(STA, ZeropageIndirect, 5, false),
+        /* 0x93 */ ill(NOP, Implied, 1, false),
+        /* 0x94 */ op(STY, ZeropageX, 4, false),
+        /* 0x95 */ op(STA, ZeropageX, 4, false),
+        /* 0x96 */ op(STX, ZeropageY, 4, false),
+        /* 0x97 */ op(SMB, Zeropage, 5, false),
+        /* 0x98 */ op(TYA, Implied, 2, false),
+        /* 0x99 */ op(STA, AbsoluteY, 5, false),
+        /* 0x9A */ op(TXS, Implied, 2, false),
+        /* 0x9B */ ill(NOP, Implied, 1, false),
+        /* 0x9C */ op(STZ, Absolute, 4, false),
+        /* 0x9D */ op(STA, AbsoluteX, 5, false),
+        /* 0x9E */ op(STZ, AbsoluteX, 5, false),
+        /* 0x9F */ op(BBS, ZeropageRelative, 5, false),
+
+        /* 0xA0 */ op(LDY, Immediate, 2, false),
+        /* 0xA1 */ op(LDA, IndirectX, 6, false),
+        /* 0xA2 */ op(LDX, Immediate, 2, false),
+        /* 0xA3 */ ill(NOP, Implied, 1, false),
+        /* 0xA4 */ op(LDY, Zeropage, 3, false),
+        /* 0xA5 */ op(LDA, Zeropage, 3, false),
+        /* 0xA6 */ op(LDX, Zeropage, 3, false),
+        /* 0xA7 */ op(SMB, Zeropage, 5, false),
+        /* 0xA8 */ op(TAY, Implied, 2, false),
+        /* 0xA9 */ op(LDA, Immediate, 2, false),
+        /* 0xAA */ op(TAX, Implied, 2, false),
+        /* 0xAB */ ill(NOP, Implied, 1, false),
+        /* 0xAC */ op(LDY, Absolute, 4, false),
+        /* 0xAD */ op(LDA, Absolute, 4, false),
+        /* 0xAE */ op(LDX, Absolute, 4, false),
+        /* 0xAF */ op(BBS, ZeropageRelative, 5, false),
+
+        /* 0xB0 */ op(BCS, Relative, 2, true),
+        /* 0xB1 */ op(LDA, IndirectY, 5, true),
+        /* 0xB2 */ op(LDA, ZeropageIndirect, 5, false),
+        /* 0xB3 */ ill(NOP, Implied, 1, false),
+        /* 0xB4 */ op(LDY, ZeropageX, 4, false),
+        /* 0xB5 */ op(LDA, ZeropageX, 4, false),
+        /* 0xB6 */ op(LDX, ZeropageY, 4, false),
+        /* 0xB7 */ op(SMB, Zeropage, 5, false),
+        /* 0xB8 */ op(CLV, Implied, 2, false),
+        /* 0xB9 */ op(LDA, AbsoluteY, 4, true),
+        /* 0xBA */ op(TSX, Implied, 2, false),
+        /* 0xBB */ ill(NOP, Implied, 1, false),
+        /* 0xBC */ op(LDY, AbsoluteX, 4, true),
+        /* 0xBD */ op(LDA, AbsoluteX, 4, true),
+        /* 0xBE */ op(LDX, AbsoluteY, 4, true),
+        /* 0xBF */ op(BBS, ZeropageRelative, 5, false),
+
+        /* 0xC0 */ op(CPY, Immediate, 2, false),
+        /* 0xC1 */ op(CMP, IndirectX, 6, false),
+        /* 0xC2 */ ill(NOP, Immediate, 2, false),
+        /* 0xC3 */ ill(NOP, Implied, 1, false),
+        /* 0xC4 */ op(CPY, Zeropage, 3, false),
+        /* 0xC5 */ op(CMP, Zeropage, 3, false),
+        /* 0xC6 */ op(DEC, Zeropage, 5, false),
+        /* 0xC7 */ op(SMB, Zeropage, 5, false),
+        /* 0xC8 */ op(INY, Implied, 2, false),
+        /* 0xC9 */ op(CMP, Immediate, 2, false),
+        /* 0xCA */ op(DEX, Implied, 2, false),
+        /* 0xCB */ op(WAI, Implied, 3, false),
+        /* 0xCC */ op(CPY, Absolute, 4, false),
+        /* 0xCD */ op(CMP, Absolute, 4, false),
+        /* 0xCE */ op(DEC, Absolute, 6, false),
+        /* 0xCF */ op(BBS, ZeropageRelative, 5, false),
+
+        /* 0xD0 */ op(BNE, Relative, 2, true),
+        /* 0xD1 */ op(CMP, IndirectY, 5, true),
+        /* 0xD2 */ op(CMP, ZeropageIndirect, 5, false),
+        /* 0xD3 */ ill(NOP, Implied, 1, false),
+        /* 0xD4 */ ill(NOP, ZeropageX, 4, false),
+        /* 0xD5 */ op(CMP, ZeropageX, 4, false),
+        /* 0xD6 */ op(DEC, ZeropageX, 6, false),
+        /* 0xD7 */ op(SMB, Zeropage, 5, false),
+        /* 0xD8 */ op(CLD, Implied, 2, false),
+        /* 0xD9 */ op(CMP, AbsoluteY, 4, true),
+        /* 0xDA */ op(PHX, Implied, 3, false),
+        /* 0xDB */ op(STP, Implied, 3, false),
+        /* 0xDC */ ill(NOP, Absolute, 4, false),
+        /* 0xDD */ op(CMP, AbsoluteX, 4, true),
+        /* 0xDE */ op(DEC, AbsoluteX, 7, false),
+        /* 0xDF */ op(BBS, ZeropageRelative, 5, false),
+
+        /* 0xE0 */ op(CPX, Immediate, 2, false),
+        /* 0xE1 */ op(SBC, IndirectX, 6, false),
+        /* 0xE2 */ ill(NOP, Immediate, 2, false),
+        /* 0xE3 */ ill(NOP, Implied, 1, false),
+        /* 0xE4 */ op(CPX, Zeropage, 3, false),
+        /* 0xE5 */ op(SBC, Zeropage, 3, false),
+        /* 0xE6 */ op(INC, Zeropage, 5, false),
+        /* 0xE7 */ op(SMB, Zeropage, 5, false),
+        /* 0xE8 */ op(INX, Implied, 2, false),
+        /* 0xE9 */ op(SBC, Immediate, 2, false),
+        /* 0xEA */ op(NOP, Implied, 2, false),
+        /* 0xEB */ ill(NOP, Implied, 1, false),
+        /* 0xEC */ op(CPX, Absolute, 4, false),
+        /* 0xED */ op(SBC, Absolute, 4, false),
+        /* 0xEE */ op(INC, Absolute, 6, false),
+        /* 0xEF */ op(BBS, ZeropageRelative, 5, false),
+
+        /* 0xF0 */ op(BEQ, Relative, 2, true),
+        /* 0xF1 */ op(SBC, IndirectY, 5, true),
+        /* 0xF2 */ op(SBC, ZeropageIndirect, 5, false),
+        /* 0xF3 */ ill(NOP, Implied, 1, false),
+        /* 0xF4 */ ill(NOP, ZeropageX, 4, false),
+        /* 0xF5 */ op(SBC, ZeropageX, 4, false),
+        /* 0xF6 */ op(INC, ZeropageX, 6, false),
+        /* 0xF7 */ op(SMB, Zeropage, 5, false),
+        /* 0xF8 */ op(SED, Implied, 2, false),
+        /* 0xF9 */ op(SBC, AbsoluteY, 4, true),
+        /* 0xFA */ op(PLX, Implied, 4, false),
+        /* 0xFB */ ill(NOP, Implied, 1, false),
+        /* 0xFC */ ill(NOP, Absolute, 4, false),
+        /* 0xFD */ op(SBC, AbsoluteX, 4, true),
+        /* 0xFE */ op(INC, AbsoluteX, 7, false),
+        /* 0xFF */ op(BBS, ZeropageRelative, 5, false),
+    ]
+};
+
+#[inline]
+pub fn decode(opcode: word) -> &'static Opcode {
+    &OPCODES[opcode.native_value() as usize]
+}
+
+#[inline]
+pub fn decode_65c02(opcode: word) -> &'static Opcode {
+    &OPCODES_65C02[opcode.native_value() as usize]
+}
+
+/// Decodes with the opcode table of `variant`
+#[inline]
+pub fn decode_for(variant: CpuVariant, opcode: word) -> &'static Opcode {
+    match variant {
+        CpuVariant::Cmos65C02 => decode_65c02(opcode),
+        _ => decode(opcode),
+    }
+}
+
+/// Mnemonic of `opcode` as written in assembly, RMB, SMB, BBR and BBS followed by their bit (e.g. RMB3)
+pub fn mnemonic_name(op: &Opcode, opcode: word) -> String {
+    match op.mnemonic {
+        Mnemonic::RMB | Mnemonic::SMB | Mnemonic::BBR | Mnemonic::BBS => format!("{:?}{}", op.mnemonic, (opcode.native_value() >> 4) & 0x07),
+        mnemonic => format!("{:?}", mnemonic),
+    }
+}
+
+/// Finds the opcode of an instruction on `variant`. Documented opcodes are preferred over
+/// undocumented duplicates (e.g. SBC # is $E9, not $EB). RMB, SMB, BBR and BBS give the opcode
+/// for bit 0, the bit goes in bits 4 to 6
+pub fn encode(variant: CpuVariant, mnemonic: Mnemonic, mode: AddressingMode) -> Option<word> {
+    let table = match variant {
+        CpuVariant::Cmos65C02 => &OPCODES_65C02,
+        _ => &OPCODES,
+    };
+    let matches = |op: &Opcode| op.mnemonic == mnemonic && op.mode == mode;
+    table.iter().position(|op| matches(op) && op.official)
+        .or_else(|| table.iter().position(matches))
+        .map(|idx| word::from(idx as u8))
+}
@@ -0,0 +1,232 @@
+//! Single instruction tests in the ProcessorTests JSON format: for every opcode, thousands of
+//! cases giving the state before and after one instruction, and the bus activity of every cycle.
+//! Files are looked up in `test-roms/processor-tests/<variant>/<opcode>.json` (see the README there).
+//! They are not vendored, so the tests are ignored by default: once fetched, run
+//! `cargo test --release -- --ignored processor_tests`. Every opcode of the variant's table, i.e. all
+//! 256, needs its file: missing ones fail the test and are listed in the report.
+//!
+//! Each case runs in both execution modes. Registers and memory are always checked, the bus
+//! cycles only in cycle-stepped mode. Mismatches are reported for the first failing case of each opcode.
+
+use std::fmt::Write;
+use std::fs;
+use std::path::PathBuf;
+use serde_json::Value;
+use super::datastructures::word;
+use super::datastructures::doubleword;
+use super::datastructures::InstructionStream;
+use super::cycle::ExecMode;
+use super::BusFault;
+use super::CpuVariant;
+use super::System;
+use super::IO6502;
+use super::B_BIT;
+use super::U_BIT;
+
+/// Flat 64K of RAM recording the bus activity
+struct BusMemory {
+    data: Vec<word>,
+    /// (address, data, is_write) of every access, in order
+    cycles: Vec<(u16, u8, bool)>,
+}
+
+impl IO6502 for BusMemory {
+    fn new_resetted() -> Self {
+        Self { data: vec![word::zero(); 0x10000], cycles: Vec::new() }
+    }
+
+    fn reset(&mut self) {
+        self.data.iter_mut().for_each(|x| *x = word::zero());
+        self.cycles.clear();
+    }
+
+    fn push_program(&mut self, program: InstructionStream) {
+        for (idx, byte) in program.stream.iter().enumerate() {
+            self.data[idx] = *byte;
+        }
+    }
+
+    fn store(&mut self, address: doubleword, data: word) -> Result<(), BusFault> {
+        self.cycles.push((address.native_value(), data.native_value(), true));
+        self.data[address.as_addr()] = data;
+        Ok(())
+    }
+
+    fn load(&mut self, address: doubleword) -> Result<word, BusFault> {
+        let ret = self.data[address.as_addr()];
+        self.cycles.push((address.native_value(), ret.native_value(), false));
+        Ok(ret)
+    }
+
+    fn peek(&self, address: doubleword) -> Option<word> {
+        Some(self.data[address.as_addr()])
+    }
+}
+
+/// Registers and memory of a case, before or after the instruction
+struct CaseState {
+    pc: u16,
+    s: u8,
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    ram: Vec<(u16, u8)>,
+}
+
+struct Case {
+    name: String,
+    initial: CaseState,
+    expected: CaseState,
+    cycles: Vec<(u16, u8, bool)>,
+}
+
+fn number(value: &Value, field: &str) -> u64 {
+    value[field].as_u64().unwrap_or_else(|| panic!("missing or invalid field {}", field))
+}
+
+fn parse_state(value: &Value) -> CaseState {
+    let ram = value["ram"].as_array().expect("missing ram").iter()
+        .map(|cell| (cell[0].as_u64().unwrap() as u16, cell[1].as_u64().unwrap() as u8))
+        .collect();
+    CaseState {
+        pc: number(value, "pc") as u16,
+        s: number(value, "s") as u8,
+        a: number(value, "a") as u8,
+        x: number(value, "x") as u8,
+        y: number(value, "y") as u8,
+        p: number(value, "p") as u8,
+        ram,
+    }
+}
+
+fn parse_case(value: &Value) -> Case {
+    let cycles = value["cycles"].as_array().expect("missing cycles").iter()
+        .map(|cycle| (cycle[0].as_u64().unwrap() as u16, cycle[1].as_u64().unwrap() as u8, cycle[2] == "write"))
+        .collect();
+    Case {
+        name: value["name"].as_str().unwrap_or("").to_string(),
+        initial: parse_state(&value["initial"]),
+        expected: parse_state(&value["final"]),
+        cycles,
+    }
+}
+
+fn format_cycle(cycle: Option<&(u16, u8, bool)>) -> String {
+    match cycle {
+        Some(&(address, data, true)) => format!("write ${:02X} to ${:04X}", data, address),
+        Some(&(address, data, false)) => format!("read ${:02X} from ${:04X}", data, address),
+        None => "nothing".to_string(),
+    }
+}
+
+/// Runs a case, returning the list of differences
+fn run_case(variant: CpuVariant, mode: ExecMode, case: &Case) -> Vec<String> {
+    let mut sys: System<BusMemory> = System::with_variant(variant);
+    sys.set_exec_mode(mode).unwrap();
+    sys.pc = doubleword::from(case.initial.pc);
+    sys.s = word::from(case.initial.s);
+    sys.a = word::from(case.initial.a);
+    sys.x = word::from(case.initial.x);
+    sys.y = word::from(case.initial.y);
+    sys.p = word::from(case.initial.p);
+    for &(address, data) in &case.initial.ram {
+        sys.mem.data[address as usize] = word::from(data);
+    }
+
+    let mut diffs = Vec::new();
+    if let Err(err) = sys.step() {
+        diffs.push(format!("step failed: {}", err));
+    }
+
+    // B and U do not exist in P, only in its copies pushed on the stack
+    let ignored = B_BIT | U_BIT;
+    let registers = [
+        ("PC", case.expected.pc, sys.pc.native_value()),
+        ("S", case.expected.s as u16, sys.s.native_value() as u16),
+        ("A", case.expected.a as u16, sys.a.native_value() as u16),
+        ("X", case.expected.x as u16, sys.x.native_value() as u16),
+        ("Y", case.expected.y as u16, sys.y.native_value() as u16),
+        ("P", (case.expected.p & !ignored) as u16, (sys.p.native_value() & !ignored) as u16),
+    ];
+    for &(name, expected, actual) in &registers {
+        if expected != actual {
+            diffs.push(format!("{}: expected ${:02X}, got ${:02X}", name, expected, actual));
+        }
+    }
+    for &(address, expected) in &case.expected.ram {
+        let actual = sys.mem.data[address as usize].native_value();
+        if expected != actual {
+            diffs.push(format!("RAM ${:04X}: expected ${:02X}, got ${:02X}", address, expected, actual));
+        }
+    }
+
+    if mode == ExecMode::Cycle && sys.mem.cycles != case.cycles {
+        let len = case.cycles.len().max(sys.mem.cycles.len());
+        for idx in (0..len).filter(|&idx| case.cycles.get(idx) != sys.mem.cycles.get(idx)) {
+            diffs.push(format!("cycle {}: expected {}, got {}", idx + 1,
+                               format_cycle(case.cycles.get(idx)), format_cycle(sys.mem.cycles.get(idx))));
+        }
+    }
+    diffs
+}
+
+/// Runs the file of every opcode of `variant`, panicking with a report of the failing and
+/// missing opcodes
+fn run_variant(variant: CpuVariant, directory: &str) {
+    let root: PathBuf = [env!("CARGO_MANIFEST_DIR"), "test-roms", "processor-tests", directory].iter().collect();
+    let mut report = String::new();
+    let mut failed = 0;
+    let mut missing = Vec::new();
+
+    for opcode in 0..=0xFFu8 {
+        let path = root.join(format!("{:02x}.json", opcode));
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(_) => {
+                missing.push(opcode);
+                continue;
+            },
+        };
+        let cases: Value = serde_json::from_str(&text).unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+        let cases = cases.as_array().unwrap_or_else(|| panic!("{}: not a list of cases", path.display()));
+
+        'cases: for case in cases.iter().map(parse_case) {
+            for &mode in &[ExecMode::Instruction, ExecMode::Cycle] {
+                let diffs = run_case(variant, mode, &case);
+                if !diffs.is_empty() {
+                    failed += 1;
+                    writeln!(report, "opcode ${:02X}, case \"{}\" in {:?} mode:", opcode, case.name, mode).unwrap();
+                    diffs.iter().for_each(|diff| writeln!(report, "    {}", diff).unwrap());
+                    break 'cases;
+                }
+            }
+        }
+    }
+
+    assert!(missing.len() < 0x100, "no test files in {} (see test-roms/processor-tests/README.md)", root.display());
+    if !missing.is_empty() {
+        let opcodes: Vec<String> = missing.iter().map(|opcode| format!("${:02X}", opcode)).collect();
+        writeln!(report, "no test file for {} opcodes: {}", missing.len(), opcodes.join(" ")).unwrap();
+    }
+    assert!(failed == 0 && missing.is_empty(), "{} of {} opcodes failed, {} missing\n{}",
+            failed, 0x100 - missing.len(), missing.len(), report);
+}
+
+#[test]
+#[ignore = "needs the vectors in test-roms/processor-tests/6502"]
+fn processor_tests_nmos6502() {
+    run_variant(CpuVariant::Nmos6502, "6502");
+}
+
+#[test]
+#[ignore = "needs the vectors in test-roms/processor-tests/nes6502"]
+fn processor_tests_2a03() {
+    run_variant(CpuVariant::Ricoh2A03, "nes6502");
+}
+
+#[test]
+#[ignore = "needs the vectors in test-roms/processor-tests/wdc65c02"]
+fn processor_tests_65c02() {
+    run_variant(CpuVariant::Cmos65C02, "wdc65c02");
+}
@@ -0,0 +1,279 @@
+//! Save states: the whole state of a system, CPU and memory, as a versioned binary blob.
+//! Restoring a state then running gives exactly the same results as the original run.
+//!
+//! Layout, little endian: the magic, the format version on 2 bytes, then the CPU state
+//! followed by the memory state, as written by the `SaveState` implementations.
+
+use std::error::Error;
+use std::fmt;
+use super::datastructures::word;
+use super::datastructures::doubleword;
+use super::CpuVariant;
+use super::ExecMode;
+use super::FamicomMemory;
+use super::System;
+use super::UndocumentedOpcodes;
+use super::IO6502;
+
+const MAGIC: &[u8; 8] = b"6502STAT";
+/// Bumped whenever the layout changes, old states are then rejected
+const VERSION: u16 = 3;
+
+/// Why a save state could not be restored. The system is left untouched
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SaveStateError {
+    /// Not a save state
+    BadMagic,
+    /// Written by another version of the format
+    UnsupportedVersion(u16),
+    /// The data ends before the state does
+    Truncated,
+    /// A field holds a value it cannot take
+    InvalidValue(&'static str),
+    /// There is data after the state
+    TrailingData,
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveStateError::BadMagic => write!(f, "not a save state"),
+            SaveStateError::UnsupportedVersion(version) => write!(f, "unsupported save state version {}", version),
+            SaveStateError::Truncated => write!(f, "truncated save state"),
+            SaveStateError::InvalidValue(field) => write!(f, "invalid value for {} in save state", field),
+            SaveStateError::TrailingData => write!(f, "unexpected data after the save state"),
+        }
+    }
+}
+
+impl Error for SaveStateError {}
+
+/// Serialises values in the save state layout
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    fn new() -> Self {
+        Self { data: Vec::new() }
+    }
+
+    pub fn write_u8(&mut self, val: u8) {
+        self.data.push(val);
+    }
+
+    pub fn write_bool(&mut self, val: bool) {
+        self.write_u8(val as u8);
+    }
+
+    pub fn write_u16(&mut self, val: u16) {
+        self.data.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, val: u64) {
+        self.data.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn write_word(&mut self, val: word) {
+        self.write_u8(val.native_value());
+    }
+
+    pub fn write_doubleword(&mut self, val: doubleword) {
+        self.write_u16(val.native_value());
+    }
+
+    pub fn write_words(&mut self, val: &[word]) {
+        self.data.extend(val.iter().map(|w| w.native_value()));
+    }
+}
+
+/// Reads values back from the save state layout
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SaveStateError> {
+        if self.data.len() < len {
+            return Err(SaveStateError::Truncated);
+        }
+        let (ret, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(ret)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, SaveStateError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SaveStateError::InvalidValue("boolean")),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, SaveStateError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, SaveStateError> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn read_word(&mut self) -> Result<word, SaveStateError> {
+        Ok(word::from(self.read_u8()?))
+    }
+
+    pub fn read_doubleword(&mut self) -> Result<doubleword, SaveStateError> {
+        Ok(doubleword::from(self.read_u16()?))
+    }
+
+    /// Fills `dest`, the length being known from the layout
+    pub fn read_words(&mut self, dest: &mut [word]) -> Result<(), SaveStateError> {
+        let bytes = self.take(dest.len())?;
+        dest.iter_mut().zip(bytes).for_each(|(w, b)| *w = word::from(*b));
+        Ok(())
+    }
+}
+
+/// Something whose state goes into save states
+pub trait SaveState {
+    fn save(&self, out: &mut StateWriter);
+
+    /// Restores what `save` wrote. On error, `self` may be partially restored
+    fn restore(&mut self, input: &mut StateReader) -> Result<(), SaveStateError>;
+}
+
+impl SaveState for FamicomMemory {
+    fn save(&self, out: &mut StateWriter) {
+        self.bus.save(out);
+    }
+
+    fn restore(&mut self, input: &mut StateReader) -> Result<(), SaveStateError> {
+        self.bus.restore(input)
+    }
+}
+
+impl<T: IO6502 + SaveState> System<T> {
+
+    /// Snapshots the CPU and its memory. Breakpoints, the tracer and other debugging settings are not included
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = StateWriter::new();
+        out.data.extend_from_slice(MAGIC);
+        out.write_u16(VERSION);
+
+        out.write_u8(match self.variant {
+            CpuVariant::Nmos6502 => 0,
+            CpuVariant::Ricoh2A03 => 1,
+            CpuVariant::Cmos65C02 => 2,
+        });
+        out.write_u8(match self.undocumented {
+            UndocumentedOpcodes::Execute => 0,
+            UndocumentedOpcodes::HaltOnJam => 1,
+            UndocumentedOpcodes::Trap => 2,
+        });
+        out.write_bool(self.jammed);
+        out.write_bool(self.waiting);
+        out.write_bool(self.stopped);
+        out.write_word(self.a);
+        out.write_word(self.x);
+        out.write_word(self.y);
+        out.write_doubleword(self.pc);
+        out.write_word(self.s);
+        out.write_word(self.p);
+        out.write_u64(self.cycles);
+        out.write_u8(match self.exec_mode {
+            ExecMode::Instruction => 0,
+            ExecMode::Cycle => 1,
+        });
+        self.cycle_state.save(&mut out);
+        out.write_bool(self.nmi_line);
+        out.write_bool(self.nmi_pending);
+        out.write_bool(self.delayed_i.is_some());
+        out.write_bool(self.delayed_i.unwrap_or(false));
+
+        self.mem.save(&mut out);
+        out.data
+    }
+
+    /// Restores a snapshot taken by `save_state`. Breakpoints, the tracer and other debugging settings are kept
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        let mut input = StateReader::new(data);
+        if input.take(MAGIC.len()).map_err(|_| SaveStateError::BadMagic)? != MAGIC {
+            return Err(SaveStateError::BadMagic);
+        }
+        let version = input.read_u16()?;
+        if version != VERSION {
+            return Err(SaveStateError::UnsupportedVersion(version));
+        }
+
+        // Everything is restored into a new system first, so that errors leave self untouched
+        let variant = match input.read_u8()? {
+            0 => CpuVariant::Nmos6502,
+            1 => CpuVariant::Ricoh2A03,
+            2 => CpuVariant::Cmos65C02,
+            _ => return Err(SaveStateError::InvalidValue("CPU variant")),
+        };
+        let mut sys = Self::with_variant(variant);
+        sys.undocumented = match input.read_u8()? {
+            0 => UndocumentedOpcodes::Execute,
+            1 => UndocumentedOpcodes::HaltOnJam,
+            2 => UndocumentedOpcodes::Trap,
+            _ => return Err(SaveStateError::InvalidValue("undocumented opcode policy")),
+        };
+        sys.jammed = input.read_bool()?;
+        sys.waiting = input.read_bool()?;
+        sys.stopped = input.read_bool()?;
+        sys.a = input.read_word()?;
+        sys.x = input.read_word()?;
+        sys.y = input.read_word()?;
+        sys.pc = input.read_doubleword()?;
+        sys.s = input.read_word()?;
+        sys.p = input.read_word()?;
+        sys.cycles = input.read_u64()?;
+        sys.exec_mode = match input.read_u8()? {
+            0 => ExecMode::Instruction,
+            1 => ExecMode::Cycle,
+            _ => return Err(SaveStateError::InvalidValue("execution mode")),
+        };
+        sys.cycle_state.restore(&mut input)?;
+        sys.nmi_line = input.read_bool()?;
+        sys.nmi_pending = input.read_bool()?;
+        let delayed = input.read_bool()?;
+        let delayed_i = input.read_bool()?;
+        sys.delayed_i = match delayed {
+            true => Some(delayed_i),
+            false => None,
+        };
+
+        // Memory is restored in place, as only it knows its layout (e.g. the devices on a bus),
+        // and put back from a backup on errors
+        let mut backup = StateWriter::new();
+        self.mem.save(&mut backup);
+        std::mem::swap(&mut sys.mem, &mut self.mem);
+        let mut restored = sys.mem.restore(&mut input);
+        if restored.is_ok() && !input.data.is_empty() {
+            restored = Err(SaveStateError::TrailingData);
+        }
+        if let Err(err) = restored {
+            sys.mem.restore(&mut StateReader::new(&backup.data)).expect("memory backup cannot be restored");
+            std::mem::swap(&mut sys.mem, &mut self.mem);
+            return Err(err);
+        }
+
+        sys.debugger = std::mem::take(&mut self.debugger);
+        sys.stop_on_trap = self.stop_on_trap;
+        sys.tracer = self.tracer.take();
+        *self = sys;
+        Ok(())
+    }
+}
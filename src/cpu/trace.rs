@@ -0,0 +1,137 @@
+//! Execution traces in the format of nestest.log, one line per instruction, so that runs can be
+//! diffed against other emulators:
+//!
+//! `C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7`
+//!
+//! Memory operands are resolved as nestest does, with the value found there before the
+//! instruction runs. Memory is only peeked at, so tracing has no side effect.
+
+use super::addressing;
+use super::addressing::AddressingContext;
+use super::datastructures::word;
+use super::datastructures::doubleword;
+use super::datastructures::ClAdd;
+use super::opcodes;
+use super::opcodes::AddressingMode;
+use super::opcodes::Mnemonic;
+use super::System;
+use super::IO6502;
+
+/// Receives the trace lines
+pub type Tracer = Box<dyn FnMut(&str)>;
+
+/// NTSC PPU timing, used to derive the PPU position from the CPU cycle count
+const PPU_DOTS_PER_CYCLE: u64 = 3;
+const PPU_DOTS_PER_SCANLINE: u64 = 341;
+const PPU_SCANLINES: u64 = 262;
+
+/// Shown for memory that cannot be peeked at, like IO registers
+const UNREADABLE: u8 = 0xFF;
+
+impl<T: IO6502> System<T> {
+
+    /// Sends the trace line of every instruction to `tracer`, before the instruction is executed.
+    /// None stops tracing
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    /// Called by `step` before an instruction is executed
+    pub(super) fn trace(&mut self) {
+        if let Some(mut tracer) = self.tracer.take() {
+            tracer(&self.trace_line());
+            self.tracer = Some(tracer);
+        }
+    }
+
+    #[inline]
+    fn peek_or_unreadable(&self, address: doubleword) -> word {
+        self.mem.peek(address).unwrap_or_else(|| word::from(UNREADABLE))
+    }
+
+    /// Trace line of the instruction at PC, in the current state
+    pub fn trace_line(&self) -> String {
+        let instr = self.peek_or_unreadable(self.pc);
+        let opcode = self.decode(instr);
+        let bytes: Vec<String> = (0..opcode.bytes as u16)
+            .map(|idx| format!("{:02X}", self.peek_or_unreadable(self.pc.cl_add(doubleword::from(idx))).native_value()))
+            .collect();
+
+        let dots = self.cycles * PPU_DOTS_PER_CYCLE;
+        format!("{:04X}  {:<8} {}{:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+                self.pc.native_value(),
+                bytes.join(" "),
+                if opcode.official { ' ' } else { '*' },
+                self.trace_disassembly(instr),
+                self.a.native_value(),
+                self.x.native_value(),
+                self.y.native_value(),
+                self.p.native_value(),
+                self.s.native_value(),
+                (dots / PPU_DOTS_PER_SCANLINE) % PPU_SCANLINES,
+                dots % PPU_DOTS_PER_SCANLINE,
+                self.cycles)
+    }
+
+    /// Disassembly with resolved operands, e.g. `LDA ($89),Y = 0300 @ 0300 = 89`
+    fn trace_disassembly(&self, instr: word) -> String {
+        let opcode = self.decode(instr);
+        let operand_byte = |idx: u16| self.peek_or_unreadable(self.pc.cl_add(doubleword::from(idx)));
+        let byte = operand_byte(1).native_value();
+        let address = doubleword::from_words(operand_byte(2), operand_byte(1)).native_value();
+
+        let ctx = AddressingContext { pc: self.pc, x: self.x, y: self.y, fixed_indirect_jmp: self.is_65c02() };
+        let effective = addressing::effective_address(opcode.mode, ctx, |addr| self.peek_or_unreadable(addr))
+            .map(|ea| ea.address)
+            .unwrap_or_else(doubleword::zero);
+        let value = self.peek_or_unreadable(effective).native_value();
+        let target = effective.native_value();
+
+        let operand = match opcode.mode {
+            AddressingMode::Implied => String::new(),
+            AddressingMode::Accumulator => "A".to_string(),
+            AddressingMode::Immediate => format!("#${:02X}", byte),
+            AddressingMode::Relative => {
+                let next = self.pc.cl_add(doubleword::from(opcode.bytes as u16));
+                format!("${:04X}", next.cl_add(doubleword::from(word::from(byte).native_value_signed() as u16)).native_value())
+            },
+            AddressingMode::Zeropage => format!("${:02X} = {:02X}", byte, value),
+            AddressingMode::ZeropageX => format!("${:02X},X @ {:02X} = {:02X}", byte, target, value),
+            AddressingMode::ZeropageY => format!("${:02X},Y @ {:02X} = {:02X}", byte, target, value),
+            AddressingMode::Absolute => match opcode.mnemonic {
+                Mnemonic::JMP | Mnemonic::JSR => format!("${:04X}", address),
+                _ => format!("${:04X} = {:02X}", address, value),
+            },
+            AddressingMode::AbsoluteX => format!("${:04X},X @ {:04X} = {:02X}", address, target, value),
+            AddressingMode::AbsoluteY => format!("${:04X},Y @ {:04X} = {:02X}", address, target, value),
+            AddressingMode::Indirect => format!("(${:04X}) = {:04X}", address, target),
+            AddressingMode::IndirectX => {
+                let pointer = word::from(byte).cl_add(self.x).native_value();
+                format!("(${:02X},X) @ {:02X} = {:04X} = {:02X}", byte, pointer, target, value)
+            },
+            AddressingMode::IndirectY => {
+                let lo = self.peek_or_unreadable(doubleword::from(byte as u16));
+                let hi = self.peek_or_unreadable(doubleword::from(byte.wrapping_add(1) as u16));
+                let base = doubleword::from_words(hi, lo).native_value();
+                format!("(${:02X}),Y = {:04X} @ {:04X} = {:02X}", byte, base, target, value)
+            },
+            AddressingMode::ZeropageIndirect => format!("(${:02X}) = {:04X} = {:02X}", byte, target, value),
+            AddressingMode::AbsoluteIndexedIndirect => format!("(${:04X},X) = {:04X}", address, target),
+            AddressingMode::ZeropageRelative => {
+                let next = self.pc.cl_add(doubleword::from(opcode.bytes as u16));
+                let offset = operand_byte(2).native_value_signed() as u16;
+                format!("${:02X},${:04X} = {:02X}", byte, next.cl_add(doubleword::from(offset)).native_value(), value)
+            },
+        };
+
+        // nestest calls ISC by its other name
+        let mnemonic = match opcode.mnemonic {
+            Mnemonic::ISC => "ISB".to_string(),
+            _ => opcodes::mnemonic_name(opcode, instr),
+        };
+        match operand.is_empty() {
+            true => mnemonic,
+            false => format!("{} {}", mnemonic, operand),
+        }
+    }
+}
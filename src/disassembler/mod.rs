@@ -0,0 +1,113 @@
+use super::cpu::datastructures::word;
+use super::cpu::datastructures::doubleword;
+use super::cpu::datastructures::InstructionStream;
+use super::cpu::opcodes;
+use super::cpu::opcodes::AddressingMode;
+use super::cpu::CpuVariant;
+
+/// Formats the operand of an instruction, following the syntax accepted by the assembler
+fn format_operand(mode: AddressingMode, operand: &[word]) -> String {
+    let byte = || operand[0].native_value();
+    let address = || doubleword::from_words(operand[1], operand[0]).native_value();
+    match mode {
+        AddressingMode::Implied => String::new(),
+        AddressingMode::Accumulator => "A".to_string(),
+        AddressingMode::Immediate => format!("#${:02X}", byte()),
+        AddressingMode::Zeropage | AddressingMode::Relative => format!("${:02X}", byte()),
+        AddressingMode::ZeropageX => format!("${:02X},X", byte()),
+        AddressingMode::ZeropageY => format!("${:02X},Y", byte()),
+        AddressingMode::Absolute => format!("${:04X}", address()),
+        AddressingMode::AbsoluteX => format!("${:04X},X", address()),
+        AddressingMode::AbsoluteY => format!("${:04X},Y", address()),
+        AddressingMode::Indirect => format!("(${:04X})", address()),
+        AddressingMode::IndirectX => format!("(${:02X},X)", byte()),
+        AddressingMode::IndirectY => format!("(${:02X}),Y", byte()),
+        AddressingMode::ZeropageIndirect => format!("(${:02X})", byte()),
+        AddressingMode::AbsoluteIndexedIndirect => format!("(${:04X},X)", address()),
+        AddressingMode::ZeropageRelative => format!("${:02X},${:02X}", operand[0].native_value(), operand[1].native_value()),
+    }
+}
+
+/// Disassembles the instruction at the start of `bytes` for `variant`, returning its text and its
+/// length in bytes. Undocumented opcodes are prefixed with '*'. Returns None if `bytes` is cut in
+/// the middle of the instruction
+pub fn disassemble_instruction(variant: CpuVariant, bytes: &[word]) -> Option<(String, usize)> {
+    let op = opcodes::decode_for(variant, *bytes.first()?);
+    let len = op.bytes as usize;
+    if bytes.len() < len {
+        return None;
+    }
+
+    let prefix = if op.official { "" } else { "*" };
+    let operand = format_operand(op.mode, &bytes[1..len]);
+    let mnemonic = opcodes::mnemonic_name(op, bytes[0]);
+    let text = match operand.is_empty() {
+        true => format!("{}{}", prefix, mnemonic),
+        false => format!("{}{} {}", prefix, mnemonic, operand),
+    };
+    Some((text, len))
+}
+
+/// Disassembles a whole stream, one line per instruction. A truncated last instruction is
+/// shown with its mnemonic only
+pub fn disassemble(variant: CpuVariant, stream: &InstructionStream) -> Vec<String> {
+    let mut ret = Vec::new();
+    let mut idx = 0;
+    while idx < stream.stream.len() {
+        match disassemble_instruction(variant, &stream.stream[idx..]) {
+            Some((text, len)) => {
+                ret.push(text);
+                idx += len;
+            },
+            None => {
+                let op = opcodes::decode_for(variant, stream.stream[idx]);
+                let prefix = if op.official { "" } else { "*" };
+                ret.push(format!("{}{}", prefix, opcodes::mnemonic_name(op, stream.stream[idx])));
+                break;
+            },
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod disassembler_tests {
+
+    use super::*;
+    use super::super::assembler;
+    use super::super::cpu::CpuVariant;
+
+    #[test]
+    fn disassembly_matches_assembly_for_every_documented_opcode() {
+        for &(variant, table) in &[(CpuVariant::Nmos6502, &opcodes::OPCODES), (CpuVariant::Cmos65C02, &opcodes::OPCODES_65C02)] {
+            for (idx, op) in table.iter().enumerate().filter(|(_, op)| op.official) {
+                let bytes = [word::from(idx as u8), word::from(0x12u8), word::from(0x34u8)];
+                let (text, len) = disassemble_instruction(variant, &bytes).unwrap();
+                assert_eq!(len, op.bytes as usize);
+
+                let stream = assembler::assemble_line(variant, &text).unwrap();
+                let assembled: Vec<u8> = stream.stream.iter().map(|w| w.native_value()).collect();
+                let original: Vec<u8> = bytes[..len].iter().map(|w| w.native_value()).collect();
+                assert_eq!(assembled, original, "round trip of {:02X} on {:?} through \"{}\"", idx, variant, text);
+            }
+        }
+
+        // Undocumented opcodes are disassembled, not assembled
+        let (text, _) = disassemble_instruction(CpuVariant::Nmos6502, &[word::from(0xA7u8), word::from(0x12u8)]).unwrap();
+        assert_eq!(text, "*LAX $12");
+        assert_eq!(assembler::assemble_line(CpuVariant::Nmos6502, &text).err(),
+                   Some(assembler::AssemblyError::UnrecognisedOperation("*LAX".to_string())));
+    }
+
+    #[test]
+    fn disassemble_stream() {
+        let program: Vec<word> = [0xA9, 0x01, 0x8D, 0x00, 0x02, 0x0A, 0xFF].iter().map(|x| word::from(*x as u8)).collect();
+        let lines = disassemble(CpuVariant::Nmos6502, &InstructionStream::from(program));
+        assert_eq!(lines, vec!["LDA #$01", "STA $0200", "ASL A", "*ISC"]);
+
+        // The same bytes on a 65C02, where $FF is BBS7
+        let program: Vec<word> = [0x64, 0x10, 0x1A, 0xB2, 0x12, 0xFF, 0x12].iter().map(|x| word::from(*x as u8)).collect();
+        let lines = disassemble(CpuVariant::Cmos65C02, &InstructionStream::from(program));
+        assert_eq!(lines, vec!["STZ $10", "INC A", "LDA ($12)", "BBS7"]);
+    }
+}
@@ -0,0 +1,472 @@
+//! GDB remote serial protocol stub, so that debugger front ends can drive a `System` over TCP.
+//!
+//! The registers are sent in the order A, X, Y, S, P, PC (little endian), which front ends
+//! must be told about since GDB has no built-in 6502 target. Supported: register and memory
+//! reads and writes, breakpoints and watchpoints (Z0 to Z4), single step, continue and
+//! interrupting a running target with Ctrl-C.
+
+use std::collections::HashMap;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use super::cpu::Breakpoint;
+use super::cpu::BreakpointId;
+use super::cpu::ExecError;
+use super::cpu::IO6502;
+use super::cpu::MemoryAccessType;
+use super::cpu::Registers;
+use super::cpu::StopReason;
+use super::cpu::System;
+
+pub const DEFAULT_PORT: u16 = 6502;
+
+/// Sent by the client to interrupt a running target
+const INTERRUPT: u8 = 0x03;
+/// Steps between two checks for an interrupt while the target runs
+const INTERRUPT_POLL_STEPS: u32 = 4096;
+
+/// Signals reported in stop replies
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+/// Register numbers used by `p` and `P`
+const REGISTER_COUNT: usize = 6;
+const REG_PC: usize = 5;
+
+/// Breakpoint types of `Z` and `z` packets
+const Z_SOFTWARE: u8 = 0;
+const Z_HARDWARE: u8 = 1;
+const Z_WRITE: u8 = 2;
+const Z_READ: u8 = 3;
+const Z_ACCESS: u8 = 4;
+
+/// Waits for a debugger on `listener` and serves it until it detaches or disconnects
+pub fn serve<T: IO6502>(sys: &mut System<T>, listener: &TcpListener) -> io::Result<()> {
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    GdbStub { sys, stream, breakpoints: HashMap::new() }.run()
+}
+
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0u8, |acc, byte| acc.wrapping_add(byte))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn parse_hex(text: &str) -> Option<u16> {
+    u16::from_str_radix(text, 16).ok()
+}
+
+fn parse_hex_bytes(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|idx| u8::from_str_radix(text.get(idx..idx + 2)?, 16).ok()).collect()
+}
+
+/// Splits "addr,len" into its numbers
+fn parse_range(text: &str) -> Option<(u16, u16)> {
+    let (address, len) = text.split_once(',')?;
+    Some((parse_hex(address)?, parse_hex(len)?))
+}
+
+fn register_bytes(regs: &Registers) -> [u8; 7] {
+    let [pc_lo, pc_hi] = regs.pc.to_le_bytes();
+    [regs.a, regs.x, regs.y, regs.s, regs.p, pc_lo, pc_hi]
+}
+
+struct GdbStub<'a, T: IO6502> {
+    sys: &'a mut System<T>,
+    stream: TcpStream,
+    /// Breakpoints set by `Z` packets, by type, address and length. Access watchpoints take two
+    breakpoints: HashMap<(u8, u16, u16), Vec<BreakpointId>>,
+}
+
+impl<'a, T: IO6502> GdbStub<'a, T> {
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        let mut byte = [0u8];
+        self.stream.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    /// Next packet, acknowledged. Acks and interrupts received while stopped are skipped
+    fn read_packet(&mut self) -> io::Result<String> {
+        loop {
+            while self.read_byte()? != b'$' {}
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    b'#' => break,
+                    byte => data.push(byte),
+                }
+            }
+            let sum = [self.read_byte()?, self.read_byte()?];
+            let data = String::from_utf8_lossy(&data).into_owned();
+            let expected = std::str::from_utf8(&sum).ok().and_then(|sum| u8::from_str_radix(sum, 16).ok());
+            match expected == Some(checksum(&data)) {
+                true => {
+                    self.stream.write_all(b"+")?;
+                    return Ok(data);
+                },
+                false => self.stream.write_all(b"-")?,
+            }
+        }
+    }
+
+    /// Sends a packet until the client acknowledges it
+    fn send_packet(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum(data));
+        loop {
+            self.stream.write_all(packet.as_bytes())?;
+            match self.read_byte()? {
+                b'+' => return Ok(()),
+                _ => continue,
+            }
+        }
+    }
+
+    fn run(&mut self) -> io::Result<()> {
+        loop {
+            let packet = self.read_packet()?;
+            let reply = match packet.as_bytes().first() {
+                Some(b'D') => {
+                    self.send_packet("OK")?;
+                    return Ok(());
+                },
+                Some(b'k') => return Ok(()),
+                _ => self.handle(&packet)?,
+            };
+            self.send_packet(&reply)?;
+        }
+    }
+
+    /// Reply to `packet`, empty for unsupported packets
+    fn handle(&mut self, packet: &str) -> io::Result<String> {
+        let command_len = packet.chars().next().map_or(0, char::len_utf8);
+        let (command, args) = packet.split_at(command_len);
+        let reply = match command {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "g" => hex(&register_bytes(&self.sys.registers())),
+            "G" => self.write_registers(args),
+            "p" => self.read_register(args),
+            "P" => self.write_register(args),
+            "m" => self.read_memory(args),
+            "M" => self.write_memory(args),
+            "Z" | "z" => self.update_breakpoint(command == "Z", args),
+            "s" | "c" => {
+                if let Some(address) = parse_hex(args) {
+                    let mut regs = self.sys.registers();
+                    regs.pc = address;
+                    self.sys.set_registers(regs);
+                }
+                match command {
+                    "s" => self.single_step(),
+                    _ => self.resume()?,
+                }
+            },
+            "H" => "OK".to_string(),
+            "q" if args.starts_with("Supported") => "PacketSize=1000".to_string(),
+            "q" if args == "Attached" => "1".to_string(),
+            _ => String::new(),
+        };
+        Ok(reply)
+    }
+
+    fn write_registers(&mut self, args: &str) -> String {
+        match parse_hex_bytes(args).filter(|bytes| bytes.len() == 7) {
+            Some(bytes) => {
+                self.sys.set_registers(Registers {
+                    a: bytes[0],
+                    x: bytes[1],
+                    y: bytes[2],
+                    s: bytes[3],
+                    p: bytes[4],
+                    pc: u16::from_le_bytes([bytes[5], bytes[6]]),
+                });
+                "OK".to_string()
+            },
+            None => "E01".to_string(),
+        }
+    }
+
+    fn read_register(&mut self, args: &str) -> String {
+        let bytes = register_bytes(&self.sys.registers());
+        match usize::from_str_radix(args, 16) {
+            Ok(REG_PC) => hex(&bytes[REG_PC..]),
+            Ok(reg) if reg < REGISTER_COUNT => hex(&bytes[reg..reg + 1]),
+            _ => "E01".to_string(),
+        }
+    }
+
+    fn write_register(&mut self, args: &str) -> String {
+        let (reg, value) = match args.split_once('=') {
+            Some((reg, value)) => (usize::from_str_radix(reg, 16).ok(), parse_hex_bytes(value)),
+            None => (None, None),
+        };
+        let mut regs = self.sys.registers();
+        match (reg, value.as_deref()) {
+            (Some(REG_PC), Some(&[lo, hi])) => regs.pc = u16::from_le_bytes([lo, hi]),
+            (Some(0), Some(&[val])) => regs.a = val,
+            (Some(1), Some(&[val])) => regs.x = val,
+            (Some(2), Some(&[val])) => regs.y = val,
+            (Some(3), Some(&[val])) => regs.s = val,
+            (Some(4), Some(&[val])) => regs.p = val,
+            _ => return "E01".to_string(),
+        }
+        self.sys.set_registers(regs);
+        "OK".to_string()
+    }
+
+    /// Reads up to the first address that cannot be peeked at
+    fn read_memory(&mut self, args: &str) -> String {
+        let (address, len) = match parse_range(args) {
+            Some(range) => range,
+            None => return "E01".to_string(),
+        };
+        let bytes: Vec<u8> = (0..len).map_while(|offset| self.sys.peek(address.wrapping_add(offset))).collect();
+        match bytes.is_empty() && len > 0 {
+            true => "E01".to_string(),
+            false => hex(&bytes),
+        }
+    }
+
+    fn write_memory(&mut self, args: &str) -> String {
+        let parsed = args.split_once(':').and_then(|(range, data)| Some((parse_range(range)?, parse_hex_bytes(data)?)));
+        let (address, bytes) = match parsed {
+            Some(((address, len), bytes)) if bytes.len() == len as usize => (address, bytes),
+            _ => return "E01".to_string(),
+        };
+        for (offset, byte) in bytes.iter().enumerate() {
+            if self.sys.poke(address.wrapping_add(offset as u16), *byte).is_err() {
+                return "E01".to_string();
+            }
+        }
+        "OK".to_string()
+    }
+
+    /// Z or z packet: "type,addr,kind", kind being the length for watchpoints
+    fn update_breakpoint(&mut self, insert: bool, args: &str) -> String {
+        let fields: Vec<Option<u16>> = args.splitn(3, ',').map(parse_hex).collect();
+        let (tpe, address, len) = match fields.as_slice() {
+            &[Some(tpe), Some(address), Some(len)] => (tpe as u8, address, len),
+            _ => return "E01".to_string(),
+        };
+        let end = address.wrapping_add(len.max(1) - 1);
+        let breakpoints = match tpe {
+            Z_SOFTWARE | Z_HARDWARE => vec![Breakpoint::Execute { address, bank: None }],
+            Z_WRITE => vec![Breakpoint::Write { start: address, end }],
+            Z_READ => vec![Breakpoint::Read { start: address, end }],
+            Z_ACCESS => vec![Breakpoint::Read { start: address, end }, Breakpoint::Write { start: address, end }],
+            _ => return String::new(),
+        };
+
+        let key = (tpe, address, len);
+        if insert {
+            let ids = breakpoints.into_iter().map(|bp| self.sys.add_breakpoint(bp)).collect();
+            if let Some(old) = self.breakpoints.insert(key, ids) {
+                old.into_iter().for_each(|id| { self.sys.remove_breakpoint(id); });
+            }
+        } else if let Some(ids) = self.breakpoints.remove(&key) {
+            ids.into_iter().for_each(|id| { self.sys.remove_breakpoint(id); });
+        }
+        "OK".to_string()
+    }
+
+    fn error_signal(err: ExecError) -> u8 {
+        match err {
+            ExecError::BusFault(_) => SIGSEGV,
+            ExecError::IllegalOpcode { .. } | ExecError::Halted { .. } => SIGILL,
+        }
+    }
+
+    fn single_step(&mut self) -> String {
+        match self.sys.step() {
+            Ok(_) => format!("S{:02x}", SIGTRAP),
+            Err(err) => format!("S{:02x}", Self::error_signal(err)),
+        }
+    }
+
+    /// Runs until a stop or an interrupt from the client
+    fn resume(&mut self) -> io::Result<String> {
+        self.stream.set_nonblocking(true)?;
+        let stream = &mut self.stream;
+        let mut steps = 0u32;
+        let mut poll_error = None;
+        let result = self.sys.run_until(|_, _| {
+            steps = steps.wrapping_add(1);
+            if !steps.is_multiple_of(INTERRUPT_POLL_STEPS) {
+                return false;
+            }
+            let mut byte = [0u8];
+            match stream.read(&mut byte) {
+                Ok(1) => byte[0] == INTERRUPT,
+                Ok(_) => {
+                    poll_error = Some(io::Error::from(io::ErrorKind::UnexpectedEof));
+                    true
+                },
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => false,
+                Err(err) => {
+                    poll_error = Some(err);
+                    true
+                },
+            }
+        });
+        self.stream.set_nonblocking(false)?;
+        if let Some(err) = poll_error {
+            return Err(err);
+        }
+
+        let reply = match result {
+            Ok(StopReason::Condition) => format!("S{:02x}", SIGINT),
+            Ok(StopReason::Watchpoint { id, address, access, .. }) => {
+                let is_access = self.breakpoints.iter().any(|(&(tpe, _, _), ids)| tpe == Z_ACCESS && ids.contains(&id));
+                let kind = match (is_access, access) {
+                    (true, _) => "awatch",
+                    (false, MemoryAccessType::Load) => "rwatch",
+                    (false, MemoryAccessType::Store) => "watch",
+                };
+                format!("T{:02x}{}:{:04x};", SIGTRAP, kind, address)
+            },
+            Ok(StopReason::Jammed { .. }) | Ok(StopReason::IllegalOpcode { .. }) => format!("S{:02x}", SIGILL),
+            Ok(_) => format!("S{:02x}", SIGTRAP),
+            Err(err) => format!("S{:02x}", Self::error_signal(err)),
+        };
+        Ok(reply)
+    }
+}
+
+#[cfg(test)]
+mod gdb_tests {
+
+    use super::*;
+    use std::thread;
+    use super::super::cpu::CpuVariant;
+    use super::super::cpu::FamicomMemory;
+
+    /// Client side of the protocol, sending packets and returning the replies
+    struct Client {
+        stream: TcpStream,
+    }
+
+    impl Client {
+        fn read_byte(&mut self) -> u8 {
+            let mut byte = [0u8];
+            self.stream.read_exact(&mut byte).unwrap();
+            byte[0]
+        }
+
+        fn send(&mut self, data: &str) {
+            write!(self.stream, "${}#{:02x}", data, checksum(data)).unwrap();
+            assert_eq!(self.read_byte(), b'+');
+        }
+
+        fn reply(&mut self) -> String {
+            assert_eq!(self.read_byte(), b'$');
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte() {
+                    b'#' => break,
+                    byte => data.push(byte),
+                }
+            }
+            self.read_byte();
+            self.read_byte();
+            self.stream.write_all(b"+").unwrap();
+            String::from_utf8(data).unwrap()
+        }
+
+        fn request(&mut self, data: &str) -> String {
+            self.send(data);
+            self.reply()
+        }
+    }
+
+    /// Serves a system running `program` from $0200, with `session` as the client
+    fn with_client<F: FnOnce(&mut Client) + Send + 'static>(program: &[u8], session: F) -> System<FamicomMemory> {
+        let mut sys: System<FamicomMemory> = System::with_variant(CpuVariant::Nmos6502);
+        for (idx, byte) in program.iter().enumerate() {
+            sys.poke(0x0200 + idx as u16, *byte).unwrap();
+        }
+        let mut regs = sys.registers();
+        regs.pc = 0x0200;
+        sys.set_registers(regs);
+
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let client = thread::spawn(move || {
+            let mut client = Client { stream: TcpStream::connect(("127.0.0.1", port)).unwrap() };
+            session(&mut client);
+            assert_eq!(client.request("D"), "OK");
+        });
+        serve(&mut sys, &listener).unwrap();
+        client.join().unwrap();
+        sys
+    }
+
+    #[test]
+    fn registers_and_memory() {
+        let sys = with_client(&[0xA9, 0x05], |client| {
+            assert_eq!(client.request("?"), "S05");
+            assert_eq!(client.request("g"), "00000000000002");
+            assert_eq!(client.request("P1=42"), "OK");
+            assert_eq!(client.request("p1"), "42");
+            assert_eq!(client.request("p5"), "0002");
+            assert_eq!(client.request("G0102030405ff01"), "OK");
+            assert_eq!(client.request("g"), "0102030405ff01");
+
+            assert_eq!(client.request("m200,2"), "a905");
+            assert_eq!(client.request("M10,3:aabbcc"), "OK");
+            assert_eq!(client.request("m10,4"), "aabbcc00");
+            // Reads stop at unreadable memory, writes to ROM are ignored
+            assert_eq!(client.request("m1ffe,4"), "0000");
+            assert_eq!(client.request("m2000,1"), "E01");
+            assert_eq!(client.request("M8000,1:01"), "OK");
+            assert_eq!(client.request("m8000,1"), "00");
+            assert_eq!(client.request("vMustReplyEmpty"), "");
+        });
+        assert_eq!(sys.registers(), Registers { a: 1, x: 2, y: 3, s: 4, p: 5, pc: 0x01FF });
+        assert_eq!(sys.peek(0x0011), Some(0xBB));
+    }
+
+    #[test]
+    fn breakpoints_steps_and_continue() {
+        // loop: INX; STX $10; JMP loop
+        let sys = with_client(&[0xE8, 0x86, 0x10, 0x4C, 0x00, 0x02], |client| {
+            assert_eq!(client.request("s"), "S05");
+            assert_eq!(client.request("p5"), "0102");
+
+            assert_eq!(client.request("Z0,203,1"), "OK");
+            assert_eq!(client.request("c"), "S05");
+            assert_eq!(client.request("p5"), "0302");
+            assert_eq!(client.request("z0,203,1"), "OK");
+
+            assert_eq!(client.request("Z2,10,1"), "OK");
+            assert_eq!(client.request("c"), "T05watch:0010;");
+            assert_eq!(client.request("z2,10,1"), "OK");
+            assert_eq!(client.request("Z4,10,1"), "OK");
+            assert_eq!(client.request("c"), "T05awatch:0010;");
+            assert_eq!(client.request("z4,10,1"), "OK");
+        });
+        assert_eq!(sys.registers().x, 3);
+        assert_eq!(sys.breakpoints().count(), 0);
+    }
+
+    #[test]
+    fn interrupting_a_running_target() {
+        // loop: JMP loop
+        with_client(&[0x4C, 0x00, 0x02], |client| {
+            client.send("c");
+            thread::sleep(std::time::Duration::from_millis(50));
+            client.stream.write_all(&[INTERRUPT]).unwrap();
+            assert_eq!(client.reply(), "S02");
+            assert_eq!(client.request("p5"), "0002");
+        });
+    }
+}
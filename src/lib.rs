@@ -0,0 +1,23 @@
+//! Emulator of the 6502 family of CPUs: the NMOS 6502, the Ricoh 2A03 of the NES/Famicom and
+//! the CMOS 65C02, with an assembler, a disassembler, a machine-language monitor and a GDB stub.
+//!
+//! A `System` runs a CPU over any memory implementing `IO6502`. `FlatMemory` is 64K of RAM for
+//! generic programs, `FamicomMemory` the NES address space, and a `Bus` assembles systems from
+//! memory and `Device`s mapped on address ranges.
+
+pub mod cpu;
+pub mod assembler;
+pub mod disassembler;
+pub mod gdb;
+pub mod monitor;
+
+pub use cpu::Bus;
+pub use cpu::Device;
+pub use cpu::DeviceId;
+pub use cpu::FlatMemory;
+pub use cpu::CpuVariant;
+pub use cpu::ExecError;
+pub use cpu::FamicomMemory;
+pub use cpu::StopReason;
+pub use cpu::System;
+pub use cpu::IO6502;
@@ -1,13 +1,61 @@
-#![allow(dead_code)]
+use std::env;
+use std::io;
+use std::io::BufRead;
+use std::io::Write;
+use std::process;
+use cpu_6502_rs::CpuVariant;
+use cpu_6502_rs::FamicomMemory;
+use cpu_6502_rs::System;
+use cpu_6502_rs::monitor::Monitor;
 
-mod cpu;
-mod assembler;
+const USAGE: &str = "usage: cpu-6502-rs [--cpu 6502|2a03|65c02] [file]";
 
 fn main() {
-    let _test = 0b10000000u8;
-    let _testt = 0b01000000u8;
-    let _testtt = 0b00100000u8;
+    let mut variant = CpuVariant::Nmos6502;
+    let mut file = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--cpu" => variant = match args.next().as_deref() {
+                Some("6502") => CpuVariant::Nmos6502,
+                Some("2a03") => CpuVariant::Ricoh2A03,
+                Some("65c02") => CpuVariant::Cmos65C02,
+                _ => {
+                    eprintln!("{}", USAGE);
+                    process::exit(2);
+                },
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            },
+            _ => file = Some(arg),
+        }
+    }
 
-    // println!("{}", _testt.overflowing_shl(2).1);
-    assembler::parse_program("dfsfsdf");
-}
\ No newline at end of file
+    let sys: System<FamicomMemory> = System::with_variant(variant);
+    let mut monitor = Monitor::new(sys);
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    if let Some(file) = file {
+        monitor.execute(&format!("l {}", file), &mut out).expect("cannot write to stdout");
+    }
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        write!(out, ".").and_then(|_| out.flush()).expect("cannot write to stdout");
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => break,
+        };
+        match monitor.execute(&line, &mut out) {
+            Ok(true) => {},
+            Ok(false) => break,
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(1);
+            },
+        }
+    }
+}
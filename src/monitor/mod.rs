@@ -0,0 +1,543 @@
+//! Machine-language monitor in the spirit of the classic ones: one command per line, numbers in
+//! hexadecimal with an optional `$`. Everything goes through the public API of `System`.
+
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::net::TcpListener;
+use super::assembler;
+use super::assembler::AssemblyError;
+use super::cpu::datastructures::word;
+use super::cpu::datastructures::InstructionStream;
+use super::cpu::Breakpoint;
+use super::cpu::BreakpointId;
+use super::cpu::BusFault;
+use super::cpu::ExecError;
+use super::cpu::IO6502;
+use super::cpu::MemoryAccessType;
+use super::cpu::StopReason;
+use super::cpu::System;
+use super::disassembler;
+use super::gdb;
+
+const HELP: &str = "\
+r                      show registers
+r <reg> <value>        set A, X, Y, S, P or PC
+m [start [end]]        dump memory
+d [start [end]]        disassemble
+a <addr> <instr>       assemble an instruction, e.g. a 0200 LDA #$01
+g [addr]               go until a breakpoint or another stop, at most 10M cycles
+t [count]              trace count instructions, 10 by default
+s                      step one instruction
+o                      step over a subroutine call
+u                      run until the current subroutine returns
+b [addr]               list breakpoints, or break at addr
+br <start> [end]       break on reads
+bw <start> [end]       break on writes
+bo <opcode>            break on an opcode
+bi                     break on interrupts
+bc <id>                clear a breakpoint
+f <start> <end> <byte>...  fill memory with a pattern
+c <start> <end> <dest> compare memory
+x <start> <end> <dest> transfer memory
+l <file>               load an iNES ROM or a raw binary, then reset
+reset                  reset the CPU
+gdb [port]             serve a GDB remote debugger on localhost until it detaches
+q                      quit";
+
+/// Cycle budget of `g`, so that a program stuck in a loop gives the prompt back
+const GO_CYCLES: u64 = 10_000_000;
+const DUMP_LINE: u16 = 16;
+const DUMP_LINES: u16 = 8;
+const DISASSEMBLY_LINES: usize = 16;
+const TRACE_COUNT: u64 = 10;
+
+const INES_MAGIC: &[u8; 4] = b"NES\x1A";
+const INES_HEADER_LEN: usize = 16;
+const PRG_BANK_LEN: usize = 0x4000;
+
+/// Why a command failed: mistakes are reported to the user, output errors end the monitor
+enum CommandError {
+    Invalid(String),
+    Output(io::Error),
+}
+
+impl From<String> for CommandError {
+    fn from(err: String) -> Self {
+        CommandError::Invalid(err)
+    }
+}
+
+impl From<&str> for CommandError {
+    fn from(err: &str) -> Self {
+        CommandError::Invalid(err.to_string())
+    }
+}
+
+impl From<io::Error> for CommandError {
+    fn from(err: io::Error) -> Self {
+        CommandError::Output(err)
+    }
+}
+
+impl From<ExecError> for CommandError {
+    fn from(err: ExecError) -> Self {
+        CommandError::Invalid(err.to_string())
+    }
+}
+
+impl From<BusFault> for CommandError {
+    fn from(fault: BusFault) -> Self {
+        ExecError::from(fault).into()
+    }
+}
+
+impl From<AssemblyError> for CommandError {
+    fn from(err: AssemblyError) -> Self {
+        CommandError::Invalid(err.to_string())
+    }
+}
+
+type CommandResult = Result<(), CommandError>;
+
+pub struct Monitor<T: IO6502> {
+    sys: System<T>,
+    /// Where `m` and `d` go on when no address is given
+    next_dump: u16,
+    next_disassembly: u16,
+}
+
+/// Hexadecimal number, with an optional `$`
+fn parse_number(arg: &str) -> Result<u16, String> {
+    u16::from_str_radix(arg.trim_start_matches('$'), 16).map_err(|_| format!("invalid number {}", arg))
+}
+
+fn parse_byte(arg: &str) -> Result<u8, String> {
+    match parse_number(arg)? {
+        byte @ 0..=0xFF => Ok(byte as u8),
+        _ => Err(format!("{} does not fit in a byte", arg)),
+    }
+}
+
+/// Parses the argument at `idx`, `default` if there is none
+fn argument(args: &[&str], idx: usize, default: Option<u16>) -> Result<u16, String> {
+    match args.get(idx) {
+        Some(arg) => parse_number(arg),
+        None => default.ok_or_else(|| "missing argument".to_string()),
+    }
+}
+
+/// Bytes of an iNES ROM mapped at $8000-$FFFF, a single 16K PRG bank being mirrored
+fn ines_program(rom: &[u8]) -> Result<Vec<word>, String> {
+    let banks = *rom.get(4).ok_or("truncated iNES header")? as usize;
+    if banks != 1 && banks != 2 {
+        return Err(format!("unsupported PRG size of {} banks", banks));
+    }
+    let prg = rom.get(INES_HEADER_LEN..INES_HEADER_LEN + banks * PRG_BANK_LEN).ok_or("truncated PRG ROM")?;
+    Ok(prg.iter().cycle().take(2 * PRG_BANK_LEN).map(|b| word::from(*b)).collect())
+}
+
+fn describe_stop(reason: StopReason) -> String {
+    match reason {
+        StopReason::Breakpoint { id, pc } => format!("breakpoint {} at ${:04X}", id.0, pc),
+        StopReason::Watchpoint { id, pc, address, access } => {
+            let access = match access {
+                MemoryAccessType::Load => "read from",
+                MemoryAccessType::Store => "write to",
+            };
+            format!("breakpoint {}: {} ${:04X} by ${:04X}", id.0, access, address, pc)
+        },
+        StopReason::Jammed { pc } => format!("jammed at ${:04X}", pc),
+        StopReason::IllegalOpcode { pc, opcode } => format!("undocumented opcode ${:02X} at ${:04X}", opcode, pc),
+        StopReason::CycleBudget => "cycle budget exhausted".to_string(),
+        StopReason::InstructionBudget => "instruction budget exhausted".to_string(),
+        StopReason::Trap { pc } => format!("trapped at ${:04X}", pc),
+        StopReason::Condition => "condition met".to_string(),
+        StopReason::StepComplete => "step complete".to_string(),
+    }
+}
+
+impl<T: IO6502> Monitor<T> {
+    pub fn new(sys: System<T>) -> Self {
+        let pc = sys.registers().pc;
+        Self { sys, next_dump: pc, next_disassembly: pc }
+    }
+
+    pub fn system(&self) -> &System<T> {
+        &self.sys
+    }
+
+    /// Runs a command line, writing its output to `out`. Returns false when the user quits
+    pub fn execute<W: Write>(&mut self, line: &str, out: &mut W) -> io::Result<bool> {
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command.to_ascii_lowercase(),
+            None => return Ok(true),
+        };
+        let args: Vec<&str> = words.collect();
+        if command == "q" {
+            return Ok(false);
+        }
+        match self.run_command(&command, &args, line, out) {
+            Ok(()) => Ok(true),
+            Err(CommandError::Invalid(err)) => writeln!(out, "error: {}", err).map(|_| true),
+            Err(CommandError::Output(err)) => Err(err),
+        }
+    }
+
+    fn run_command<W: Write>(&mut self, command: &str, args: &[&str], line: &str, out: &mut W) -> CommandResult {
+        match command {
+            "h" | "?" => Ok(writeln!(out, "{}", HELP)?),
+            "r" if args.is_empty() => self.show_registers(out),
+            "r" => self.set_register(args),
+            "m" => self.dump(args, out),
+            "d" => self.disassemble(args, out),
+            "a" => self.assemble(line, args, out),
+            "g" => self.go(args, out),
+            "t" => self.trace(args, out),
+            "s" => self.step(out),
+            "o" => {
+                let reason = self.sys.step_over();
+                self.report(reason, out)
+            },
+            "u" => {
+                let reason = self.sys.step_out();
+                self.report(reason, out)
+            },
+            "b" if args.is_empty() => self.list_breakpoints(out),
+            "b" => self.add_breakpoint(Breakpoint::Execute { address: argument(args, 0, None)?, bank: None }, out),
+            "br" | "bw" => {
+                let start = argument(args, 0, None)?;
+                let end = argument(args, 1, Some(start))?;
+                let breakpoint = match command {
+                    "br" => Breakpoint::Read { start, end },
+                    _ => Breakpoint::Write { start, end },
+                };
+                self.add_breakpoint(breakpoint, out)
+            },
+            "bo" => self.add_breakpoint(Breakpoint::Opcode(parse_byte(args.first().ok_or("missing opcode")?)?), out),
+            "bi" => self.add_breakpoint(Breakpoint::Interrupt, out),
+            "bc" => self.clear_breakpoint(args),
+            "f" => self.fill(args),
+            "c" => self.compare(args, out),
+            "x" => self.transfer(args),
+            "l" => self.load(args, out),
+            "gdb" => self.serve_gdb(args, out),
+            "reset" => {
+                self.sys.reset()?;
+                self.show_registers(out)
+            },
+            _ => Err(format!("unknown command {}, h for help", command).into()),
+        }
+    }
+
+    fn show_registers<W: Write>(&mut self, out: &mut W) -> CommandResult {
+        let regs = self.sys.registers();
+        writeln!(out, "  PC  A  X  Y  S  P  NV-BDIZC  CYCLES")?;
+        writeln!(out, "{:04X} {:02X} {:02X} {:02X} {:02X} {:02X}  {:08b}  {}",
+                 regs.pc, regs.a, regs.x, regs.y, regs.s, regs.p, regs.p, self.sys.cycles())
+            ?;
+        self.next_disassembly = regs.pc;
+        Ok(())
+    }
+
+    fn set_register(&mut self, args: &[&str]) -> CommandResult {
+        let value = argument(args, 1, None)?;
+        let mut regs = self.sys.registers();
+        match args[0].to_ascii_lowercase().as_str() {
+            "pc" => regs.pc = value,
+            reg => {
+                let byte = parse_byte(args[1])?;
+                match reg {
+                    "a" => regs.a = byte,
+                    "x" => regs.x = byte,
+                    "y" => regs.y = byte,
+                    "s" => regs.s = byte,
+                    "p" => regs.p = byte,
+                    _ => return Err(format!("unknown register {}", reg).into()),
+                }
+            },
+        }
+        self.sys.set_registers(regs);
+        Ok(())
+    }
+
+    fn dump<W: Write>(&mut self, args: &[&str], out: &mut W) -> CommandResult {
+        let start = argument(args, 0, Some(self.next_dump))?;
+        let end = argument(args, 1, Some(start.saturating_add(DUMP_LINE * DUMP_LINES - 1)))?;
+        let mut address = start as u32;
+        while address <= end as u32 {
+            let line: Vec<Option<u8>> = (address..=(address + DUMP_LINE as u32 - 1).min(end as u32))
+                .map(|addr| self.sys.peek(addr as u16))
+                .collect();
+            let hex: Vec<String> = line.iter()
+                .map(|byte| byte.map_or_else(|| "--".to_string(), |b| format!("{:02X}", b)))
+                .collect();
+            let ascii: String = line.iter()
+                .map(|byte| match byte {
+                    Some(b @ 0x20..=0x7E) => *b as char,
+                    _ => '.',
+                })
+                .collect();
+            writeln!(out, "{:04X}  {:<47}  {}", address, hex.join(" "), ascii)?;
+            address += DUMP_LINE as u32;
+        }
+        self.next_dump = (end as u32 + 1) as u16;
+        Ok(())
+    }
+
+    fn disassemble<W: Write>(&mut self, args: &[&str], out: &mut W) -> CommandResult {
+        let start = argument(args, 0, Some(self.next_disassembly))?;
+        let end = args.get(1).map(|arg| parse_number(arg)).transpose()?;
+        let mut address = start;
+        let mut count = 0;
+        loop {
+            if end.map_or(count == DISASSEMBLY_LINES, |end| address > end || (count > 0 && address < start)) {
+                break;
+            }
+            let bytes: Vec<word> = (0..3u16)
+                .map_while(|idx| self.sys.peek(address.wrapping_add(idx)).map(word::from))
+                .collect();
+            let (text, len) = disassembler::disassemble_instruction(self.sys.variant(), &bytes).unwrap_or_else(|| ("???".to_string(), 1));
+            let hex: Vec<String> = bytes.iter().take(len).map(|b| format!("{:02X}", b.native_value())).collect();
+            writeln!(out, "{:04X}  {:<8}  {}", address, hex.join(" "), text)?;
+            address = address.wrapping_add(len as u16);
+            count += 1;
+        }
+        self.next_disassembly = address;
+        Ok(())
+    }
+
+    fn assemble<W: Write>(&mut self, line: &str, args: &[&str], out: &mut W) -> CommandResult {
+        let address = argument(args, 0, None)?;
+        // The instruction is whatever follows the address, as typed
+        let instr = line.trim_start()[1..].trim_start()[args[0].len()..].trim().to_ascii_uppercase();
+        let stream = assembler::assemble_line(self.sys.variant(), &instr)?;
+        for (idx, byte) in stream.stream.iter().enumerate() {
+            self.sys.poke(address.wrapping_add(idx as u16), byte.native_value())?;
+        }
+        let next = address.wrapping_add(stream.stream.len() as u16);
+        self.next_disassembly = next;
+        writeln!(out, "a {:04X}", next)?;
+        Ok(())
+    }
+
+    fn report<W: Write>(&mut self, reason: Result<StopReason, ExecError>, out: &mut W) -> CommandResult {
+        let reason = reason?;
+        writeln!(out, "{}", describe_stop(reason))?;
+        self.show_registers(out)
+    }
+
+    fn go<W: Write>(&mut self, args: &[&str], out: &mut W) -> CommandResult {
+        if let Some(arg) = args.first() {
+            let mut regs = self.sys.registers();
+            regs.pc = parse_number(arg)?;
+            self.sys.set_registers(regs);
+        }
+        let reason = self.sys.run_for_cycles(GO_CYCLES);
+        self.report(reason, out)
+    }
+
+    fn trace<W: Write>(&mut self, args: &[&str], out: &mut W) -> CommandResult {
+        let count = argument(args, 0, Some(TRACE_COUNT as u16))?;
+        for _ in 0..count {
+            let line = self.sys.trace_line();
+            self.sys.step()?;
+            writeln!(out, "{}", line)?;
+        }
+        self.next_disassembly = self.sys.registers().pc;
+        Ok(())
+    }
+
+    fn step<W: Write>(&mut self, out: &mut W) -> CommandResult {
+        self.sys.step()?;
+        self.show_registers(out)
+    }
+
+    fn list_breakpoints<W: Write>(&mut self, out: &mut W) -> CommandResult {
+        for (id, breakpoint) in self.sys.breakpoints() {
+            let text = match breakpoint {
+                Breakpoint::Execute { address, bank: None } => format!("at ${:04X}", address),
+                Breakpoint::Execute { address, bank: Some(bank) } => format!("at ${:04X} in bank {}", address, bank),
+                Breakpoint::Read { start, end } => format!("reads from ${:04X}-${:04X}", start, end),
+                Breakpoint::Write { start, end } => format!("writes to ${:04X}-${:04X}", start, end),
+                Breakpoint::Opcode(opcode) => format!("opcode ${:02X}", opcode),
+                Breakpoint::Interrupt => "interrupts".to_string(),
+            };
+            writeln!(out, "{:>3}  {}", id.0, text)?;
+        }
+        Ok(())
+    }
+
+    fn add_breakpoint<W: Write>(&mut self, breakpoint: Breakpoint, out: &mut W) -> CommandResult {
+        let id = self.sys.add_breakpoint(breakpoint);
+        writeln!(out, "breakpoint {}", id.0)?;
+        Ok(())
+    }
+
+    fn clear_breakpoint(&mut self, args: &[&str]) -> CommandResult {
+        let id = args.first().ok_or("missing breakpoint id")?;
+        let id = BreakpointId(id.parse().map_err(|_| format!("invalid breakpoint id {}", id))?);
+        match self.sys.remove_breakpoint(id) {
+            Some(_) => Ok(()),
+            None => Err(format!("no breakpoint {}", id.0).into()),
+        }
+    }
+
+    /// Start, end and destination of `c` and `x`
+    fn ranges(args: &[&str]) -> Result<(u16, u16, u16), CommandError> {
+        let start = argument(args, 0, None)?;
+        let end = argument(args, 1, None)?;
+        let dest = argument(args, 2, None)?;
+        if end < start {
+            return Err("the range ends before it starts".into());
+        }
+        Ok((start, end, dest))
+    }
+
+    fn fill(&mut self, args: &[&str]) -> CommandResult {
+        let start = argument(args, 0, None)?;
+        let end = argument(args, 1, None)?;
+        let pattern = args.get(2..).filter(|bytes| !bytes.is_empty()).ok_or("missing fill pattern")?
+            .iter()
+            .map(|byte| parse_byte(byte))
+            .collect::<Result<Vec<u8>, String>>()?;
+        for (address, byte) in (start..=end).zip(pattern.iter().cycle()) {
+            self.sys.poke(address, *byte)?;
+        }
+        Ok(())
+    }
+
+    fn compare<W: Write>(&mut self, args: &[&str], out: &mut W) -> CommandResult {
+        let (start, end, dest) = Self::ranges(args)?;
+        let show = |byte: Option<u8>| byte.map_or_else(|| "--".to_string(), |b| format!("{:02X}", b));
+        for offset in 0..=(end - start) {
+            let (lhs, rhs) = (start.wrapping_add(offset), dest.wrapping_add(offset));
+            let (lhs_val, rhs_val) = (self.sys.peek(lhs), self.sys.peek(rhs));
+            if lhs_val != rhs_val {
+                writeln!(out, "{:04X} {}  {:04X} {}", lhs, show(lhs_val), rhs, show(rhs_val))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn transfer(&mut self, args: &[&str]) -> CommandResult {
+        let (start, end, dest) = Self::ranges(args)?;
+        // Copies backwards when moving up, so that overlapping ranges are handled
+        let offsets: Box<dyn Iterator<Item = u16>> = match dest > start {
+            true => Box::new((0..=(end - start)).rev()),
+            false => Box::new(0..=(end - start)),
+        };
+        for offset in offsets {
+            let from = start.wrapping_add(offset);
+            let byte = self.sys.peek(from).ok_or_else(|| format!("${:04X} cannot be read", from))?;
+            self.sys.poke(dest.wrapping_add(offset), byte)?;
+        }
+        Ok(())
+    }
+
+    fn serve_gdb<W: Write>(&mut self, args: &[&str], out: &mut W) -> CommandResult {
+        let port = match args.first() {
+            Some(port) => port.parse().map_err(|_| format!("invalid port {}", port))?,
+            None => gdb::DEFAULT_PORT,
+        };
+        let listener = TcpListener::bind(("127.0.0.1", port)).map_err(|err| format!("cannot listen on port {}: {}", port, err))?;
+        writeln!(out, "waiting for a debugger on 127.0.0.1:{}", port)?;
+        out.flush()?;
+        gdb::serve(&mut self.sys, &listener).map_err(|err| format!("debugger connection lost: {}", err))?;
+        writeln!(out, "debugger detached")?;
+        self.show_registers(out)
+    }
+
+    fn load<W: Write>(&mut self, args: &[&str], out: &mut W) -> CommandResult {
+        let path = args.first().ok_or("missing file name")?;
+        let bytes = fs::read(path).map_err(|err| format!("{}: {}", path, err))?;
+        let program = match bytes.starts_with(INES_MAGIC) {
+            true => ines_program(&bytes)?,
+            false => bytes.iter().map(|b| word::from(*b)).collect(),
+        };
+        writeln!(out, "loaded {} bytes", bytes.len())?;
+        self.sys.memory_mut().push_program(InstructionStream::from(program));
+        self.sys.reset()?;
+        self.show_registers(out)
+    }
+}
+
+#[cfg(test)]
+mod monitor_tests {
+
+    use super::*;
+    use super::super::cpu::CpuVariant;
+    use super::super::cpu::FamicomMemory;
+
+    /// Runs the commands, returning the output of the last one
+    fn run(monitor: &mut Monitor<FamicomMemory>, commands: &[&str]) -> String {
+        let mut out = Vec::new();
+        for command in commands {
+            out.clear();
+            assert!(monitor.execute(command, &mut out).unwrap());
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    fn new_monitor() -> Monitor<FamicomMemory> {
+        Monitor::new(System::with_variant(CpuVariant::Nmos6502))
+    }
+
+    #[test]
+    fn assemble_and_disassemble() {
+        let mut monitor = new_monitor();
+        assert_eq!(run(&mut monitor, &["a 0200 lda #$05"]), "a 0202\n");
+        assert_eq!(run(&mut monitor, &["a $0202 STA $10", "a 0204 inx", "d 0200 0204"]),
+                   "0200  A9 05     LDA #$05\n0202  85 10     STA $10\n0204  E8        INX\n");
+        assert_eq!(run(&mut monitor, &["a 0200 LDA"]), "error: impossible instruction - operand combination: LDA Implied\n");
+    }
+
+    #[test]
+    fn assemble_and_disassemble_65c02() {
+        let mut monitor = Monitor::new(System::<FamicomMemory>::with_variant(CpuVariant::Cmos65C02));
+        run(&mut monitor, &["a 0200 stz $10", "a 0202 BRA $02", "a 0204 LDA ($12)", "a 0206 RMB3 $12",
+                            "a 0208 BBS7 $12,$FD", "a 020B JMP ($1234,X)", "a 020E PHX"]);
+        let bytes: Vec<u8> = (0x0200..=0x020E).map(|address| monitor.system().peek(address).unwrap()).collect();
+        assert_eq!(bytes, [0x64, 0x10, 0x80, 0x02, 0xB2, 0x12, 0x37, 0x12, 0xFF, 0x12, 0xFD, 0x7C, 0x34, 0x12, 0xDA]);
+        assert_eq!(run(&mut monitor, &["d 0204 0208"]), "0204  B2 12     LDA ($12)\n0206  37 12     RMB3 $12\n0208  FF 12 FD  BBS7 $12,$FD\n");
+        assert!(run(&mut new_monitor(), &["a 0200 STZ $10"]).starts_with("error: impossible instruction"));
+    }
+
+    #[test]
+    fn registers_and_steps() {
+        let mut monitor = new_monitor();
+        let registers = run(&mut monitor, &["a 0200 LDA #$05", "a 0202 INX", "r pc 0200", "r x 41", "s"]);
+        assert_eq!(registers, "  PC  A  X  Y  S  P  NV-BDIZC  CYCLES\n0202 05 41 00 00 00  00000000  2\n");
+        assert!(run(&mut monitor, &["t 1"]).starts_with("0202  E8        INX"));
+        assert_eq!(monitor.system().registers().x, 0x42);
+        assert_eq!(run(&mut monitor, &["r y 100"]), "error: 100 does not fit in a byte\n");
+    }
+
+    #[test]
+    fn memory_commands() {
+        let mut monitor = new_monitor();
+        run(&mut monitor, &["f 0300 0307 aa bb", "x 0300 0303 0302"]);
+        assert_eq!(run(&mut monitor, &["m 0300 0307"]), format!("0300  {:<47}  {}\n", "AA BB AA BB AA BB AA BB", "........"));
+        assert_eq!(run(&mut monitor, &["c 0300 0301 0304"]), "");
+        assert_eq!(run(&mut monitor, &["c 0300 0301 0305"]), "0300 AA  0305 BB\n0301 BB  0306 AA\n");
+        // Unreadable addresses are shown as --, writes to the cartridge ROM are ignored
+        assert_eq!(run(&mut monitor, &["m 2000 2001"]), format!("2000  {:<47}  ..\n", "-- --"));
+        assert_eq!(run(&mut monitor, &["f 8000 8000 01"]), "");
+        assert_eq!(run(&mut monitor, &["m 8000 8000"]), format!("8000  {:<47}  .\n", "00"));
+    }
+
+    #[test]
+    fn breakpoints_and_go() {
+        let mut monitor = new_monitor();
+        run(&mut monitor, &["a 0200 INX", "a 0201 STX $10", "a 0203 JMP $0200", "r pc 0200"]);
+        assert_eq!(run(&mut monitor, &["bw 10"]), "breakpoint 0\n");
+        assert!(run(&mut monitor, &["g"]).starts_with("breakpoint 0: write to $0010 by $0201\n"));
+        assert_eq!(run(&mut monitor, &["bc 0", "b 0203", "b"]), "  1  at $0203\n");
+        assert!(run(&mut monitor, &["g"]).starts_with("breakpoint 1 at $0203\n"));
+        assert_eq!(monitor.system().registers().x, 2);
+        assert_eq!(run(&mut monitor, &["bc 0"]), "error: no breakpoint 0\n");
+        assert_eq!(run(&mut monitor, &["zz"]), "error: unknown command zz, h for help\n");
+        assert!(!monitor.execute("q", &mut Vec::new()).unwrap());
+    }
+}
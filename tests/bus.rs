@@ -0,0 +1,93 @@
+//! Systems assembled from memory and custom devices on a `Bus`, through the public API only
+
+use cpu_6502_rs::assembler;
+use cpu_6502_rs::cpu::word;
+use cpu_6502_rs::cpu::doubleword;
+use cpu_6502_rs::cpu::SaveState;
+use cpu_6502_rs::cpu::SaveStateError;
+use cpu_6502_rs::cpu::StateReader;
+use cpu_6502_rs::cpu::StateWriter;
+use cpu_6502_rs::Bus;
+use cpu_6502_rs::CpuVariant;
+use cpu_6502_rs::Device;
+use cpu_6502_rs::FamicomMemory;
+use cpu_6502_rs::StopReason;
+use cpu_6502_rs::System;
+use cpu_6502_rs::IO6502;
+
+/// Write-only output port collecting what is sent to it
+#[derive(Default)]
+struct Serial {
+    sent: Vec<u8>,
+}
+
+impl Device for Serial {
+    fn load(&mut self, _offset: doubleword) -> Option<word> {
+        None
+    }
+
+    fn store(&mut self, _offset: doubleword, data: word) -> bool {
+        self.sent.push(data.native_value());
+        true
+    }
+
+    fn peek(&self, _offset: doubleword) -> Option<word> {
+        None
+    }
+}
+
+impl SaveState for Serial {
+    fn save(&self, out: &mut StateWriter) {
+        out.write_u16(self.sent.len() as u16);
+        self.sent.iter().for_each(|byte| out.write_u8(*byte));
+    }
+
+    fn restore(&mut self, input: &mut StateReader) -> Result<(), SaveStateError> {
+        let len = input.read_u16()?;
+        self.sent = (0..len).map(|_| input.read_u8()).collect::<Result<_, _>>()?;
+        Ok(())
+    }
+}
+
+#[test]
+fn custom_device_on_a_bus() {
+    let mut bus = Bus::new();
+    let ram = bus.add_ram(0x1000);
+    bus.map(ram, 0x0000..=0x7FFF, 0x0FFF);
+    let serial = bus.add_device(Serial::default());
+    bus.map(serial, 0xD000..=0xD0FF, 0x0000);
+    let rom = bus.add_rom(0x1000);
+    bus.map(rom, 0xF000..=0xFFFF, 0x0FFF);
+    bus.set_program_origin(0xF000);
+    bus.push_program(assembler::assemble(CpuVariant::Nmos6502, "
+        LDA #$48
+        STA $D000
+        LDA #$49
+        STA $D012
+    ").unwrap());
+    bus.memory_mut(rom).unwrap()[0x0FFD] = word::from(0xF0u8); // Reset vector, low byte 0
+
+    let mut sys = System::with_memory(CpuVariant::Nmos6502, bus);
+    sys.reset().unwrap();
+    assert_eq!(sys.registers().pc, 0xF000);
+    assert_eq!(sys.run_for_instructions(4), Ok(StopReason::InstructionBudget));
+    assert_eq!(sys.memory().device::<Serial>(serial).unwrap().sent, b"HI");
+
+    // Devices are part of save states
+    let state = sys.save_state();
+    sys.memory_mut().device_mut::<Serial>(serial).unwrap().sent.clear();
+    sys.load_state(&state).unwrap();
+    assert_eq!(sys.memory().device::<Serial>(serial).unwrap().sent, b"HI");
+}
+
+#[test]
+fn famicom_open_bus() {
+    let mut sys: System<FamicomMemory> = System::with_variant(CpuVariant::Ricoh2A03);
+    // LDA $5000 reads unmapped space: the high byte of the address is still on the bus
+    sys.memory_mut().push_program(assembler::assemble(CpuVariant::Nmos6502, "LDA $5000").unwrap());
+    let mut registers = sys.registers();
+    registers.pc = 0x8000;
+    sys.set_registers(registers);
+    sys.run_for_instructions(1).unwrap();
+    assert_eq!(sys.registers().a, 0x50);
+}
@@ -0,0 +1,75 @@
+//! Running programs through the public API only, as a crate depending on this one would
+
+use cpu_6502_rs::assembler;
+use cpu_6502_rs::cpu::Breakpoint;
+use cpu_6502_rs::disassembler;
+use cpu_6502_rs::monitor::Monitor;
+use cpu_6502_rs::CpuVariant;
+use cpu_6502_rs::ExecError;
+use cpu_6502_rs::FlatMemory;
+use cpu_6502_rs::StopReason;
+use cpu_6502_rs::System;
+
+const PROGRAM: &str = "
+LDX #$00
+LDA #$05
+STA $0200
+INX
+STX $0201
+STA $F000
+";
+
+fn flat_system() -> System<FlatMemory> {
+    let mut mem = FlatMemory::new();
+    mem.load_at(0x0400, assembler::assemble(CpuVariant::Nmos6502, PROGRAM).unwrap());
+    mem.set_reset_vector(0x0400);
+    mem.protect(0xF000..=0xFFFF);
+    let mut sys = System::with_memory(CpuVariant::Nmos6502, mem);
+    sys.reset().unwrap();
+    sys
+}
+
+#[test]
+fn assembled_program_runs_on_flat_memory() {
+    let mut sys = flat_system();
+    assert_eq!(sys.registers().pc, 0x0400);
+
+    let id = sys.add_breakpoint(Breakpoint::Execute { address: 0x040B, bank: None });
+    assert_eq!(sys.run(), Ok(StopReason::Breakpoint { id, pc: 0x040B }));
+    assert_eq!(sys.peek(0x0200), Some(0x05));
+    let registers = sys.registers();
+    assert_eq!((registers.a, registers.x), (0x05, 0x01));
+
+    // The last store hits the protected region
+    assert!(sys.remove_breakpoint(id).is_some());
+    assert!(matches!(sys.run_for_instructions(2), Err(ExecError::BusFault(_))));
+    assert_eq!(sys.peek(0x0201), Some(0x01));
+    assert_eq!(sys.peek(0xF000), Some(0x00));
+}
+
+#[test]
+fn save_states_restore_runs() {
+    let mut sys = flat_system();
+    sys.run_for_instructions(2).unwrap();
+    let state = sys.save_state();
+    sys.run_for_instructions(3).unwrap();
+    let done = sys.save_state();
+
+    let mut restored = System::with_memory(CpuVariant::Cmos65C02, FlatMemory::new());
+    restored.load_state(&state).unwrap();
+    restored.run_for_instructions(3).unwrap();
+    assert_eq!(restored.save_state(), done);
+}
+
+#[test]
+fn disassembly_and_monitor() {
+    let program = assembler::assemble(CpuVariant::Nmos6502, PROGRAM).unwrap();
+    let lines = disassembler::disassemble(CpuVariant::Nmos6502, &program);
+    assert_eq!(lines[..3], ["LDX #$00", "LDA #$05", "STA $0200"]);
+
+    let mut monitor = Monitor::new(flat_system());
+    let mut out = Vec::new();
+    assert!(monitor.execute("d 0400 0401", &mut out).unwrap());
+    assert!(String::from_utf8(out).unwrap().contains("LDX #$00"));
+    assert!(!monitor.execute("q", &mut Vec::new()).unwrap());
+}